@@ -5,7 +5,14 @@ extern crate clap;
 #[macro_use]
 extern crate anyhow;
 
-use lancelot::{analysis::dis, aspace::AddressSpace, loader::pe::PE, util, RVA, VA};
+use std::collections::BTreeMap;
+
+use lancelot::{
+    analysis::{cfg::CFG, dis, pe::Function},
+    aspace::AddressSpace,
+    loader::pe::PE,
+    util, RVA, VA,
+};
 
 fn handle_functions(pe: &PE) -> Result<()> {
     let functions = lancelot::analysis::pe::find_function_starts(pe)?;
@@ -18,6 +25,34 @@ fn handle_functions(pe: &PE) -> Result<()> {
     Ok(())
 }
 
+fn handle_pat(pe: &PE) -> Result<()> {
+    let functions = lancelot::analysis::pe::find_functions(pe)?;
+    let names = lancelot::analysis::pe::flirt::find_pe_known_names(pe, &functions)?;
+
+    let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+    for function in functions.iter() {
+        if let Function::Local(va) = function {
+            if !names.contains_key(va) {
+                continue;
+            }
+
+            match lancelot::analysis::cfg::build_cfg(&pe.module, *va) {
+                Ok(cfg) => {
+                    cfgs.insert(*va, cfg);
+                }
+                Err(e) => debug!("failed to build CFG: {:#x}: {}", va, e),
+            }
+        }
+    }
+    let cg = lancelot::analysis::call_graph::build_call_graph(&pe.module, &cfgs)?;
+
+    let sigs = lancelot::analysis::pe::flirt::generate_pe_signatures(pe, &cfgs, &cg, &names)?;
+    info!("generated {} signatures", sigs.len());
+    print!("{}", lancelot_flirt::pat::write(&sigs)?);
+
+    Ok(())
+}
+
 fn render_insn_buf(buf: &[u8], width: usize) -> String {
     let mut out = String::new();
     for (i, c) in hex::encode(buf).chars().enumerate() {
@@ -51,6 +86,9 @@ fn handle_disassemble(pe: &PE, va: VA) -> Result<()> {
     let cfg = lancelot::analysis::cfg::build_cfg(&pe.module, va)?;
     let decoder = dis::get_disassembler(&pe.module)?;
 
+    let scopes = lancelot::analysis::pe::exception_handlers::find_pe_exception_scopes(pe)?;
+    let exception_edges = lancelot::analysis::pe::exception_handlers::find_cfg_exception_edges(&cfg, &scopes);
+
     info!("found {} basic blocks", cfg.basic_blocks.len());
     for bb in cfg.basic_blocks.values() {
         // need to over-read the bb buffer, to account for the final instructions.
@@ -89,6 +127,11 @@ fn handle_disassemble(pe: &PE, va: VA) -> Result<()> {
                 break;
             }
         }
+        if let Some(handlers) = exception_edges.get(&bb.address) {
+            for handler in handlers.iter() {
+                println!("  exception handler: {}", handler);
+            }
+        }
         println!();
     }
 
@@ -118,7 +161,10 @@ fn _main() -> Result<()> {
         (@subcommand disassemble =>
             (about: "disassemble function")
             (@arg input: +required "path to file to analyze")
-            (@arg va: +required "VA of function")))
+            (@arg va: +required "VA of function"))
+        (@subcommand pat =>
+            (about: "generate FLIRT patterns for the named functions")
+            (@arg input: +required "path to file to analyze")))
     .get_matches();
 
     // --quiet overrides --verbose
@@ -175,6 +221,16 @@ fn _main() -> Result<()> {
         let pe = PE::from_bytes(&buf)?;
 
         handle_disassemble(&pe, va)
+    } else if let Some(matches) = matches.subcommand_matches("pat") {
+        debug!("mode: generate patterns");
+
+        let filename = matches.value_of("input").unwrap();
+        debug!("input: {}", filename);
+
+        let buf = util::read_file(filename)?;
+        let pe = PE::from_bytes(&buf)?;
+
+        handle_pat(&pe)
     } else {
         Err(anyhow!("SUBCOMMAND required"))
     }
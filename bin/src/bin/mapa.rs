@@ -10,12 +10,11 @@
 // TODO: overlay
 // TODO: stack strings
 // TODO: function names
-// TODO: flirt function names
 
 use std::collections::BTreeMap;
 
 use anyhow::Result;
-use log::{debug, error};
+use log::{debug, error, info, warn};
 #[macro_use]
 extern crate clap;
 #[macro_use]
@@ -23,10 +22,18 @@ extern crate anyhow;
 use ansi_term::Colour as Color;
 
 use lancelot::{
+    analysis::{
+        entropy,
+        pe::{
+            carve::{Format, Source},
+            flirt::Library,
+        },
+    },
     aspace::{AbsoluteAddressSpace, AddressSpace},
+    demangle,
     loader::pe::{
         imports::{get_import_directory, read_import_descriptors, read_thunks, IMAGE_THUNK_DATA},
-        rsrc::{NodeChild, NodeIdentifier, ResourceDataType, ResourceSectionData},
+        rsrc::ResourceSectionData,
         PE,
     },
     util, RVA, VA,
@@ -62,6 +69,9 @@ enum Structure {
     String(String),
     Function(String),
     Overlay,
+    /// a payload, like an executable or archive, embedded in the overlay or resources.
+    /// this may span the entire overlay or resource, replacing its range.
+    Artifact(Format, Source),
 }
 
 impl std::fmt::Display for Structure {
@@ -92,6 +102,7 @@ impl std::fmt::Display for Structure {
             Structure::Function(name) => write!(f, "function: {}", name),
             Structure::Resource(name) => write!(f, "resource: {}", name),
             Structure::Overlay => write!(f, "overlay"),
+            Structure::Artifact(format, source) => write!(f, "embedded {} in {}", format, source),
         }
     }
 }
@@ -228,22 +239,11 @@ fn fo_to_va_ranges(pe: &PE, src: Ranges) -> Ranges {
     dst
 }
 
-fn get_overlay_range(buf: &[u8], pe: &PE) -> Result<(FileOffset, FileOffset)> {
-    let start = pe
-        .module
-        .sections
-        .iter()
-        .map(|sec| sec.physical_range.end)
-        .max()
-        .unwrap();
-
-    let end = buf.len();
-
-    Ok((start as FileOffset, end as FileOffset))
-}
-
 fn insert_overlay_ranges(ranges: &mut Ranges, buf: &[u8], pe: &PE) -> Result<()> {
-    let (start, end) = get_overlay_range(buf, pe)?;
+    let (start, end) = match pe.get_overlay_range() {
+        None => return Ok(()),
+        Some(overlay) => (overlay.start, overlay.end),
+    };
     ranges.insert(start, end, Structure::Overlay)?;
 
     let buf = &buf[start as usize..end as usize];
@@ -427,10 +427,8 @@ fn insert_data_directory_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
             Box::new(|| *opt.data_directories.get_exception_table()),
             Structure::ExceptionTable,
         ));
-        directories.push((
-            Box::new(|| *opt.data_directories.get_certificate_table()),
-            Structure::CertificateTable,
-        ));
+        // the certificate table is found at a file offset, not an RVA,
+        // so its handled by insert_certificate_table_range
         directories.push((
             Box::new(|| *opt.data_directories.get_base_relocation_table()),
             Structure::BaseRelocationTable,
@@ -511,58 +509,39 @@ fn insert_imports_range(ranges: &mut Ranges, pe: &PE) -> Result<()> {
     Ok(())
 }
 
-fn insert_resource_ranges_inner(
-    ranges: &mut Ranges,
-    pe: &PE,
-    rsrc: &ResourceSectionData,
-    prefix: String,
-    node: NodeChild,
-) -> Result<()> {
-    match node {
-        NodeChild::Data(d) => {
-            // rsrc RVAs are file offsets?
-            ranges.insert(
-                d.rva as FileOffset,
-                (d.rva + d.size) as FileOffset,
-                Structure::Resource(prefix),
-            )?;
+fn insert_resource_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
+    if let Some(rsrc) = ResourceSectionData::from_pe(pe)? {
+        let base_address = pe.module.address_space.base_address;
+        for (path, d) in rsrc.data_entries()?.into_iter() {
+            let start = base_address + d.rva as RVA;
+            let end = start + d.size as RVA;
+            ranges.va_insert(pe, start, end, Structure::Resource(path))?;
         }
-        NodeChild::Node(child) => {
-            for (entry, child) in child.children(&rsrc)?.into_iter() {
-                // when the first element is recognized, render it like `RT_VERSION`,
-                // otherwise, like `0x0`.
-                let prefix = match entry.id(&rsrc)? {
-                    NodeIdentifier::ID(id) => {
-                        if prefix.is_empty() {
-                            match ResourceDataType::from_u32(id) {
-                                Some(dt) => format!("{:?}", dt),
-                                None => format!("{:#x}", id),
-                            }
-                        } else {
-                            format!("{}/{:#x}", prefix, id)
-                        }
-                    }
-                    NodeIdentifier::Name(s) => {
-                        if prefix.is_empty() {
-                            s.clone()
-                        } else {
-                            format!("{}/{}", prefix, s)
-                        }
-                    }
-                };
+    }
 
-                insert_resource_ranges_inner(ranges, pe, rsrc, prefix, child)?;
-            }
-        }
+    Ok(())
+}
+
+fn insert_certificate_table_range(ranges: &mut Ranges, pe: &PE) -> Result<()> {
+    if let Some(certificate_table) = pe.get_certificate_table_range() {
+        ranges.insert(
+            certificate_table.start,
+            certificate_table.end,
+            Structure::CertificateTable,
+        )?;
     }
 
     Ok(())
 }
 
-fn insert_resource_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
-    if let Some(rsrc) = ResourceSectionData::from_pe(pe)? {
-        let root = rsrc.root()?;
-        insert_resource_ranges_inner(ranges, pe, &rsrc, "".to_string(), NodeChild::Node(root))?;
+/// add a range for each payload embedded in the overlay or resources.
+fn insert_artifact_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
+    for artifact in lancelot::analysis::pe::carve::find_pe_artifacts(pe)?.into_iter() {
+        ranges.insert(
+            artifact.range.start,
+            artifact.range.end,
+            Structure::Artifact(artifact.format, artifact.source),
+        )?;
     }
 
     Ok(())
@@ -571,10 +550,17 @@ fn insert_resource_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
 /// add a range for each basic block. these won't be rendered, though.
 /// add a range for each function, from its start through all contiguous basic
 /// blocks. only the function start address will be rendered.
-fn insert_function_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
-    let functions = lancelot::analysis::pe::find_function_starts(pe)?;
+fn insert_function_ranges(ranges: &mut Ranges, pe: &PE, libraries: &[Library]) -> Result<()> {
+    let functions = lancelot::analysis::pe::find_functions(pe)?;
+    let exports = lancelot::analysis::pe::exports::find_pe_export_names(pe, demangle::Form::Short)?;
+    let virtual_methods = lancelot::analysis::pe::rtti::find_pe_virtual_methods(pe)?;
+
+    for labeled in lancelot::analysis::pe::flirt::label_pe_functions(pe, &functions, libraries)?.into_iter() {
+        let function = labeled.address;
+        let library_name = labeled
+            .library
+            .map(|f| demangle::demangle_or_raw(&f.name, demangle::Form::Short));
 
-    for &function in functions.iter() {
         if let Ok(cfg) = lancelot::analysis::cfg::build_cfg(&pe.module, function) {
             let mut end = function;
             for bb in cfg.basic_blocks.values() {
@@ -584,9 +570,16 @@ fn insert_function_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
                 end += bb.length;
             }
 
-            // TODO get function name
+            let name = match exports
+                .get(&function)
+                .or(library_name.as_ref())
+                .or_else(|| virtual_methods.get(&function))
+            {
+                Some(name) => name.clone(),
+                None => format!("sub_{:x}", function),
+            };
 
-            ranges.va_insert(pe, function, end, Structure::Function(format!("sub_{:x}", function)))?;
+            ranges.va_insert(pe, function, end, Structure::Function(name))?;
         } else {
             debug!("failed to compute build CFG at 0x{:#x}", function);
         }
@@ -656,7 +649,7 @@ fn insert_string_ranges(ranges: &mut Ranges, pe: &PE) -> Result<()> {
     Ok(())
 }
 
-fn compute_ranges(buf: &[u8], pe: &PE) -> Result<Ranges> {
+fn compute_ranges(buf: &[u8], pe: &PE, libraries: &[Library]) -> Result<Ranges> {
     let mut ranges = Default::default();
 
     insert_file_range(&mut ranges, buf, pe)?;
@@ -666,8 +659,10 @@ fn compute_ranges(buf: &[u8], pe: &PE) -> Result<Ranges> {
     insert_section_ranges(&mut ranges, pe)?;
     insert_data_directory_ranges(&mut ranges, pe)?;
     insert_imports_range(&mut ranges, pe)?;
+    insert_certificate_table_range(&mut ranges, pe)?;
     insert_resource_ranges(&mut ranges, pe)?;
-    insert_function_ranges(&mut ranges, pe)?;
+    insert_artifact_ranges(&mut ranges, pe)?;
+    insert_function_ranges(&mut ranges, pe, libraries)?;
     insert_string_ranges(&mut ranges, pe)?;
 
     Ok(ranges)
@@ -699,13 +694,45 @@ fn prefixln(depth: usize, s: &str) {
     println!("{}", prefix(depth, s));
 }
 
+/// render the entropy of the data in the range like ` (entropy: 6.21)`,
+/// flagging data that's probably compressed or encrypted like ` (entropy: 7.98, high)`.
+/// strings and functions are too short for a meaningful entropy,
+/// so they're rendered without one.
+fn format_entropy(address_space: &AbsoluteAddressSpace, range: &Range) -> String {
+    if let Structure::String(_) | Structure::Function(_) = range.structure {
+        return "".to_string();
+    }
+
+    let size = (range.end - range.start) as usize;
+    if size == 0 {
+        return "".to_string();
+    }
+
+    match address_space.read_bytes(range.start, size) {
+        Ok(buf) => {
+            let e = entropy::entropy(&buf);
+            if e > entropy::HIGH_ENTROPY {
+                format!(" (entropy: {:.2}, high)", e)
+            } else {
+                format!(" (entropy: {:.2})", e)
+            }
+        }
+        Err(_) => "".to_string(),
+    }
+}
+
 /// render the range block start separator like:
 ///
-///   ┌── 0x000290 IMAGE_SECTION_HEADER .rsrc ────
+///   ┌── 0x000290 IMAGE_SECTION_HEADER .rsrc (entropy: 3.12) ────
 ///
 /// pads the line with `WIDTH` characters,
-fn format_block_start(range: &Range) -> String {
-    let label = format!(" {:#08x} {} ", range.start, range.structure);
+fn format_block_start(address_space: &AbsoluteAddressSpace, range: &Range) -> String {
+    let label = format!(
+        " {:#08x} {}{} ",
+        range.start,
+        range.structure,
+        format_entropy(address_space, range)
+    );
     let prefix = "┌──";
 
     let mut chars: Vec<char> = Vec::with_capacity(WIDTH);
@@ -713,7 +740,7 @@ fn format_block_start(range: &Range) -> String {
     chars.extend(label.chars());
 
     let dash = MUTED.paint("─").to_string();
-    for _ in 0..WIDTH - (label.len() + prefix.len()) {
+    for _ in 0..WIDTH.saturating_sub(label.len() + prefix.len()) {
         chars.extend(dash.chars());
     }
 
@@ -732,7 +759,7 @@ fn format_block_end(range: &Range) -> String {
     chars.extend(label.chars());
 
     let dash = MUTED.paint("─").to_string();
-    for _ in 0..WIDTH - (label.len() + prefix.len()) {
+    for _ in 0..WIDTH.saturating_sub(label.len() + prefix.len()) {
         chars.extend(dash.chars());
     }
 
@@ -753,7 +780,7 @@ fn format_range_hex(address_space: &AbsoluteAddressSpace, range: &Range) -> Stri
     // usize], range.start);
     format!(
         "{}\n{}\n{}",
-        format_block_start(range),
+        format_block_start(address_space, range),
         prefix(1, hex.trim_end()),
         format_block_end(range)
     )
@@ -796,9 +823,17 @@ fn render_range<'a>(
             let has_children = !children.is_empty();
 
             if !has_children {
-                prefixln(depth, &format!(" {:#08x}: [{}]", range.start, range.structure))
+                prefixln(
+                    depth,
+                    &format!(
+                        " {:#08x}: [{}]{}",
+                        range.start,
+                        range.structure,
+                        format_entropy(address_space, range)
+                    ),
+                )
             } else {
-                prefixln(depth, &format_block_start(range));
+                prefixln(depth, &format_block_start(address_space, range));
 
                 // iterate over pairs of children.
                 // always render the first.
@@ -834,6 +869,36 @@ fn render(address_space: &AbsoluteAddressSpace, ranges: &Ranges) -> Result<()> {
     Ok(())
 }
 
+/// load the FLIRT signatures from the given .sig, .pat, or cache file,
+/// naming the library after the file.
+fn load_flirt_library(path: &str) -> Result<Library> {
+    let buf = util::read_file(path)?;
+
+    let sigs = if lancelot_flirt::cache::is_cache(&buf) {
+        lancelot_flirt::cache::load(buf)?.sigs
+    } else if path.ends_with(".pat") {
+        // skip any invalid lines rather than discard the whole library.
+        let (sigs, errors) = lancelot_flirt::pat::parse_lenient(&String::from_utf8(buf)?);
+        for e in errors.iter() {
+            warn!("{}: {}", path, e);
+        }
+        lancelot_flirt::FlirtSignatureSet::with_signatures(sigs)
+    } else {
+        let (sigs, error) = lancelot_flirt::sig::parse_lenient(&buf)?;
+        if let Some(e) = error {
+            warn!("{}: {}", path, e);
+        }
+        lancelot_flirt::FlirtSignatureSet::with_signatures(sigs)
+    };
+
+    let name = std::path::Path::new(path)
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string());
+
+    Ok(Library { name, sigs })
+}
+
 fn _main() -> Result<()> {
     better_panic::install();
 
@@ -843,6 +908,8 @@ fn _main() -> Result<()> {
         (@arg verbose: -v --verbose +multiple "log verbose messages")
         (@arg quiet: -q --quiet "disable informational messages")
         (@arg va: --va "output addresses as mapped into memory")
+        (@arg sig: -s --sig +takes_value +multiple number_of_values(1) "path to FLIRT .sig, .pat, or signature cache file used to name library functions")
+        (@arg sigdir: --sigdir +takes_value "path to directory of FLIRT .sig and .pat files from which to select libraries")
         (@arg input: +required "path to file to analyze"))
     .get_matches();
 
@@ -884,8 +951,24 @@ fn _main() -> Result<()> {
     let buf = util::read_file(filename)?;
     let pe = PE::from_bytes(&buf)?;
 
+    let mut libraries = vec![];
+    if let Some(paths) = matches.values_of("sig") {
+        for path in paths {
+            debug!("sig: {}", path);
+            libraries.push(load_flirt_library(path)?);
+        }
+    }
+    if let Some(path) = matches.value_of("sigdir") {
+        debug!("sigdir: {}", path);
+        let index = lancelot_flirt::library::LibraryIndex::from_directory(path)?;
+        for info in lancelot::analysis::pe::flirt::select_pe_libraries(&pe, &index)?.into_iter() {
+            info!("selected library: {} ({})", info.name, info.compiler);
+            libraries.push(Library::load(info)?);
+        }
+    }
+
     // returns a Ranges containing FileOffsets
-    let ranges = compute_ranges(&buf, &pe)?;
+    let ranges = compute_ranges(&buf, &pe, &libraries)?;
 
     if matches.is_present("va") {
         // user wants to display output as VAs
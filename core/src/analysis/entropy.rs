@@ -0,0 +1,139 @@
+//! Shannon entropy of data, in bits per byte.
+//!
+//! Compressed or encrypted data approaches 8.0,
+//! while code and text usually fall well below 7.0,
+//! so entropy is a quick way to spot packed or encrypted blobs.
+use std::ops::Range;
+
+/// bits per byte above which data is probably compressed or encrypted.
+pub const HIGH_ENTROPY: f64 = 7.0;
+
+/// the default size of the windows used to profile data.
+pub const WINDOW_SIZE: usize = 0x400;
+
+/// compute the Shannon entropy of the given data, in bits per byte.
+///
+/// ```
+/// use lancelot::analysis::entropy::entropy;
+/// assert_eq!(entropy(&[0x0; 0x100]), 0.0);
+/// assert_eq!(entropy(&(0..=255).collect::<Vec<u8>>()), 8.0);
+/// ```
+pub fn entropy(buf: &[u8]) -> f64 {
+    if buf.is_empty() {
+        return 0.0;
+    }
+
+    let mut counts = [0usize; 256];
+    for &b in buf.iter() {
+        counts[b as usize] += 1;
+    }
+
+    let size = buf.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / size;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// compute the entropy of overlapping windows across the given data.
+/// windows start every half window, so a region that straddles two windows
+/// is still covered by a third. the final window may be shorter.
+///
+/// returns the offset of each window and its entropy.
+///
+/// ```
+/// use lancelot::analysis::entropy::profile;
+/// let buf = [0u8; 0x300];
+/// assert_eq!(profile(&buf, 0x200), vec![(0x0, 0.0), (0x100, 0.0)]);
+/// ```
+pub fn profile(buf: &[u8], window: usize) -> Vec<(u64, f64)> {
+    let step = std::cmp::max(window / 2, 1);
+
+    let mut ret = vec![];
+    for offset in (0..buf.len()).step_by(step) {
+        let end = std::cmp::min(offset + window, buf.len());
+        ret.push((offset as u64, entropy(&buf[offset..end])));
+
+        if end == buf.len() {
+            break;
+        }
+    }
+
+    ret
+}
+
+/// find the regions of the given data whose windows have entropy above the threshold,
+/// merging overlapping and adjacent windows.
+///
+/// returns ranges of offsets into the data.
+pub fn find_high_entropy_regions(buf: &[u8], window: usize, threshold: f64) -> Vec<Range<u64>> {
+    let mut regions: Vec<Range<u64>> = vec![];
+
+    for (offset, entropy) in profile(buf, window).into_iter() {
+        if entropy <= threshold {
+            continue;
+        }
+
+        let end = std::cmp::min(offset + window as u64, buf.len() as u64);
+        match regions.last_mut() {
+            Some(last) if last.end >= offset => last.end = end,
+            _ => regions.push(offset..end),
+        }
+    }
+
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// a deterministic, incompressible-looking sequence of bytes.
+    fn noise(size: usize) -> Vec<u8> {
+        let mut state = 0x1234_5678u32;
+        (0..size)
+            .map(|_| {
+                // xorshift32
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_profile() {
+        assert_eq!(profile(&[], 0x400), vec![]);
+        assert_eq!(profile(&[0u8; 0x10], 0x400), vec![(0x0, 0.0)]);
+
+        let mut buf = vec![0u8; 0x1000];
+        buf[0x800..0xC00].copy_from_slice(&noise(0x400));
+        let p = profile(&buf, 0x400);
+        assert_eq!(p.len(), 7);
+        assert_eq!(p[0], (0x0, 0.0));
+        assert!(p[4].1 > HIGH_ENTROPY);
+        assert!(p[3].1 < p[4].1);
+    }
+
+    #[test]
+    fn test_high_entropy_regions() {
+        assert_eq!(find_high_entropy_regions(&[0u8; 0x1000], 0x400, HIGH_ENTROPY), vec![]);
+
+        let mut buf = vec![0u8; 0x3000];
+        buf[0x1000..0x2000].copy_from_slice(&noise(0x1000));
+        assert_eq!(
+            find_high_entropy_regions(&buf, 0x400, HIGH_ENTROPY),
+            vec![0x1000..0x2000]
+        );
+
+        assert_eq!(
+            find_high_entropy_regions(&noise(0x1000), 0x400, HIGH_ENTROPY),
+            vec![0x0..0x1000]
+        );
+    }
+}
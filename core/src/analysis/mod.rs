@@ -1,4 +1,5 @@
 pub mod call_graph;
 pub mod cfg;
 pub mod dis;
+pub mod entropy;
 pub mod pe;
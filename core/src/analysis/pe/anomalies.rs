@@ -0,0 +1,397 @@
+//! Heuristics that flag suspicious traits of a PE file,
+//! such as those left behind by packers, to help triage.
+//!
+//! These only inspect data the loader already provides,
+//! so they're cheap enough to run across many files.
+//! Each trait is common enough in benign files that none is conclusive alone.
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use regex::bytes::Regex;
+
+use crate::{
+    analysis::entropy::{entropy, HIGH_ENTROPY},
+    aspace::AddressSpace,
+    loader::pe::{self, PE},
+    module::Permissions,
+    util, VA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Packer {
+    UPX,
+    MPRESS,
+    ASPack,
+}
+
+impl std::fmt::Display for Packer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Packer::UPX => write!(f, "UPX"),
+            Packer::MPRESS => write!(f, "MPRESS"),
+            Packer::ASPack => write!(f, "ASPack"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// the loader worked around a malformation of the file.
+    Malformed(pe::Anomaly),
+    /// the section is both writable and executable.
+    WritableExecutableSection(String),
+    /// the entry point isn't within an executable section.
+    EntryPointOutsideCode(VA),
+    /// the section name is empty, duplicated, or contains unprintable characters.
+    SuspiciousSectionName(String),
+    /// the raw data of the section looks compressed or encrypted.
+    HighEntropySection { name: String, entropy: f64 },
+    /// so few functions are imported that the rest are probably resolved at runtime.
+    FewImports(usize),
+    /// the executable section is much larger in memory than on disk,
+    /// like the destination of unpacked code.
+    SizeMismatch {
+        name:         String,
+        raw_size:     u64,
+        virtual_size: u64,
+    },
+    /// data follows the sections in the file.
+    Overlay { offset: u64, size: u64 },
+    /// the checksum in the optional header doesn't match the file.
+    ChecksumMismatch { expected: u32, actual: u32 },
+    /// the section has a name used by the packer.
+    PackerSection { packer: Packer, name: String },
+    /// the code at the entry point matches the stub of the packer.
+    PackerEntryPoint(Packer),
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::Malformed(anomaly) => write!(f, "malformed: {}", anomaly),
+            Anomaly::WritableExecutableSection(name) => write!(f, "writable and executable section: {}", name),
+            Anomaly::EntryPointOutsideCode(va) => write!(f, "entry point outside code: {:#x}", va),
+            Anomaly::SuspiciousSectionName(name) => write!(f, "suspicious section name: {:?}", name),
+            Anomaly::HighEntropySection { name, entropy } => {
+                write!(f, "high entropy section: {}: {:.2}", name, entropy)
+            }
+            Anomaly::FewImports(count) => write!(f, "few imports: {}", count),
+            Anomaly::SizeMismatch {
+                name,
+                raw_size,
+                virtual_size,
+            } => write!(
+                f,
+                "section size mismatch: {}: raw: {:#x} virtual: {:#x}",
+                name, raw_size, virtual_size
+            ),
+            Anomaly::Overlay { offset, size } => write!(f, "overlay: {:#x} bytes at {:#x}", size, offset),
+            Anomaly::ChecksumMismatch { expected, actual } => {
+                write!(f, "checksum mismatch: expected: {:#x} actual: {:#x}", expected, actual)
+            }
+            Anomaly::PackerSection { packer, name } => write!(f, "{} section: {}", packer, name),
+            Anomaly::PackerEntryPoint(packer) => write!(f, "{} entry point", packer),
+        }
+    }
+}
+
+/// sections smaller than this don't have enough data for a meaningful entropy.
+const MIN_ENTROPY_SIZE: usize = 0x400;
+
+/// programs with fewer imports than this probably resolve the rest at runtime.
+const FEW_IMPORTS: usize = 10;
+
+/// compute the checksum of the PE file, as found in the optional header,
+/// given the file offset of the checksum field, which is excluded.
+pub fn compute_checksum(buf: &[u8], checksum_offset: usize) -> u32 {
+    let mut sum = 0u64;
+    for (i, word) in buf.chunks(2).enumerate() {
+        let offset = i * 2;
+        if offset == checksum_offset || offset == checksum_offset + 2 {
+            continue;
+        }
+
+        let word = match word {
+            [lo, hi] => u16::from_le_bytes([*lo, *hi]),
+            [lo] => *lo as u16,
+            _ => unreachable!(),
+        };
+        sum += word as u64;
+        sum = (sum & 0xFFFF) + (sum >> 16);
+    }
+    sum = (sum & 0xFFFF) + (sum >> 16);
+
+    (sum as u32).wrapping_add(buf.len() as u32)
+}
+
+const PACKER_SECTIONS: &[(&str, Packer)] = &[
+    ("UPX0", Packer::UPX),
+    ("UPX1", Packer::UPX),
+    ("UPX2", Packer::UPX),
+    ("UPX3", Packer::UPX),
+    (".UPX0", Packer::UPX),
+    (".UPX1", Packer::UPX),
+    (".MPRESS1", Packer::MPRESS),
+    (".MPRESS2", Packer::MPRESS),
+    (".aspack", Packer::ASPack),
+    (".adata", Packer::ASPack),
+];
+
+lazy_static! {
+    static ref PACKER_STUBS: Vec<(Packer, Regex)> = vec![
+        // PUSHAD; MOV ESI, ????; LEA EDI, [ESI+????]; PUSH EDI; OR EBP, -1
+        (Packer::UPX, Regex::new(r"(?s-u)^\x60\xBE.{4}\x8D\xBE.{4}\x57\x83\xCD\xFF").unwrap()),
+        // PUSH RBX; PUSH RSI; PUSH RDI; PUSH RBP; LEA RSI, [RIP+????]; LEA RDI, [RSI+????]; PUSH RDI
+        (Packer::UPX, Regex::new(r"(?s-u)^\x53\x56\x57\x55\x48\x8D\x35.{4}\x48\x8D\xBE.{4}\x57").unwrap()),
+        // PUSHAD; CALL $+5; POP EAX; ADD EAX, ????; MOV ESI, [EAX]; ADD ESI, EAX
+        (Packer::MPRESS, Regex::new(r"(?s-u)^\x60\xE8\x00\x00\x00\x00\x58\x05.{4}\x8B\x30\x03\xF0").unwrap()),
+        // PUSHAD; CALL $+8; JMP ????; POP EBP; INC EBP; PUSH EBP; RET; CALL $+6
+        (Packer::ASPack, Regex::new(r"(?s-u)^\x60\xE8\x03\x00\x00\x00\xE9\xEB\x04\x5D\x45\x55\xC3\xE8\x01").unwrap()),
+    ];
+}
+
+/// identify the packer whose stub begins with the given code.
+///
+/// ```
+/// use lancelot::analysis::pe::anomalies::*;
+/// // PUSHAD; MOV ESI, 0x415000; LEA EDI, [ESI-0x14000]; PUSH EDI; OR EBP, -1
+/// let buf = b"\x60\xBE\x00\x50\x41\x00\x8D\xBE\x00\xC0\xFE\xFF\x57\x83\xCD\xFF";
+/// assert_eq!(match_packer_stub(buf), Some(Packer::UPX));
+/// assert_eq!(match_packer_stub(b"\x55\x8B\xEC"), None);
+/// ```
+pub fn match_packer_stub(buf: &[u8]) -> Option<Packer> {
+    PACKER_STUBS
+        .iter()
+        .find(|(_, re)| re.is_match(buf))
+        .map(|&(packer, _)| packer)
+}
+
+fn is_suspicious_name(name: &str) -> bool {
+    name.is_empty() || !name.chars().all(|c| c.is_ascii_graphic())
+}
+
+fn find_section_anomalies(pe: &PE, anomalies: &mut Vec<Anomaly>) {
+    let mut names: BTreeMap<&str, usize> = Default::default();
+
+    // skip the header, which the loader maps like a section.
+    for section in pe.module.sections.iter().skip(1) {
+        *names.entry(&section.name).or_default() += 1;
+
+        if section.permissions.contains(Permissions::W | Permissions::X) {
+            anomalies.push(Anomaly::WritableExecutableSection(section.name.clone()));
+        }
+
+        if let Some(&(_, packer)) = PACKER_SECTIONS.iter().find(|(name, _)| *name == section.name) {
+            anomalies.push(Anomaly::PackerSection {
+                packer,
+                name: section.name.clone(),
+            });
+        }
+
+        let raw_size = section.physical_range.end - section.physical_range.start;
+        let virtual_size = section.virtual_range.end - section.virtual_range.start;
+        if section.permissions.intersects(Permissions::X) && virtual_size > util::align(raw_size, 0x1000) * 2 {
+            anomalies.push(Anomaly::SizeMismatch {
+                name: section.name.clone(),
+                raw_size,
+                virtual_size,
+            });
+        }
+
+        let buf = &pe.buf[section.physical_range.start as usize..section.physical_range.end as usize];
+        if buf.len() >= MIN_ENTROPY_SIZE {
+            let entropy = entropy(buf);
+            if entropy > HIGH_ENTROPY {
+                anomalies.push(Anomaly::HighEntropySection {
+                    name: section.name.clone(),
+                    entropy,
+                });
+            }
+        }
+    }
+
+    for (name, count) in names.into_iter() {
+        if count > 1 || is_suspicious_name(name) {
+            anomalies.push(Anomaly::SuspiciousSectionName(name.to_string()));
+        }
+    }
+}
+
+fn find_entry_point_anomalies(pe: &PE, anomalies: &mut Vec<Anomaly>) {
+    let entry_point = match pe.header.optional_header {
+        Some(opt) if opt.standard_fields.address_of_entry_point != 0 => {
+            pe.module.address_space.base_address + opt.standard_fields.address_of_entry_point as VA
+        }
+        _ => return,
+    };
+
+    let is_code = pe.module.sections.iter().any(|section| {
+        section.virtual_range.contains(&entry_point) && section.permissions.intersects(Permissions::X)
+    });
+    if !is_code {
+        anomalies.push(Anomaly::EntryPointOutsideCode(entry_point));
+    }
+
+    if let Ok(buf) = pe.module.address_space.read_bytes(entry_point, 0x20) {
+        if let Some(packer) = match_packer_stub(&buf) {
+            anomalies.push(Anomaly::PackerEntryPoint(packer));
+        }
+    }
+}
+
+fn find_import_anomalies(pe: &PE, anomalies: &mut Vec<Anomaly>) {
+    // resource-only DLLs don't have an import table at all.
+    // corrupt import tables are already reported by the loader.
+    if !matches!(crate::loader::pe::imports::get_import_directory(pe), Ok(Some(_))) {
+        return;
+    }
+
+    if let Ok(imports) = crate::analysis::pe::get_imports(pe) {
+        if imports.len() < FEW_IMPORTS {
+            anomalies.push(Anomaly::FewImports(imports.len()));
+        }
+    }
+}
+
+fn find_file_anomalies(pe: &PE, anomalies: &mut Vec<Anomaly>) {
+    if let Some(overlay) = pe.get_overlay_range() {
+        anomalies.push(Anomaly::Overlay {
+            offset: overlay.start,
+            size:   overlay.end - overlay.start,
+        });
+    }
+
+    if let Some(opt) = pe.header.optional_header {
+        // the checksum is optional for everything but drivers and boot-time DLLs.
+        let expected = opt.windows_fields.check_sum;
+        if expected != 0 {
+            // IMAGE_NT_HEADERS.Signature, IMAGE_FILE_HEADER, IMAGE_OPTIONAL_HEADER.CheckSum
+            let checksum_offset = pe.header.dos_header.pe_pointer as usize + 0x4 + 0x14 + 0x40;
+            let actual = compute_checksum(&pe.buf, checksum_offset);
+            if actual != expected {
+                anomalies.push(Anomaly::ChecksumMismatch { expected, actual });
+            }
+        }
+    }
+}
+
+/// find the suspicious traits of the given PE file.
+pub fn find_pe_anomalies(pe: &PE) -> Result<Vec<Anomaly>> {
+    let mut anomalies: Vec<Anomaly> = pe.anomalies.iter().cloned().map(Anomaly::Malformed).collect();
+
+    find_section_anomalies(pe, &mut anomalies);
+    find_entry_point_anomalies(pe, &mut anomalies);
+    find_import_anomalies(pe, &mut anomalies);
+    find_file_anomalies(pe, &mut anomalies);
+
+    Ok(anomalies)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsrc::*;
+    use anyhow::Result;
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(find_pe_anomalies(&pe)?, vec![]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let mut buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        // the certificate table follows the sections, but isn't an overlay.
+        assert_eq!(find_pe_anomalies(&pe)?, vec![]);
+
+        // data appended after signing is.
+        buf.extend(&[0x41; 0x10]);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(
+            find_pe_anomalies(&pe)?,
+            vec![
+                Anomaly::Overlay {
+                    offset: 0xC5698,
+                    size:   0x10,
+                },
+                Anomaly::ChecksumMismatch {
+                    expected: 0xC7DDB,
+                    actual:   0xC87F5,
+                },
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn checksum() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert!(!find_pe_anomalies(&pe)?
+            .iter()
+            .any(|anomaly| matches!(anomaly, Anomaly::ChecksumMismatch { .. })));
+
+        // patch a byte of .data.
+        buf[0xA5E00] ^= 0xFF;
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert!(find_pe_anomalies(&pe)?
+            .iter()
+            .any(|anomaly| matches!(anomaly, Anomaly::ChecksumMismatch { expected: 0xBFE7E, .. })));
+
+        Ok(())
+    }
+
+    #[test]
+    fn upx() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // rename .text to UPX1, and make it writable.
+        buf[0x1D0..0x1D8].copy_from_slice(b"UPX1\x00\x00\x00\x00");
+        buf[0x1F4..0x1F8].copy_from_slice(&0xE000_0020u32.to_le_bytes());
+        // overwrite the code at the entry point with the UPX stub.
+        buf[0x1081..0x1091].copy_from_slice(b"\x60\xBE\x00\x50\x41\x00\x8D\xBE\x00\xC0\xFE\xFF\x57\x83\xCD\xFF");
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(
+            find_pe_anomalies(&pe)?,
+            vec![
+                Anomaly::WritableExecutableSection("UPX1".to_string()),
+                Anomaly::PackerSection {
+                    packer: Packer::UPX,
+                    name:   "UPX1".to_string(),
+                },
+                Anomaly::PackerEntryPoint(Packer::UPX),
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn few_imports() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // end the import address table after the third import.
+        buf[0x600C..0x6010].copy_from_slice(&0x0u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_anomalies(&pe)?, vec![Anomaly::FewImports(3)]);
+
+        // imports are checked even without an entry point.
+        // IMAGE_OPTIONAL_HEADER.AddressOfEntryPoint
+        buf[0x100..0x104].copy_from_slice(&0x0u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_anomalies(&pe)?, vec![Anomaly::FewImports(3)]);
+
+        // resource-only DLLs don't have an import table.
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(find_pe_anomalies(&pe)?, vec![]);
+
+        Ok(())
+    }
+}
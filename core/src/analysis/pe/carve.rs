@@ -0,0 +1,517 @@
+//! Carve embedded payloads, like executables and archives,
+//! from the overlay and resources of a PE file.
+//!
+//! Installers and droppers carry their payloads in these places.
+//! Payloads are identified by their magic, and then validated
+//! and sized using their headers, where the format allows.
+use std::ops::Range;
+
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use regex::bytes::Regex;
+
+use crate::{
+    loader::pe::{rsrc::ResourceSectionData, PE},
+    RVA,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Format {
+    PE,
+    ZIP,
+    CAB,
+    SevenZip,
+    RAR,
+    /// OLE compound file, like legacy Office documents and MSI packages.
+    OLE,
+    PDF,
+}
+
+impl std::fmt::Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::PE => write!(f, "PE"),
+            Format::ZIP => write!(f, "ZIP"),
+            Format::CAB => write!(f, "CAB"),
+            Format::SevenZip => write!(f, "7-Zip"),
+            Format::RAR => write!(f, "RAR"),
+            Format::OLE => write!(f, "OLE"),
+            Format::PDF => write!(f, "PDF"),
+        }
+    }
+}
+
+/// where in the PE file the artifact was found.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Source {
+    /// the data after the sections, see [`PE::get_overlay_range`].
+    Overlay,
+    /// the data of the resource with the given path, like `RT_RCDATA/0x65/0x409`.
+    Resource(String),
+}
+
+impl std::fmt::Display for Source {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Source::Overlay => write!(f, "overlay"),
+            Source::Resource(path) => write!(f, "resource: {}", path),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Artifact {
+    pub format: Format,
+    pub source: Source,
+    /// the file offsets of the artifact.
+    pub range:  Range<u64>,
+}
+
+impl Artifact {
+    /// fetch the data of the artifact from the PE file in which it was found.
+    pub fn data<'a>(&self, pe: &'a PE) -> &'a [u8] {
+        &pe.buf[self.range.start as usize..self.range.end as usize]
+    }
+}
+
+/// the magic at the start of each format, used both to find and to identify payloads.
+const MAGICS: &[(&[u8], Format)] = &[
+    (b"MZ", Format::PE),
+    (b"PK\x03\x04", Format::ZIP),
+    (b"MSCF\x00\x00\x00\x00", Format::CAB),
+    (b"7z\xBC\xAF\x27\x1C", Format::SevenZip),
+    // RAR 1.5 and 5.0
+    (b"Rar!\x1A\x07\x00", Format::RAR),
+    (b"Rar!\x1A\x07\x01\x00", Format::RAR),
+    (b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1", Format::OLE),
+    (b"%PDF-1.", Format::PDF),
+    (b"%PDF-2.", Format::PDF),
+];
+
+lazy_static! {
+    static ref MAGIC_RE: Regex = {
+        let alternatives: Vec<String> = MAGICS
+            .iter()
+            .map(|(magic, _)| magic.iter().map(|b| format!("\\x{:02X}", b)).collect())
+            .collect();
+        Regex::new(&format!("(?-u){}", alternatives.join("|"))).unwrap()
+    };
+}
+
+/// the size of a PE file is the end of its furthest section,
+/// or of its certificate table, which follows the sections.
+/// without an optional header, there's nothing to go on,
+/// so like the loader, assume the PE spans the data.
+fn get_pe_size(buf: &[u8]) -> Option<u64> {
+    use goblin::pe::header;
+
+    let header = header::Header::parse(buf).ok()?;
+    if header.optional_header.is_none() {
+        return Some(buf.len() as u64);
+    }
+
+    let mut offset = header.dos_header.pe_pointer as usize
+        + header::SIZEOF_PE_MAGIC
+        + header::SIZEOF_COFF_HEADER
+        + header.coff_header.size_of_optional_header as usize;
+    let sections = header.coff_header.sections(buf, &mut offset).ok()?;
+
+    let mut size = offset as u64;
+    for section in sections.iter().filter(|section| section.size_of_raw_data > 0) {
+        size = std::cmp::max(
+            size,
+            section.pointer_to_raw_data as u64 + section.size_of_raw_data as u64,
+        );
+    }
+
+    if let Some(opt) = header.optional_header {
+        size = std::cmp::max(size, opt.windows_fields.size_of_headers as u64);
+
+        if let Some(certificate_table) = opt.data_directories.get_certificate_table() {
+            if certificate_table.virtual_address != 0 {
+                size = std::cmp::max(
+                    size,
+                    certificate_table.virtual_address as u64 + certificate_table.size as u64,
+                );
+            }
+        }
+    }
+
+    Some(size)
+}
+
+/// the size of a ZIP archive extends through its end of central directory record,
+/// including the trailing comment.
+///
+/// like ZIP readers, search backwards from the end of the data for the record,
+/// though only as far as the largest comment allows.
+/// the central directory must immediately precede the record,
+/// which rejects records of other archives that follow this one.
+fn get_zip_size(buf: &[u8]) -> Option<u64> {
+    const SIZEOF_EOCD: usize = 22;
+
+    if buf.len() < SIZEOF_EOCD {
+        return None;
+    }
+
+    // the record is followed by, at most, the largest comment.
+    let last = buf.len() - SIZEOF_EOCD;
+    let first = last.saturating_sub(u16::MAX as usize);
+    for offset in (first..=last).rev() {
+        let eocd = &buf[offset..offset + SIZEOF_EOCD];
+        if &eocd[..4] != b"PK\x05\x06" {
+            continue;
+        }
+
+        let disk = LittleEndian::read_u16(&eocd[4..6]);
+        let directory_disk = LittleEndian::read_u16(&eocd[6..8]);
+        let disk_entry_count = LittleEndian::read_u16(&eocd[8..10]);
+        let entry_count = LittleEndian::read_u16(&eocd[10..12]);
+        let directory_size = LittleEndian::read_u32(&eocd[12..16]) as u64;
+        let directory_offset = LittleEndian::read_u32(&eocd[16..20]) as u64;
+        let comment_size = LittleEndian::read_u16(&eocd[20..22]) as u64;
+
+        // split archives aren't carved.
+        if disk != 0 || directory_disk != 0 || disk_entry_count != entry_count {
+            continue;
+        }
+
+        if directory_offset + directory_size != offset as u64 {
+            continue;
+        }
+
+        if entry_count > 0 && !buf[directory_offset as usize..].starts_with(b"PK\x01\x02") {
+            continue;
+        }
+
+        let size = (offset + SIZEOF_EOCD) as u64 + comment_size;
+        if size > buf.len() as u64 {
+            continue;
+        }
+
+        return Some(size);
+    }
+
+    None
+}
+
+/// the size of a cabinet is found in its header, along with the format version 1.3.
+fn get_cab_size(buf: &[u8]) -> Option<u64> {
+    const SIZEOF_CFHEADER: usize = 0x24;
+
+    if buf.len() < SIZEOF_CFHEADER {
+        return None;
+    }
+
+    let size = LittleEndian::read_u32(&buf[0x8..0xC]);
+    let (minor, major) = (buf[0x18], buf[0x19]);
+    if (size as usize) < SIZEOF_CFHEADER || major != 1 || minor != 3 {
+        return None;
+    }
+
+    Some(size as u64)
+}
+
+/// the size of a 7-Zip archive is its header, and the data through the end of its trailing header.
+fn get_7z_size(buf: &[u8]) -> Option<u64> {
+    const SIZEOF_SIGNATURE_HEADER: u64 = 0x20;
+
+    if buf.len() < SIZEOF_SIGNATURE_HEADER as usize || buf[6] != 0 {
+        return None;
+    }
+
+    let next_header_offset = LittleEndian::read_u64(&buf[0xC..0x14]);
+    let next_header_size = LittleEndian::read_u64(&buf[0x14..0x1C]);
+
+    SIZEOF_SIGNATURE_HEADER
+        .checked_add(next_header_offset)?
+        .checked_add(next_header_size)
+}
+
+/// identify the payload at the start of the given data, and its size.
+/// when the format doesn't record its size, the payload extends to the end of the data.
+/// the size may exceed the data, when the payload is truncated.
+///
+/// ```
+/// use lancelot::analysis::pe::carve::*;
+/// let buf = b"%PDF-1.7\n...";
+/// assert_eq!(identify(buf), Some((Format::PDF, buf.len() as u64)));
+/// assert_eq!(identify(b"MZ...."), None);
+/// ```
+pub fn identify(buf: &[u8]) -> Option<(Format, u64)> {
+    let end = buf.len() as u64;
+
+    let format = MAGICS
+        .iter()
+        .find(|(magic, _)| buf.starts_with(magic))
+        .map(|&(_, format)| format)?;
+
+    let size = match format {
+        Format::PE => get_pe_size(buf)?,
+        Format::ZIP => get_zip_size(buf)?,
+        Format::CAB => get_cab_size(buf)?,
+        Format::SevenZip => get_7z_size(buf)?,
+        Format::RAR | Format::OLE | Format::PDF => end,
+    };
+
+    Some((format, size))
+}
+
+/// find the payloads embedded anywhere within the given data.
+/// payloads don't overlap: the data within a payload isn't searched further.
+///
+/// returns the format of each payload and its offsets into the data,
+/// truncated to the end of the data.
+pub fn carve(buf: &[u8]) -> Vec<(Format, Range<u64>)> {
+    let mut ret = vec![];
+
+    let mut offset = 0;
+    while let Some(m) = MAGIC_RE.find_at(buf, offset) {
+        let start = m.start();
+
+        match identify(&buf[start..]) {
+            Some((format, size)) => {
+                let end = std::cmp::min(start as u64 + size, buf.len() as u64);
+                ret.push((format, start as u64..end));
+                offset = end as usize;
+            }
+            None => offset = start + 1,
+        }
+    }
+
+    ret
+}
+
+/// find the payloads embedded in the overlay.
+pub fn find_overlay_artifacts(pe: &PE) -> Result<Vec<Artifact>> {
+    let overlay = match pe.get_overlay_range() {
+        None => return Ok(vec![]),
+        Some(overlay) => overlay,
+    };
+
+    Ok(
+        carve(&pe.buf[overlay.start as usize..overlay.end as usize])
+            .into_iter()
+            .map(|(format, range)| Artifact {
+                format,
+                source: Source::Overlay,
+                range: overlay.start + range.start..overlay.start + range.end,
+            })
+            .collect(),
+    )
+}
+
+/// find the file offsets of the given data, when it's entirely backed by the file.
+pub(crate) fn get_file_range(pe: &PE, rva: RVA, size: u64) -> Option<Range<u64>> {
+    let va = pe.module.address_space.base_address + rva;
+    let section = pe
+        .module
+        .sections
+        .iter()
+        .find(|section| section.virtual_range.contains(&va))?;
+
+    let start = section.physical_range.start + (va - section.virtual_range.start);
+    let end = start.checked_add(size)?;
+    if end > section.physical_range.end {
+        return None;
+    }
+
+    Some(start..end)
+}
+
+/// find the payloads embedded in the resources.
+/// resources whose data isn't entirely found in the file are skipped.
+pub fn find_resource_artifacts(pe: &PE) -> Result<Vec<Artifact>> {
+    let rsrc = match ResourceSectionData::from_pe(pe)? {
+        None => return Ok(vec![]),
+        Some(rsrc) => rsrc,
+    };
+
+    let mut ret = vec![];
+    for (path, d) in rsrc.data_entries()?.into_iter() {
+        let resource = match get_file_range(pe, d.rva as RVA, d.size as u64) {
+            None => continue,
+            Some(resource) => resource,
+        };
+
+        for (format, range) in carve(&pe.buf[resource.start as usize..resource.end as usize]).into_iter() {
+            ret.push(Artifact {
+                format,
+                source: Source::Resource(path.clone()),
+                range: resource.start + range.start..resource.start + range.end,
+            });
+        }
+    }
+
+    Ok(ret)
+}
+
+/// find the payloads embedded in the overlay and resources of the PE file.
+pub fn find_pe_artifacts(pe: &PE) -> Result<Vec<Artifact>> {
+    let mut artifacts = find_overlay_artifacts(pe)?;
+    artifacts.extend(find_resource_artifacts(pe)?);
+    Ok(artifacts)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsrc::*;
+    use anyhow::Result;
+
+    /// a ZIP archive with a single stored file.
+    fn zip(name: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut buf = b"PK\x03\x04".to_vec();
+        // version, flags, method, time, date, and crc32.
+        buf.extend(&[0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        buf.extend(&(data.len() as u32).to_le_bytes());
+        buf.extend(&(data.len() as u32).to_le_bytes());
+        buf.extend(&(name.len() as u16).to_le_bytes());
+        buf.extend(&0u16.to_le_bytes());
+        buf.extend(name);
+        buf.extend(data);
+
+        let directory_offset = buf.len() as u32;
+        buf.extend(b"PK\x01\x02");
+        // versions, flags, method, time, date, and crc32.
+        buf.extend(&[0x14, 0, 0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        buf.extend(&(data.len() as u32).to_le_bytes());
+        buf.extend(&(data.len() as u32).to_le_bytes());
+        buf.extend(&(name.len() as u16).to_le_bytes());
+        // extra and comment lengths, disk, attributes, and local header offset.
+        buf.extend(&[0u8; 16]);
+        buf.extend(name);
+        let directory_size = buf.len() as u32 - directory_offset;
+
+        buf.extend(b"PK\x05\x06");
+        buf.extend(&[0, 0, 0, 0, 1, 0, 1, 0]);
+        buf.extend(&directory_size.to_le_bytes());
+        buf.extend(&directory_offset.to_le_bytes());
+        buf.extend(&0u16.to_le_bytes());
+        buf
+    }
+
+    #[test]
+    fn identify_formats() {
+        let mut cab = vec![0u8; 0x40];
+        cab[..8].copy_from_slice(b"MSCF\x00\x00\x00\x00");
+        cab[0x8..0xC].copy_from_slice(&0x30u32.to_le_bytes());
+        cab[0x18] = 3;
+        cab[0x19] = 1;
+        assert_eq!(identify(&cab), Some((Format::CAB, 0x30)));
+
+        let mut buf = zip(b"a.txt", b"hello");
+        let size = buf.len() as u64;
+        buf.extend(b"trailing data");
+        assert_eq!(identify(&buf), Some((Format::ZIP, size)));
+        assert_eq!(identify(&buf[..20]), None);
+
+        let tiny = get_buf(Rsrc::TINY);
+        assert_eq!(identify(&tiny), Some((Format::PE, tiny.len() as u64)));
+
+        let nop = get_buf(Rsrc::NOP);
+        assert_eq!(identify(&nop), Some((Format::PE, nop.len() as u64)));
+
+        let k32 = get_buf(Rsrc::K32);
+        assert_eq!(identify(&k32), Some((Format::PE, k32.len() as u64)));
+    }
+
+    #[test]
+    fn zip_size() {
+        // the stored file is itself an archive, whose record isn't the outer one.
+        let inner = zip(b"a.txt", b"hello");
+        let outer = zip(b"a.zip", &inner);
+        assert_eq!(get_zip_size(&outer), Some(outer.len() as u64));
+
+        // consecutive archives are carved separately.
+        let first = zip(b"a.txt", b"hello");
+        let mut buf = first.clone();
+        buf.extend(zip(b"b.txt", b"world"));
+        assert_eq!(get_zip_size(&buf), Some(first.len() as u64));
+        assert_eq!(
+            carve(&buf),
+            vec![
+                (Format::ZIP, 0..first.len() as u64),
+                (Format::ZIP, first.len() as u64..buf.len() as u64),
+            ]
+        );
+
+        // the central directory doesn't precede the record.
+        let mut buf = zip(b"a.txt", b"hello");
+        let eocd = buf.len() - 22;
+        buf[eocd + 16] += 1;
+        assert_eq!(get_zip_size(&buf), None);
+
+        // the record is too far from the end of the data to be found.
+        let mut buf = zip(b"a.txt", b"hello");
+        buf.extend(vec![0u8; 0x10000]);
+        assert_eq!(get_zip_size(&buf), None);
+    }
+
+    #[test]
+    fn no_artifacts() -> Result<()> {
+        for &rsrc in [Rsrc::K32, Rsrc::TINY, Rsrc::NOP, Rsrc::MIMI].iter() {
+            let buf = get_buf(rsrc);
+            let pe = PE::from_bytes(&buf)?;
+            assert_eq!(find_pe_artifacts(&pe)?, vec![]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        let nop = buf.clone();
+        let tiny = get_buf(Rsrc::TINY);
+
+        // junk, followed by two executables, the last of which has no optional header.
+        buf.extend(b"MZ junk ");
+        buf.extend(&nop);
+        buf.extend(&tiny);
+        let pe = PE::from_bytes(&buf)?;
+
+        let artifacts = find_overlay_artifacts(&pe)?;
+        assert_eq!(
+            artifacts,
+            vec![
+                Artifact {
+                    format: Format::PE,
+                    source: Source::Overlay,
+                    range:  0x9008..0x12008,
+                },
+                Artifact {
+                    format: Format::PE,
+                    source: Source::Overlay,
+                    range:  0x12008..0x12114,
+                },
+            ]
+        );
+        assert_eq!(artifacts[0].data(&pe), &nop[..]);
+        assert_eq!(artifacts[1].data(&pe), &tiny[..]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn resource() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        // overwrite the start of the MUI resource with a small cabinet.
+        buf[0xABE58..0xABE60].copy_from_slice(b"MSCF\x00\x00\x00\x00");
+        buf[0xABE60..0xABE64].copy_from_slice(&0x30u32.to_le_bytes());
+        buf[0xABE70] = 3;
+        buf[0xABE71] = 1;
+        let pe = PE::from_bytes(&buf)?;
+
+        assert_eq!(
+            find_pe_artifacts(&pe)?,
+            vec![Artifact {
+                format: Format::CAB,
+                source: Source::Resource("MUI/0x1/0x409".to_string()),
+                range:  0xABE58..0xABE88,
+            }]
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,417 @@
+//! Find and load the PE files embedded within a PE file,
+//! like the payloads that droppers carry in their resources, overlay, or data.
+//!
+//! Both the file and the loaded module are scanned,
+//! since the data of a PE isn't necessarily laid out the same in both.
+//! Each child is a complete `PE`; use `find_nested_pes` to find the PEs within them, in turn.
+use std::ops::Range;
+
+use anyhow::Result;
+use log::debug;
+
+use crate::{
+    analysis::pe::carve::{self, Format},
+    aspace::AddressSpace,
+    loader::pe::{rsrc::ResourceSectionData, Anomaly, PE},
+    RVA, VA,
+};
+
+/// where the embedded PE starts within its container.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Location {
+    /// the offset into the file.
+    File(u64),
+    /// the address in the loaded module.
+    /// only PEs that aren't found at the corresponding file offset are reported here.
+    Memory(VA),
+}
+
+impl std::fmt::Display for Location {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Location::File(offset) => write!(f, "file offset {:#x}", offset),
+            Location::Memory(va) => write!(f, "address {:#x}", va),
+        }
+    }
+}
+
+pub struct EmbeddedPE {
+    pub location: Location,
+    /// the path of the resource that contains the PE, like `RT_RCDATA/0x65/0x409`.
+    pub resource: Option<String>,
+    pub pe:       PE,
+}
+
+struct Resource {
+    path:          String,
+    virtual_range: Range<VA>,
+    /// the file offsets, when the resource is entirely backed by the file.
+    file_range:    Option<Range<u64>>,
+}
+
+fn get_resources(pe: &PE) -> Result<Vec<Resource>> {
+    let rsrc = match ResourceSectionData::from_pe(pe)? {
+        None => return Ok(vec![]),
+        Some(rsrc) => rsrc,
+    };
+
+    let base_address = pe.module.address_space.base_address;
+    Ok(rsrc
+        .data_entries()?
+        .into_iter()
+        .map(|(path, d)| Resource {
+            path,
+            virtual_range: base_address + d.rva as RVA..base_address + d.rva as RVA + d.size as RVA,
+            file_range: carve::get_file_range(pe, d.rva as RVA, d.size as u64),
+        })
+        .collect())
+}
+
+/// PEs nested deeper than this within the container aren't searched for.
+const MAX_NESTING_DEPTH: usize = 3;
+
+/// the largest image that a child of the given PE may claim:
+/// the size of the container on disk and in memory, combined.
+/// data that only coincidentally looks like a PE often claims a much larger image.
+fn get_max_image_size(pe: &PE) -> u64 {
+    let base_address = pe.module.address_space.base_address;
+    let image_size = pe
+        .module
+        .sections
+        .iter()
+        .map(|section| section.virtual_range.end - base_address)
+        .max()
+        .unwrap_or_default();
+
+    pe.buf.len() as u64 + image_size
+}
+
+/// the loader recovers from these anomalies,
+/// but in a child they suggest that the data only coincidentally looks like a PE,
+/// or that the PE wasn't entirely found.
+fn is_fatal_anomaly(anomaly: &Anomaly) -> bool {
+    matches!(
+        anomaly,
+        Anomaly::TruncatedSection { .. } | Anomaly::OversizedSection { .. } | Anomaly::DistantSection { .. }
+    )
+}
+
+/// load the PE at the start of the given data, if it's valid,
+/// and its image is no larger than `max_image_size`.
+/// returns its size, which may be less than the data, and the PE.
+fn load_embedded_pe(buf: &[u8], max_image_size: u64) -> Option<(usize, PE)> {
+    let size = match carve::identify(buf)? {
+        (Format::PE, size) => std::cmp::min(size as usize, buf.len()),
+        _ => return None,
+    };
+
+    // check the claimed image size before the loader allocates for it.
+    let header = goblin::pe::header::Header::parse(&buf[..size]).ok()?;
+    if let Some(opt) = header.optional_header {
+        if opt.windows_fields.size_of_image as u64 > max_image_size {
+            debug!("embedded: PE image too large: {:#x}", opt.windows_fields.size_of_image);
+            return None;
+        }
+    }
+
+    match PE::from_bytes(&buf[..size]) {
+        Ok(pe) => {
+            if let Some(anomaly) = pe.anomalies.iter().find(|anomaly| is_fatal_anomaly(anomaly)) {
+                debug!("embedded: PE is malformed: {}", anomaly);
+                return None;
+            }
+            Some((size, pe))
+        }
+        Err(e) => {
+            debug!("embedded: failed to load PE: {}", e);
+            None
+        }
+    }
+}
+
+/// find the offsets of the `MZ` magic in the given data, starting at the given offset.
+fn find_mz(buf: &[u8], offset: usize) -> Option<usize> {
+    buf.get(offset..)?
+        .windows(2)
+        .position(|w| w == b"MZ")
+        .map(|i| offset + i)
+}
+
+fn find_file_pes(pe: &PE, resources: &[Resource]) -> Vec<(Range<u64>, EmbeddedPE)> {
+    let mut ret = vec![];
+    let max_image_size = get_max_image_size(pe);
+
+    // skip the container itself, at the start of the file.
+    let mut offset = 1;
+    while let Some(start) = find_mz(&pe.buf, offset) {
+        // when within a resource, the PE can't extend past the resource.
+        let resource = resources.iter().find(|resource| match &resource.file_range {
+            Some(file_range) => file_range.contains(&(start as u64)),
+            None => false,
+        });
+        let end = match resource.and_then(|resource| resource.file_range.as_ref()) {
+            Some(file_range) => file_range.end as usize,
+            None => pe.buf.len(),
+        };
+
+        match load_embedded_pe(&pe.buf[start..end], max_image_size) {
+            Some((size, child)) => {
+                debug!("embedded: found PE at file offset {:#x}", start);
+                ret.push((
+                    start as u64..(start + size) as u64,
+                    EmbeddedPE {
+                        location: Location::File(start as u64),
+                        resource: resource.map(|resource| resource.path.clone()),
+                        pe:       child,
+                    },
+                ));
+                // the data within the child is searched when the child is analyzed.
+                offset = start + size;
+            }
+            None => offset = start + 1,
+        }
+    }
+
+    ret
+}
+
+fn find_memory_pes(pe: &PE, resources: &[Resource], file_pes: &[Range<u64>]) -> Result<Vec<EmbeddedPE>> {
+    let mut ret = vec![];
+    let max_image_size = get_max_image_size(pe);
+
+    for section in pe.module.sections.iter() {
+        let buf = pe.module.address_space.read_bytes(
+            section.virtual_range.start,
+            (section.virtual_range.end - section.virtual_range.start) as usize,
+        )?;
+        let physical_size = section.physical_range.end - section.physical_range.start;
+
+        let mut offset = 0;
+        while let Some(start) = find_mz(&buf, offset) {
+            offset = start + 1;
+
+            let va = section.virtual_range.start + start as u64;
+            if va == pe.module.address_space.base_address {
+                // this is the container itself.
+                continue;
+            }
+
+            // skip the data that's mapped from a PE already found in the file.
+            if (start as u64) < physical_size {
+                let file_offset = section.physical_range.start + start as u64;
+                if file_pes.iter().any(|file_pe| file_pe.contains(&file_offset)) {
+                    continue;
+                }
+            }
+
+            let resource = resources
+                .iter()
+                .find(|resource| resource.virtual_range.contains(&va));
+            let end = match resource {
+                Some(resource) => std::cmp::min(
+                    buf.len(),
+                    (resource.virtual_range.end - section.virtual_range.start) as usize,
+                ),
+                None => buf.len(),
+            };
+
+            if let Some((size, child)) = load_embedded_pe(&buf[start..end], max_image_size) {
+                debug!("embedded: found PE at address {:#x}", va);
+                ret.push(EmbeddedPE {
+                    location: Location::Memory(va),
+                    resource: resource.map(|resource| resource.path.clone()),
+                    pe:       child,
+                });
+                offset = start + size;
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// find and load the PE files embedded within the given PE file,
+/// such as in its resources, overlay, or sections.
+///
+/// the PEs embedded within each child aren't reported here;
+/// see [`find_nested_pes`].
+pub fn find_embedded_pes(pe: &PE) -> Result<Vec<EmbeddedPE>> {
+    let resources = get_resources(pe)?;
+
+    let (file_pes, mut ret): (Vec<_>, Vec<_>) = find_file_pes(pe, &resources).into_iter().unzip();
+    ret.extend(find_memory_pes(pe, &resources, &file_pes)?);
+
+    Ok(ret)
+}
+
+/// an embedded PE, along with the PEs embedded within it.
+pub struct NestedPE {
+    pub embedded: EmbeddedPE,
+    pub children: Vec<NestedPE>,
+}
+
+/// search each of the given PEs for the PEs embedded within it, in turn.
+fn nest(embedded: Vec<EmbeddedPE>, depth: usize) -> Vec<NestedPE> {
+    embedded
+        .into_iter()
+        .map(|embedded| {
+            let children = if depth >= MAX_NESTING_DEPTH {
+                debug!("embedded: not searching {}: nested too deeply", embedded.location);
+                vec![]
+            } else {
+                match find_embedded_pes(&embedded.pe) {
+                    Ok(children) => nest(children, depth + 1),
+                    Err(e) => {
+                        debug!("embedded: failed to search {}: {}", embedded.location, e);
+                        vec![]
+                    }
+                }
+            };
+            NestedPE { embedded, children }
+        })
+        .collect()
+}
+
+/// find and load the PE files embedded within the given PE file,
+/// and those embedded within them, in turn, up to a few levels deep.
+/// the locations of nested PEs are relative to their immediate container.
+///
+/// the children found at each level don't overlap, and claim no more than their container,
+/// so the work at each level is bounded by the size of the given PE.
+pub fn find_nested_pes(pe: &PE) -> Result<Vec<NestedPE>> {
+    Ok(nest(find_embedded_pes(pe)?, 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsrc::*;
+    use anyhow::Result;
+
+    #[test]
+    fn no_embedded_pes() -> Result<()> {
+        for &rsrc in [Rsrc::K32, Rsrc::TINY, Rsrc::NOP, Rsrc::MIMI].iter() {
+            let buf = get_buf(rsrc);
+            let pe = PE::from_bytes(&buf)?;
+            assert!(find_embedded_pes(&pe)?.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay() -> Result<()> {
+        let nop = get_buf(Rsrc::NOP);
+        let mut buf = nop.clone();
+        buf.extend(&nop);
+        let pe = PE::from_bytes(&buf)?;
+
+        let children = find_embedded_pes(&pe)?;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].location, Location::File(0x9000));
+        assert_eq!(children[0].resource, None);
+        assert_eq!(children[0].pe.buf, nop);
+        assert_eq!(children[0].pe.module.address_space.base_address, 0x400000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn consecutive() -> Result<()> {
+        let nop = get_buf(Rsrc::NOP);
+        let mut buf = nop.clone();
+        buf.extend(&nop);
+        buf.extend(&nop);
+        let pe = PE::from_bytes(&buf)?;
+
+        // the size of each child is given by its headers,
+        // so the first doesn't swallow the second as its overlay.
+        let children = find_embedded_pes(&pe)?;
+        assert_eq!(
+            children.iter().map(|child| child.location).collect::<Vec<_>>(),
+            vec![Location::File(0x9000), Location::File(0x12000)]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn resource() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        let tiny = get_buf(Rsrc::TINY);
+        // overwrite the start of the RT_VERSION resource (0x3A4 bytes) with a small PE.
+        buf[0xABAB0..0xABAB0 + tiny.len()].copy_from_slice(&tiny);
+        let pe = PE::from_bytes(&buf)?;
+
+        let children = find_embedded_pes(&pe)?;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].location, Location::File(0xABAB0));
+        assert_eq!(children[0].resource, Some("RT_VERSION/0x1/0x409".to_string()));
+        // without an optional header, the PE extends through the end of the resource.
+        assert_eq!(children[0].pe.buf.len(), 0x3A4);
+
+        // the same PE is mapped into memory, where it's found when not already found in the file.
+        let resources = get_resources(&pe)?;
+        let file_pes: Vec<_> = find_file_pes(&pe, &resources)
+            .into_iter()
+            .map(|(range, _)| range)
+            .collect();
+        assert_eq!(file_pes, vec![0xABAB0..0xABE54]);
+        assert!(find_memory_pes(&pe, &resources, &file_pes)?.is_empty());
+
+        let children = find_memory_pes(&pe, &resources, &[])?;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].location, Location::Memory(0x1800B00B0));
+        assert_eq!(children[0].resource, Some("RT_VERSION/0x1/0x409".to_string()));
+        assert_eq!(children[0].pe.buf.len(), 0x3A4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn implausible() -> Result<()> {
+        let nop = get_buf(Rsrc::NOP);
+
+        // the child claims a larger image than the container could hold.
+        let mut buf = nop.clone();
+        buf.extend(&nop);
+        // IMAGE_OPTIONAL_HEADER.SizeOfImage
+        buf[0x9128..0x912C].copy_from_slice(&0x4000_0000u32.to_le_bytes());
+        let pe = PE::from_bytes(&buf)?;
+        assert!(find_embedded_pes(&pe)?.is_empty());
+
+        // the child is missing the end of its sections.
+        let mut buf = nop.clone();
+        buf.extend(&nop[..0x7000]);
+        let pe = PE::from_bytes(&buf)?;
+        assert!(find_embedded_pes(&pe)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nested() -> Result<()> {
+        let mut k32 = get_buf(Rsrc::K32);
+        let tiny = get_buf(Rsrc::TINY);
+        // overwrite the start of the RT_VERSION resource with a small PE.
+        k32[0xABAB0..0xABAB0 + tiny.len()].copy_from_slice(&tiny);
+        let mut buf = get_buf(Rsrc::NOP);
+        buf.extend(&k32);
+        let pe = PE::from_bytes(&buf)?;
+
+        let children = find_nested_pes(&pe)?;
+        assert_eq!(children.len(), 1);
+        assert_eq!(children[0].embedded.location, Location::File(0x9000));
+        assert_eq!(children[0].embedded.pe.buf, k32);
+        assert_eq!(children[0].children.len(), 1);
+        assert_eq!(children[0].children[0].embedded.location, Location::File(0xABAB0));
+        assert!(children[0].children[0].children.is_empty());
+
+        // the search stops at the deepest level.
+        let children = nest(find_embedded_pes(&pe)?, MAX_NESTING_DEPTH);
+        assert_eq!(children.len(), 1);
+        assert!(children[0].children.is_empty());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,157 @@
+//! Entropy of the sections and resources of a PE file,
+//! and the regions of the loaded module that look compressed or encrypted.
+//!
+//! See [`crate::analysis::entropy`] for entropy of arbitrary data,
+//! such as the raw file.
+use std::ops::Range;
+
+use anyhow::Result;
+
+use crate::{
+    analysis::entropy::{self, HIGH_ENTROPY, WINDOW_SIZE},
+    aspace::AddressSpace,
+    loader::pe::{rsrc::ResourceSectionData, PE},
+    RVA, VA,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionEntropy {
+    pub name:          String,
+    pub virtual_range: Range<VA>,
+    /// entropy of the section data in the file.
+    pub raw:           f64,
+    /// entropy of the section as mapped into memory,
+    /// including the zero padding beyond the file data.
+    pub mapped:        f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResourceEntropy {
+    /// like `RT_VERSION/0x1/0x409`.
+    pub path:    String,
+    pub address: VA,
+    pub size:    u64,
+    pub entropy: f64,
+}
+
+/// compute the entropy of each section, both in the file and as mapped into memory.
+pub fn find_pe_section_entropy(pe: &PE) -> Result<Vec<SectionEntropy>> {
+    let mut ret = vec![];
+
+    for section in pe.module.sections.iter() {
+        let raw = &pe.buf[section.physical_range.start as usize..section.physical_range.end as usize];
+        let mapped = pe.module.address_space.read_bytes(
+            section.virtual_range.start,
+            (section.virtual_range.end - section.virtual_range.start) as usize,
+        )?;
+
+        ret.push(SectionEntropy {
+            name:          section.name.clone(),
+            virtual_range: section.virtual_range.clone(),
+            raw:           entropy::entropy(raw),
+            mapped:        entropy::entropy(&mapped),
+        });
+    }
+
+    Ok(ret)
+}
+
+/// compute the entropy of each resource, as mapped into memory.
+/// resources whose data isn't mapped are skipped.
+pub fn find_pe_resource_entropy(pe: &PE) -> Result<Vec<ResourceEntropy>> {
+    let rsrc = match ResourceSectionData::from_pe(pe)? {
+        None => return Ok(vec![]),
+        Some(rsrc) => rsrc,
+    };
+
+    let mut ret = vec![];
+    for (path, d) in rsrc.data_entries()?.into_iter() {
+        if let Ok(buf) = d.data(pe) {
+            ret.push(ResourceEntropy {
+                path,
+                address: pe.module.address_space.base_address + d.rva as RVA,
+                size: d.size as u64,
+                entropy: entropy::entropy(&buf),
+            });
+        }
+    }
+
+    Ok(ret)
+}
+
+/// find the regions of the sections, as mapped into memory,
+/// that are probably compressed or encrypted.
+pub fn find_pe_high_entropy_regions(pe: &PE) -> Result<Vec<Range<VA>>> {
+    let mut ret = vec![];
+
+    for section in pe.module.sections.iter() {
+        let start = section.virtual_range.start;
+        let buf = pe
+            .module
+            .address_space
+            .read_bytes(start, (section.virtual_range.end - start) as usize)?;
+
+        ret.extend(
+            entropy::find_high_entropy_regions(&buf, WINDOW_SIZE, HIGH_ENTROPY)
+                .into_iter()
+                .map(|region| start + region.start..start + region.end),
+        );
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::rsrc::*;
+    use anyhow::Result;
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let sections = find_pe_section_entropy(&pe)?;
+        let text = sections.iter().find(|section| section.name == ".text").unwrap();
+        assert!(text.raw > 5.0 && text.raw < HIGH_ENTROPY);
+
+        let resources = find_pe_resource_entropy(&pe)?;
+        assert!(resources.iter().any(|resource| resource.path.starts_with("RT_VERSION")));
+        assert!(resources.iter().all(|resource| resource.entropy < HIGH_ENTROPY));
+
+        // small blobs of compressed data in .rdata.
+        assert_eq!(
+            find_pe_high_entropy_regions(&pe)?,
+            vec![0x18007EC00..0x18007F200, 0x180083400..0x180083A00]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        assert_eq!(find_pe_high_entropy_regions(&crate::loader::pe::PE::from_bytes(&buf)?)?, vec![]);
+
+        // fill .data with incompressible data.
+        let mut state = 0x1234_5678u32;
+        for b in buf[0x8000..0x9000].iter_mut() {
+            // xorshift32
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            *b = state as u8;
+        }
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(find_pe_high_entropy_regions(&pe)?, vec![0x408000..0x409000]);
+        let data = find_pe_section_entropy(&pe)?
+            .into_iter()
+            .find(|section| section.name == ".data")
+            .unwrap();
+        assert!(data.raw > HIGH_ENTROPY);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,961 @@
+//! Parse the compiler-generated exception handling metadata to find
+//! exception filters, termination handlers, catch funclets, and unwind
+//! actions.
+//!
+//! These routines are only ever invoked by the exception dispatcher,
+//! so there are no direct calls to them, and they're easy to miss.
+//!
+//! We support the following schemes:
+//!
+//!   - x64 `__C_specific_handler` (and `__GSHandlerCheck_SEH`):
+//!     the handler data that follows the UNWIND_INFO is a scope table:
+//!
+//!     ```text
+//!     u32 count
+//!     struct {
+//!       u32 begin;    // RVA of protected region start
+//!       u32 end;      // RVA of protected region end
+//!       u32 handler;  // RVA of filter/finally handler, or 1 (EXCEPTION_EXECUTE_HANDLER)
+//!       u32 target;   // RVA of __except block, or 0 for __finally
+//!     } records[count];
+//!     ```
+//!
+//!   - x32 `_except_handler3`/`_except_handler4`:
+//!     the scope table is pushed in the function prologue, either inline
+//!     (`push -1; push scopetable; push handler; mov eax, fs:[0]`)
+//!     or via `__SEH_prolog4` (`push size; push scopetable; call __SEH_prolog4`).
+//!     The table is an array of (enclosing level, filter, handler) records.
+//!     `_except_handler4` prefixes this with four u32 GS/EH cookie fields.
+//!
+//!   - MSVC C++ `__CxxFrameHandler3` (both x32 and x64):
+//!     a `FuncInfo` structure references an unwind map (cleanup actions)
+//!     and try block map, which references the `HandlerType` catch funclets.
+//!     On x64, the RVA of the `FuncInfo` is the handler data that follows
+//!     the UNWIND_INFO. On x32, its referenced by the `__ehhandler$` stub
+//!     (`mov eax, FuncInfo; jmp __CxxFrameHandler3`).
+//!
+//! We don't rely on the names of the handler routines (which may be
+//! statically linked and anonymous), but validate the structures instead.
+//! Bail on any structure that doesn't make sense.
+//!
+//! references:
+//!   - https://docs.microsoft.com/en-us/cpp/build/exception-handling-x64
+//!   - http://www.openrce.org/articles/full_view/21
+//!   - https://www.hexblog.com/wp-content/uploads/2012/06/Recon-2012-Skochinsky-Compiler-Internals.pdf
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use anyhow::Result;
+use log::debug;
+use regex::bytes::Regex;
+
+use crate::{
+    analysis::{
+        cfg::CFG,
+        pe::runtime_functions::{read_pe_runtime_functions, UnwindInfoData},
+    },
+    arch::Arch,
+    aspace::AddressSpace,
+    loader::pe::PE,
+    module::Permissions,
+    VA,
+};
+
+/// A routine or block of code invoked during exception dispatch.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Ord, PartialOrd)]
+pub enum Handler {
+    /// `__except` filter expression, a funclet invoked to decide
+    /// whether the `__except` block handles the exception.
+    Filter(VA),
+    /// the body of an `__except` block.
+    /// this is a continuation within the parent function, not a funclet.
+    Except(VA),
+    /// `__finally` termination handler funclet.
+    Finally(VA),
+    /// C++ `catch` block funclet.
+    Catch(VA),
+    /// C++ unwind action funclet, such as a destructor call for a local.
+    Cleanup(VA),
+}
+
+impl Handler {
+    pub fn va(&self) -> VA {
+        match *self {
+            Handler::Filter(va) => va,
+            Handler::Except(va) => va,
+            Handler::Finally(va) => va,
+            Handler::Catch(va) => va,
+            Handler::Cleanup(va) => va,
+        }
+    }
+
+    /// is the handler invoked like a function (versus jumped to)?
+    pub fn is_funclet(&self) -> bool {
+        !matches!(self, Handler::Except(_))
+    }
+}
+
+impl std::fmt::Display for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Handler::Filter(va) => write!(f, "filter: {:#x}", va),
+            Handler::Except(va) => write!(f, "except: {:#x}", va),
+            Handler::Finally(va) => write!(f, "finally: {:#x}", va),
+            Handler::Catch(va) => write!(f, "catch: {:#x}", va),
+            Handler::Cleanup(va) => write!(f, "cleanup: {:#x}", va),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum ExceptionScheme {
+    /// x64 `__C_specific_handler`
+    CSpecificHandler,
+    /// x32 `_except_handler3`
+    ExceptHandler3,
+    /// x32 `_except_handler4`
+    ExceptHandler4,
+    /// x32/x64 `__CxxFrameHandler3`
+    CxxFrameHandler3,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExceptionScope {
+    pub scheme:    ExceptionScheme,
+    /// address of the structure from which the scope was parsed,
+    /// such as a scope table or `FuncInfo`.
+    pub metadata:  VA,
+    /// the address ranges protected by this scope.
+    /// x32 SEH and C++ EH track the active scope with a state variable at
+    /// runtime, so these are recovered heuristically from the writes to the
+    /// variable, and may be incomplete or empty.
+    pub protected: Vec<std::ops::Range<VA>>,
+    pub handlers:  Vec<Handler>,
+}
+
+/// upper bound on the number of entries we'll read from any table.
+/// tables larger than this are assumed to be garbage.
+const MAX_ENTRY_COUNT: u32 = 0x400;
+
+/// upper bound on the size of a x32 function scanned for EH state variable writes.
+const MAX_X32_FUNCTION_SIZE: VA = 0x4000;
+
+/// `EXCEPTION_EXECUTE_HANDLER` in place of a filter funclet.
+const EXCEPTION_EXECUTE_HANDLER: u32 = 1;
+
+/// marks the outermost entry of an `_except_handler3` scope table.
+const TRYLEVEL_NONE: u32 = 0xFFFF_FFFF;
+/// marks the outermost entry of an `_except_handler4` scope table.
+const TRYLEVEL_INVALID: u32 = 0xFFFF_FFFE;
+
+const EH_MAGIC_NUMBER1: u32 = 0x1993_0520;
+const EH_MAGIC_NUMBER2: u32 = 0x1993_0521;
+const EH_MAGIC_NUMBER3: u32 = 0x1993_0522;
+
+fn is_executable(pe: &PE, va: VA) -> bool {
+    pe.module.probe_va(va, Permissions::X)
+}
+
+/// C++ EH metadata references code and data using absolute VAs on x32,
+/// and image-relative RVAs on x64.
+/// both are 32 bits wide.
+fn read_eh_pointer(pe: &PE, va: VA) -> Result<Option<VA>> {
+    let v = pe.module.address_space.read_u32(va)? as VA;
+    if v == 0 {
+        return Ok(None);
+    }
+
+    match pe.module.arch {
+        Arch::X32 => Ok(Some(v)),
+        Arch::X64 => Ok(Some(pe.module.address_space.base_address + v)),
+    }
+}
+
+/// parse a x64 `__C_specific_handler` scope table.
+fn read_c_specific_scope_table(pe: &PE, va: VA) -> Result<Option<Vec<ExceptionScope>>> {
+    let base_address = pe.module.address_space.base_address;
+
+    let count = pe.module.address_space.read_u32(va)?;
+    if count == 0 || count > MAX_ENTRY_COUNT {
+        return Ok(None);
+    }
+
+    let mut scopes = vec![];
+    for i in 0..count as VA {
+        let record = va + 4 + i * 0x10;
+        let begin = base_address + pe.module.address_space.read_u32(record)? as VA;
+        let end = base_address + pe.module.address_space.read_u32(record + 0x4)? as VA;
+        let handler = pe.module.address_space.read_u32(record + 0x8)?;
+        let target = pe.module.address_space.read_u32(record + 0xC)?;
+
+        if begin >= end || !is_executable(pe, begin) || !is_executable(pe, end - 1) {
+            return Ok(None);
+        }
+
+        let mut handlers = vec![];
+        if target == 0 {
+            // __try/__finally
+            let handler = base_address + handler as VA;
+            if !is_executable(pe, handler) {
+                return Ok(None);
+            }
+            handlers.push(Handler::Finally(handler));
+        } else {
+            // __try/__except
+            if handler != EXCEPTION_EXECUTE_HANDLER {
+                let handler = base_address + handler as VA;
+                if !is_executable(pe, handler) {
+                    return Ok(None);
+                }
+                handlers.push(Handler::Filter(handler));
+            }
+
+            let target = base_address + target as VA;
+            if !is_executable(pe, target) {
+                return Ok(None);
+            }
+            handlers.push(Handler::Except(target));
+        }
+
+        scopes.push(ExceptionScope {
+            scheme: ExceptionScheme::CSpecificHandler,
+            metadata: va,
+            protected: std::iter::once(begin..end).collect(),
+            handlers,
+        });
+    }
+
+    Ok(Some(scopes))
+}
+
+/// parse a x32 `_except_handler3` or `_except_handler4` scope table.
+///
+/// the scope table isn't prefixed with a count,
+/// so read records until one doesn't make sense.
+///
+/// `prologues` are the code ranges that start with a reference to the scope table,
+/// from which the state variable writes are collected (see `read_x32_state_map`).
+/// the scope of each record protects the code in its state, or in any nested state.
+fn read_except_handler_scope_table(
+    pe: &PE,
+    va: VA,
+    prologues: &[std::ops::Range<VA>],
+) -> Result<Option<Vec<ExceptionScope>>> {
+    let (scheme, records, top_level) = if pe.module.address_space.read_u32(va)? == TRYLEVEL_NONE {
+        (ExceptionScheme::ExceptHandler3, va, TRYLEVEL_NONE)
+    } else if pe.module.address_space.read_u32(va + 0x10)? == TRYLEVEL_INVALID {
+        // skip the GS cookie offset, GS cookie XOR offset, EH cookie offset, EH cookie XOR offset.
+        (ExceptionScheme::ExceptHandler4, va + 0x10, TRYLEVEL_INVALID)
+    } else {
+        return Ok(None);
+    };
+
+    let mut records_handlers: Vec<Vec<Handler>> = vec![];
+    let mut enclosing_levels: Vec<u32> = vec![];
+    for i in 0..MAX_ENTRY_COUNT {
+        let record = records + i as VA * 0xC;
+
+        let enclosing_level = match pe.module.address_space.read_u32(record) {
+            Ok(v) => v,
+            Err(_) => break,
+        };
+        if enclosing_level != top_level && enclosing_level >= i {
+            break;
+        }
+
+        let filter = match pe.module.address_space.read_u32(record + 0x4) {
+            Ok(v) => v as VA,
+            Err(_) => break,
+        };
+        let handler = match pe.module.address_space.read_u32(record + 0x8) {
+            Ok(v) => v as VA,
+            Err(_) => break,
+        };
+
+        if filter != 0 && !is_executable(pe, filter) {
+            break;
+        }
+        if !is_executable(pe, handler) {
+            break;
+        }
+
+        if filter == 0 {
+            // __try/__finally
+            records_handlers.push(vec![Handler::Finally(handler)]);
+        } else {
+            // __try/__except
+            records_handlers.push(vec![Handler::Filter(filter), Handler::Except(handler)]);
+        }
+        enclosing_levels.push(enclosing_level);
+    }
+
+    if records_handlers.is_empty() {
+        return Ok(None);
+    }
+
+    let state_map: Vec<(std::ops::Range<VA>, i32)> = prologues
+        .iter()
+        .flat_map(|prologue| read_x32_state_map(pe, prologue, records_handlers.len() as i32))
+        .collect();
+
+    // is the given try level nested within (or the same as) the given record?
+    let is_within = |mut level: u32, record: u32| {
+        // enclosing levels always decrease, so this terminates.
+        while (level as usize) < enclosing_levels.len() {
+            if level == record {
+                return true;
+            }
+            level = enclosing_levels[level as usize];
+        }
+        false
+    };
+
+    Ok(Some(
+        records_handlers
+            .into_iter()
+            .enumerate()
+            .map(|(i, handlers)| ExceptionScope {
+                scheme,
+                metadata: va,
+                protected: state_map
+                    .iter()
+                    .filter(|(_, state)| *state >= 0 && is_within(*state as u32, i as u32))
+                    .map(|(range, _)| range.clone())
+                    .collect(),
+                handlers,
+            })
+            .collect(),
+    ))
+}
+
+/// find the code ranges in which the x32 EH state variable at `[ebp-4]` holds each state,
+/// by scanning the code following the given prologue for writes to the variable.
+/// the state holds from each write through the next one.
+/// a register stored into the variable is assumed to be zero, since the compiler
+/// only reuses a register for the state when it holds the first try level.
+///
+/// the scan is linear, so it stops at the end of the function, which is found heuristically:
+/// at the end of the given range (the next known prologue), at the next `push ebp; mov ebp, esp`,
+/// or at the first write of a state that's not valid
+/// (less than `state_count` or one of the terminal states), which is probably a local
+/// variable of another function.
+fn read_x32_state_map(pe: &PE, prologue: &std::ops::Range<VA>, state_count: i32) -> Vec<(std::ops::Range<VA>, i32)> {
+    let (prologue, limit) = (prologue.start, prologue.end);
+    let section = match pe
+        .module
+        .sections
+        .iter()
+        .find(|section| section.virtual_range.contains(&prologue))
+    {
+        Some(section) => section,
+        None => return vec![],
+    };
+    let end = std::cmp::min(
+        std::cmp::min(section.virtual_range.end, limit),
+        prologue + MAX_X32_FUNCTION_SIZE,
+    );
+    let buf = match pe.module.address_space.read_bytes(prologue, (end - prologue) as usize) {
+        Ok(buf) => buf,
+        Err(_) => return vec![],
+    };
+
+    // skip past the prologue itself, which may be preceded by `push ebp; mov ebp, esp`.
+    let buf_end = match X32_FUNCTION_START.find_at(&buf, 1) {
+        Some(m) => m.start(),
+        None => buf.len(),
+    };
+    let buf = &buf[..buf_end];
+
+    // (start of the write instruction, end of the write instruction, state)
+    let mut writes: Vec<(VA, VA, i32)> = vec![];
+    for capture in X32_STATE_WRITES.captures_iter(buf) {
+        let m = capture.get(0).unwrap();
+        let state = if let Some(v) = capture.name("dword") {
+            read_u32_le(v.as_bytes()) as u32 as i32
+        } else if let Some(v) = capture.name("byte") {
+            v.as_bytes()[0] as i32
+        } else if capture.name("none").is_some() {
+            -1
+        } else {
+            0
+        };
+
+        if state >= state_count || state < TRYLEVEL_INVALID as i32 {
+            break;
+        }
+
+        writes.push((prologue + m.start() as VA, prologue + m.end() as VA, state));
+    }
+
+    let mut ret = vec![];
+    for (i, &(_, start, state)) in writes.iter().enumerate() {
+        let end = match writes.get(i + 1) {
+            Some(&(next, _, _)) => next,
+            None => prologue + buf.len() as VA,
+        };
+
+        if state >= 0 && start < end {
+            ret.push((start..end, state));
+        }
+    }
+
+    ret
+}
+
+/// compute the address ranges assigned to each EH state using the x64
+/// IP-to-state map.
+/// the final entry extends to the end of the function.
+fn read_ip_to_state_map(pe: &PE, va: VA, count: u32, function_end: VA) -> Result<Vec<(std::ops::Range<VA>, i32)>> {
+    let base_address = pe.module.address_space.base_address;
+
+    let mut entries = vec![];
+    for i in 0..count as VA {
+        let ip = base_address + pe.module.address_space.read_u32(va + i * 8)? as VA;
+        let state = pe.module.address_space.read_u32(va + i * 8 + 4)? as i32;
+        entries.push((ip, state));
+    }
+    entries.sort_unstable();
+
+    let mut ret = vec![];
+    for (i, &(ip, state)) in entries.iter().enumerate() {
+        let end = match entries.get(i + 1) {
+            Some(&(next, _)) => next,
+            None => function_end,
+        };
+
+        if ip < end {
+            ret.push((ip..end, state));
+        }
+    }
+
+    Ok(ret)
+}
+
+/// parse a MSVC C++ `FuncInfo` structure, along with its unwind map and try
+/// block map.
+///
+/// ```text
+/// x32                         x64
+/// 0x00 u32 magicNumber        0x00 u32 magicNumber
+/// 0x04 i32 maxState           0x04 i32 maxState
+/// 0x08 VA  pUnwindMap         0x08 RVA dispUnwindMap
+/// 0x0C u32 nTryBlocks         0x0C u32 nTryBlocks
+/// 0x10 VA  pTryBlockMap       0x10 RVA dispTryBlockMap
+/// 0x14 u32 nIPMapEntries      0x14 u32 nIPMapEntries
+/// 0x18 VA  pIPtoStateMap      0x18 RVA dispIPtoStateMap
+/// ```
+///
+/// `function_end` is used to bound the final IP-to-state map entry (x64 only).
+/// x32 doesn't have an IP-to-state map, so the states are found by scanning the code
+/// in the given `prologues` that reference the `FuncInfo` (see `read_x32_state_map`).
+fn read_func_info(
+    pe: &PE,
+    va: VA,
+    function_end: Option<VA>,
+    prologues: &[std::ops::Range<VA>],
+) -> Result<Option<Vec<ExceptionScope>>> {
+    let magic = pe.module.address_space.read_u32(va)? & 0x1FFF_FFFF;
+    if magic != EH_MAGIC_NUMBER1 && magic != EH_MAGIC_NUMBER2 && magic != EH_MAGIC_NUMBER3 {
+        return Ok(None);
+    }
+
+    let max_state = pe.module.address_space.read_u32(va + 0x4)?;
+    let unwind_map = read_eh_pointer(pe, va + 0x8)?;
+    let try_block_count = pe.module.address_space.read_u32(va + 0xC)?;
+    let try_block_map = read_eh_pointer(pe, va + 0x10)?;
+    let ip_map_count = pe.module.address_space.read_u32(va + 0x14)?;
+    let ip_map = read_eh_pointer(pe, va + 0x18)?;
+
+    if max_state > MAX_ENTRY_COUNT || try_block_count > MAX_ENTRY_COUNT || ip_map_count > MAX_ENTRY_COUNT {
+        return Ok(None);
+    }
+    debug!(
+        "exception handlers: FuncInfo: {:#x} states: {} try blocks: {}",
+        va, max_state, try_block_count
+    );
+
+    let ip_to_state = match (ip_map, function_end) {
+        (Some(ip_map), Some(function_end)) => read_ip_to_state_map(pe, ip_map, ip_map_count, function_end)?,
+        _ => prologues
+            .iter()
+            .flat_map(|prologue| read_x32_state_map(pe, prologue, max_state as i32))
+            .collect(),
+    };
+
+    let mut scopes = vec![];
+
+    // UnwindMapEntry:
+    //   i32     toState
+    //   VA/RVA  action
+    if let Some(unwind_map) = unwind_map {
+        let mut handlers = vec![];
+        for i in 0..max_state as VA {
+            if let Some(action) = read_eh_pointer(pe, unwind_map + i * 8 + 4)? {
+                if !is_executable(pe, action) {
+                    return Ok(None);
+                }
+                handlers.push(Handler::Cleanup(action));
+            }
+        }
+
+        if !handlers.is_empty() {
+            // unwinding from any state may invoke the cleanup actions.
+            let protected = ip_to_state
+                .iter()
+                .filter(|(_, state)| *state >= 0)
+                .map(|(range, _)| range.clone())
+                .collect();
+
+            scopes.push(ExceptionScope {
+                scheme: ExceptionScheme::CxxFrameHandler3,
+                metadata: va,
+                protected,
+                handlers,
+            });
+        }
+    }
+
+    // TryBlockMapEntry:
+    //   i32     tryLow
+    //   i32     tryHigh
+    //   i32     catchHigh
+    //   i32     nCatches
+    //   VA/RVA  pHandlerArray
+    //
+    // HandlerType:
+    //   u32     adjectives
+    //   VA/RVA  pType
+    //   i32     dispCatchObj
+    //   VA/RVA  addressOfHandler
+    //   u32     dispFrame        (x64 only)
+    let sizeof_handler_type: VA = match pe.module.arch {
+        Arch::X32 => 0x10,
+        Arch::X64 => 0x14,
+    };
+
+    if let Some(try_block_map) = try_block_map {
+        for i in 0..try_block_count as VA {
+            let entry = try_block_map + i * 0x14;
+            let try_low = pe.module.address_space.read_u32(entry)? as i32;
+            let try_high = pe.module.address_space.read_u32(entry + 0x4)? as i32;
+            let catch_count = pe.module.address_space.read_u32(entry + 0xC)?;
+            let handler_array = match read_eh_pointer(pe, entry + 0x10)? {
+                Some(handler_array) => handler_array,
+                None => continue,
+            };
+
+            if catch_count > MAX_ENTRY_COUNT {
+                return Ok(None);
+            }
+
+            let mut handlers = vec![];
+            for j in 0..catch_count as VA {
+                if let Some(handler) = read_eh_pointer(pe, handler_array + j * sizeof_handler_type + 0xC)? {
+                    if !is_executable(pe, handler) {
+                        return Ok(None);
+                    }
+                    handlers.push(Handler::Catch(handler));
+                }
+            }
+
+            let protected = ip_to_state
+                .iter()
+                .filter(|(_, state)| *state >= try_low && *state <= try_high)
+                .map(|(range, _)| range.clone())
+                .collect();
+
+            scopes.push(ExceptionScope {
+                scheme: ExceptionScheme::CxxFrameHandler3,
+                metadata: va,
+                protected,
+                handlers,
+            });
+        }
+    }
+
+    Ok(Some(scopes))
+}
+
+fn find_x64_exception_scopes(pe: &PE) -> Result<Vec<ExceptionScope>> {
+    let mut ret = vec![];
+    let mut seen: HashSet<VA> = Default::default();
+
+    for (runtime_function, unwind_info) in read_pe_runtime_functions(pe)?.into_iter() {
+        let data = match unwind_info.data {
+            UnwindInfoData::ExceptionHandler { data, .. } => data,
+            _ => continue,
+        };
+
+        if !seen.insert(data) {
+            continue;
+        }
+
+        // C++ EH: the handler data is the RVA of the FuncInfo.
+        if let Ok(Some(func_info)) = read_eh_pointer(pe, data) {
+            if let Ok(Some(scopes)) = read_func_info(pe, func_info, Some(runtime_function.function_end), &[]) {
+                debug!(
+                    "exception handlers: {:#x}: C++ FuncInfo {:#x}",
+                    runtime_function.function_start, func_info
+                );
+                ret.extend(scopes);
+                continue;
+            }
+        }
+
+        // SEH: the handler data is the scope table.
+        if let Ok(Some(scopes)) = read_c_specific_scope_table(pe, data) {
+            debug!(
+                "exception handlers: {:#x}: scope table {:#x}",
+                runtime_function.function_start, data
+            );
+            ret.extend(scopes);
+            continue;
+        }
+    }
+
+    Ok(ret)
+}
+
+lazy_static! {
+    static ref X32_SCOPE_TABLE_REFERENCES: Regex = Regex::new(
+        r"(?x)
+          (?s-u)                  # disable unicode mode, so we can match raw bytes, including \n
+          (?:
+              # _except_handler3/4 inline prologue
+              \x6A [\xFE\xFF]     # push -1 / push -2
+              \x68 (?P<table>....)  # push scopetable
+              \x68 ....           # push _except_handler
+              \x64 \xA1 \x00\x00\x00\x00  # mov eax, fs:[0]
+          |
+              # __SEH_prolog/__SEH_prolog4
+              (?: \x68 .... | \x6A . )  # push size
+              \x68 (?P<prolog_table>....)  # push scopetable
+              \xE8                # call __SEH_prolog4
+          )
+        "
+    )
+    .unwrap();
+    static ref X32_FUNC_INFO_REFERENCES: Regex = Regex::new(
+        r"(?x)
+          (?s-u)                  # disable unicode mode, so we can match raw bytes, including \n
+          # __ehhandler$ stub
+          \xB8 (?P<func_info>....)  # mov eax, FuncInfo
+          \xE9                    # jmp __CxxFrameHandler3
+        "
+    )
+    .unwrap();
+    static ref X32_EH_HANDLER_REFERENCES: Regex = Regex::new(
+        r"(?x)
+          (?s-u)                  # disable unicode mode, so we can match raw bytes, including \n
+          (?:
+              # inline prologue
+              \x6A \xFF           # push -1
+              \x68 (?P<handler>....)  # push __ehhandler$
+              \x64 \xA1 \x00\x00\x00\x00  # mov eax, fs:[0]
+          |
+              # __EH_prolog/__EH_prolog3
+              \xB8 (?P<prolog_handler>....)  # mov eax, __ehhandler$
+              \xE8                # call __EH_prolog3
+          )
+        "
+    )
+    .unwrap();
+    static ref X32_STATE_WRITES: Regex = Regex::new(
+        r"(?x)
+          (?s-u)                  # disable unicode mode, so we can match raw bytes, including \n
+            \xC7 \x45 \xFC (?P<dword>....)  # mov dword [ebp-4], imm32
+          | \xC6 \x45 \xFC (?P<byte>.)     # mov byte [ebp-4], imm8
+          | (?P<none> \x83 \x4D \xFC \xFF)  # or dword [ebp-4], -1
+          | \x83 \x65 \xFC \x00            # and dword [ebp-4], 0
+          | \x89 [\x45\x4D\x55\x5D\x75\x7D] \xFC  # mov dword [ebp-4], r32
+        "
+    )
+    .unwrap();
+    static ref X32_FUNCTION_START: Regex = Regex::new(
+        r"(?x)
+          (?s-u)                  # disable unicode mode, so we can match raw bytes, including \n
+          \x55 \x8B \xEC          # push ebp; mov ebp, esp
+        "
+    )
+    .unwrap();
+}
+
+fn read_u32_le(buf: &[u8]) -> VA {
+    (buf[0] as VA) | (buf[1] as VA) << 8 | (buf[2] as VA) << 16 | (buf[3] as VA) << 24
+}
+
+fn find_x32_exception_scopes(pe: &PE) -> Result<Vec<ExceptionScope>> {
+    // map from scope table to the prologues that reference it.
+    let mut scope_tables: BTreeMap<VA, Vec<VA>> = Default::default();
+    // map from `__ehhandler$` stub to the `FuncInfo` that it references.
+    let mut eh_handlers: BTreeMap<VA, VA> = Default::default();
+    // map from `FuncInfo` to the prologues that reference its `__ehhandler$` stub.
+    let mut func_infos: BTreeMap<VA, Vec<VA>> = Default::default();
+
+    let mut sections = vec![];
+    for section in pe.executable_sections() {
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = pe.module.address_space.read_bytes(vstart, vsize)?;
+
+        for capture in X32_SCOPE_TABLE_REFERENCES.captures_iter(&sec_buf) {
+            if let Some(m) = capture.name("table").or_else(|| capture.name("prolog_table")) {
+                let prologue = vstart + capture.get(0).unwrap().start() as VA;
+                scope_tables
+                    .entry(read_u32_le(m.as_bytes()))
+                    .or_default()
+                    .push(prologue);
+            }
+        }
+
+        for capture in X32_FUNC_INFO_REFERENCES.captures_iter(&sec_buf) {
+            if let Some(m) = capture.name("func_info") {
+                let func_info = read_u32_le(m.as_bytes());
+                eh_handlers.insert(vstart + capture.get(0).unwrap().start() as VA, func_info);
+                func_infos.entry(func_info).or_default();
+            }
+        }
+
+        sections.push((vstart, sec_buf));
+    }
+
+    for (vstart, sec_buf) in sections.iter() {
+        for capture in X32_EH_HANDLER_REFERENCES.captures_iter(sec_buf) {
+            if let Some(m) = capture.name("handler").or_else(|| capture.name("prolog_handler")) {
+                if let Some(func_info) = eh_handlers.get(&read_u32_le(m.as_bytes())) {
+                    let prologue = vstart + capture.get(0).unwrap().start() as VA;
+                    func_infos.entry(*func_info).or_default().push(prologue);
+                }
+            }
+        }
+    }
+
+    // bound the code following each prologue by the next prologue,
+    // since functions using `__SEH_prolog` don't start with `push ebp; mov ebp, esp`.
+    let all_prologues: BTreeSet<VA> = scope_tables
+        .values()
+        .chain(func_infos.values())
+        .flatten()
+        .cloned()
+        .collect();
+    let to_ranges = |prologues: Vec<VA>| -> Vec<std::ops::Range<VA>> {
+        prologues
+            .into_iter()
+            .map(|prologue| {
+                let next = all_prologues
+                    .range(prologue + 1..)
+                    .next()
+                    .cloned()
+                    .unwrap_or(prologue + MAX_X32_FUNCTION_SIZE);
+                prologue..next
+            })
+            .collect()
+    };
+
+    let mut ret = vec![];
+    for (va, prologues) in scope_tables.into_iter() {
+        if !pe.module.probe_va(va, Permissions::R) {
+            continue;
+        }
+
+        if let Ok(Some(scopes)) = read_except_handler_scope_table(pe, va, &to_ranges(prologues)) {
+            debug!("exception handlers: scope table {:#x}", va);
+            ret.extend(scopes);
+        }
+    }
+
+    for (va, prologues) in func_infos.into_iter() {
+        if !pe.module.probe_va(va, Permissions::R) {
+            continue;
+        }
+
+        if let Ok(Some(scopes)) = read_func_info(pe, va, None, &to_ranges(prologues)) {
+            debug!("exception handlers: C++ FuncInfo {:#x}", va);
+            ret.extend(scopes);
+        }
+    }
+
+    Ok(ret)
+}
+
+/// find the exception handling scopes described by the compiler-generated
+/// metadata in the given PE.
+pub fn find_pe_exception_scopes(pe: &PE) -> Result<Vec<ExceptionScope>> {
+    match pe.module.arch {
+        Arch::X32 => find_x32_exception_scopes(pe),
+        Arch::X64 => find_x64_exception_scopes(pe),
+    }
+}
+
+/// find the filter, termination handler, catch, and cleanup funclets
+/// referenced by exception handling metadata.
+pub fn find_pe_exception_handlers(pe: &PE) -> Result<Vec<VA>> {
+    let mut ret: Vec<VA> = find_pe_exception_scopes(pe)?
+        .iter()
+        .flat_map(|scope| scope.handlers.iter())
+        .filter(|handler| handler.is_funclet())
+        .map(|handler| handler.va())
+        .collect();
+
+    ret.sort_unstable();
+    ret.dedup();
+
+    Ok(ret)
+}
+
+/// annotate the given CFG with its exceptional control flow.
+/// returns a map from the address of each protected basic block to the
+/// handlers that may receive control when it raises an exception.
+pub fn find_cfg_exception_edges(cfg: &CFG, scopes: &[ExceptionScope]) -> BTreeMap<VA, Vec<Handler>> {
+    let mut ret: BTreeMap<VA, Vec<Handler>> = Default::default();
+
+    for bb in cfg.basic_blocks.values() {
+        for scope in scopes.iter() {
+            if scope.protected.iter().any(|range| range.contains(&bb.address)) {
+                ret.entry(bb.address)
+                    .or_default()
+                    .extend(scope.handlers.iter().cloned());
+            }
+        }
+    }
+
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::{
+            cfg::{BasicBlock, CFG},
+            pe::exception_handlers::*,
+        },
+        rsrc::*,
+    };
+    use anyhow::Result;
+
+    fn find_scope(scopes: &[ExceptionScope], metadata: VA) -> &ExceptionScope {
+        scopes.iter().find(|scope| scope.metadata == metadata).unwrap()
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let scopes = find_pe_exception_scopes(&pe)?;
+        let scope = find_scope(&scopes, 0x180089260);
+        assert_eq!(ExceptionScheme::CSpecificHandler, scope.scheme);
+        assert_eq!(vec![0x1800076E8..0x18000790B], scope.protected);
+        assert_eq!(
+            vec![Handler::Filter(0x1800222E6), Handler::Except(0x18000790B)],
+            scope.handlers
+        );
+
+        let scope = find_scope(&scopes, 0x180089FAC);
+        assert_eq!(vec![0x18000F6F8..0x18000F7AF], scope.protected);
+        assert_eq!(vec![Handler::Finally(0x18002233A)], scope.handlers);
+
+        let fns = find_pe_exception_handlers(&pe)?;
+        assert_eq!(148, fns.len());
+        assert!(fns.contains(&0x1800222E6));
+        assert!(fns.contains(&0x18002233A));
+        // the body of an `__except` block isn't a funclet.
+        assert!(!fns.contains(&0x18000790B));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tiny() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let fns = find_pe_exception_handlers(&pe)?;
+        assert_eq!(0, fns.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let scopes = find_pe_exception_scopes(&pe)?;
+        let scope = find_scope(&scopes, 0x406160);
+        assert_eq!(ExceptionScheme::ExceptHandler3, scope.scheme);
+        // from `mov [ebp-4], edi` through `or [ebp-4], -1`.
+        assert_eq!(vec![0x40117A..0x401239], scope.protected);
+        assert_eq!(
+            vec![Handler::Filter(0x40120E), Handler::Except(0x401222)],
+            scope.handlers
+        );
+
+        let fns = find_pe_exception_handlers(&pe)?;
+        assert_eq!(10, fns.len());
+        assert!(fns.contains(&0x40120E));
+        assert!(!fns.contains(&0x401222));
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let scopes = find_pe_exception_scopes(&pe)?;
+        let scope = find_scope(&scopes, 0x4B2350);
+        assert_eq!(ExceptionScheme::ExceptHandler3, scope.scheme);
+        assert_eq!(vec![0x4674DC..0x46751A], scope.protected);
+        assert_eq!(
+            vec![Handler::Filter(0x467503), Handler::Except(0x467517)],
+            scope.handlers
+        );
+
+        let scope = find_scope(&scopes, 0x4B21B0);
+        assert_eq!(ExceptionScheme::ExceptHandler4, scope.scheme);
+        assert_eq!(vec![0x4703B4..0x4703E1], scope.protected);
+        assert_eq!(
+            vec![Handler::Filter(0x4703EA), Handler::Except(0x4703FE)],
+            scope.handlers
+        );
+
+        let fns = find_pe_exception_handlers(&pe)?;
+        assert_eq!(31, fns.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn cfg_edges() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let scopes = find_pe_exception_scopes(&pe)?;
+
+        // the function that references the scope table at 0x406160,
+        // split around the `__try` block at 0x40117A..0x401239.
+        let mut cfg = CFG {
+            basic_blocks: Default::default(),
+        };
+        for &(address, length) in [(0x401078, 0x102), (0x40117A, 0x8C), (0x401239, 0x10)].iter() {
+            cfg.basic_blocks.insert(
+                address,
+                BasicBlock {
+                    address,
+                    length,
+                    predecessors: Default::default(),
+                    successors: Default::default(),
+                },
+            );
+        }
+
+        let edges = find_cfg_exception_edges(&cfg, &scopes);
+        assert_eq!(1, edges.len());
+        assert_eq!(
+            &vec![Handler::Filter(0x40120E), Handler::Except(0x401222)],
+            edges.get(&0x40117A).unwrap()
+        );
+
+        Ok(())
+    }
+}
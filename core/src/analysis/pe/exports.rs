@@ -2,9 +2,11 @@
 //! executable sections.
 //!
 //! PEs may export data, which we'll assume isn't in an executable section.
+use std::collections::BTreeMap;
+
 use anyhow::Result;
 
-use crate::{loader::pe::PE, module::Permissions, VA};
+use crate::{demangle, loader::pe::PE, module::Permissions, VA};
 
 pub fn find_pe_exports(pe: &PE) -> Result<Vec<VA>> {
     let base_address = match pe.header.optional_header {
@@ -30,6 +32,32 @@ pub fn find_pe_exports(pe: &PE) -> Result<Vec<VA>> {
     Ok(exports)
 }
 
+/// find the names of the exported functions, demangled into the given form.
+/// when there are multiple names for an address, the first is used.
+pub fn find_pe_export_names(pe: &PE, form: demangle::Form) -> Result<BTreeMap<VA, String>> {
+    let base_address = pe.module.address_space.base_address;
+
+    let mut names: BTreeMap<VA, String> = Default::default();
+    for exp in pe.pe()?.exports.iter() {
+        if exp.reexport.is_some() {
+            continue;
+        }
+
+        let va = base_address + exp.rva as u64;
+        if !pe.module.probe_va(va, Permissions::X) {
+            continue;
+        }
+
+        if let Some(name) = exp.name {
+            names
+                .entry(va)
+                .or_insert_with(|| demangle::demangle_or_raw(name, form));
+        }
+    }
+
+    Ok(names)
+}
+
 #[cfg(test)]
 mod tests {
     use crate::rsrc::*;
@@ -46,6 +74,18 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn k32_names() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let names = crate::analysis::pe::exports::find_pe_export_names(&pe, crate::demangle::Form::Short)?;
+        assert_eq!(1406, names.len());
+        assert!(names.values().any(|name| name == "CreateFileA"));
+
+        Ok(())
+    }
+
     #[test]
     fn tiny() -> Result<()> {
         let buf = get_buf(Rsrc::TINY);
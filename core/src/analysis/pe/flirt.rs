@@ -0,0 +1,870 @@
+//! Recognize statically-linked library functions using FLIRT signatures.
+//!
+//! For each discovered function, we match the bytes at its start against the
+//! signatures from one or more libraries (such as `libcmt_15_msvc_x86.sig`).
+//! Many library routines share the same leading bytes and CRC16,
+//! so when there are multiple candidates, we also verify the names that each
+//! candidate references (like `^0041 ___security_cookie`) against the names
+//! we already know: imports, thunks, exports, and other library functions
+//! (see `FlirtSignatureSet::match_with_resolver`).
+//!
+//! Since verifying a reference may depend upon another library function
+//! having been recognized, we repeat the matching until no new functions
+//! are recognized.
+//!
+//! We can also generate signatures for the named functions in an analyzed PE
+//! (see `generate_pe_signatures`), so that functions identified in one build
+//! of a program can be recognized in the next.
+//!
+//! references:
+//!   - https://hex-rays.com/products/ida/tech/flirt/in_depth/
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use goblin::pe::characteristic::IMAGE_FILE_DLL;
+use lancelot_flirt::{
+    library::{Compiler, LibraryIndex, LibraryInfo, Target},
+    sig::{self, AppTypes, FileTypes, OsTypes},
+    FlirtMatch, FlirtSignature, FlirtSignatureSet, Overlap,
+};
+use log::debug;
+
+use crate::{
+    analysis::{
+        call_graph::CallGraph,
+        cfg::{va_add_signed, CFG},
+        dis,
+        pe::{entrypoints::find_pe_entrypoint, Function, ImportedSymbol},
+    },
+    arch::Arch,
+    aspace::AddressSpace,
+    loader::pe::{reloc, rich, PE},
+    module::Permissions,
+    util, VA,
+};
+
+/// FLIRT signatures can describe functions up to 0x8000 bytes long.
+const MAX_FUNCTION_SIZE: u64 = 0x8000;
+
+/// a set of signatures that describe the functions from a single library,
+/// such as the MSVC C runtime.
+pub struct Library {
+    /// like `libcmt_15_msvc_x86`.
+    pub name: String,
+    pub sigs: FlirtSignatureSet,
+}
+
+impl Library {
+    /// load the signatures described by the given index entry.
+    pub fn load(info: &LibraryInfo) -> Result<Library> {
+        Ok(Library {
+            name: info.name.clone(),
+            sigs: info.load()?,
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LibraryFunction {
+    pub address:      VA,
+    /// the name from the matching signature, which may be mangled.
+    pub name:         String,
+    /// the name of the library that contains the matching signature.
+    pub library:      String,
+    /// the public names defined by the function, including its name,
+    /// and any alternate entry points.
+    pub public_names: Vec<(VA, String)>,
+    /// the local names defined by the function, like labels.
+    pub local_names:  Vec<(VA, String)>,
+    /// the names referenced by the function, like globals and other functions,
+    /// at the addresses that are referenced.
+    pub references:   Vec<(VA, String)>,
+}
+
+/// a discovered local function, labeled with the library function that it matches, if any.
+#[derive(Debug, Clone)]
+pub struct LabeledFunction {
+    pub address: VA,
+    pub library: Option<LibraryFunction>,
+}
+
+impl LabeledFunction {
+    /// is the function statically linked from a library?
+    pub fn is_library(&self) -> bool {
+        self.library.is_some()
+    }
+}
+
+/// compute the addresses that may be referenced by the relocated field at the
+/// given address: either a relative displacement (like `CALL rel32`) or an
+/// absolute address (like `CALL [IAT]` on x32).
+fn get_reference_targets(pe: &PE, field: VA) -> Vec<VA> {
+    let v = match pe.module.address_space.read_u32(field) {
+        Ok(v) => v,
+        Err(_) => return vec![],
+    };
+
+    // relative displacements are signed, and computed from the end of the field.
+    vec![v as VA, (field + 4).wrapping_add(v as i32 as i64 as u64)]
+}
+
+/// guess the address referenced by the relocated field at the given address:
+/// the first interpretation that falls within the module.
+fn get_reference_target(pe: &PE, field: VA) -> Option<VA> {
+    get_reference_targets(pe, field)
+        .into_iter()
+        .find(|&target| pe.module.probe_va(target, Permissions::RWX))
+}
+
+/// resolve the name referenced by the relocated field at the given address,
+/// if its already known.
+fn resolve_reference(pe: &PE, field: VA, names: &BTreeMap<VA, String>) -> Option<String> {
+    get_reference_targets(pe, field)
+        .iter()
+        .find_map(|target| names.get(target))
+        .cloned()
+}
+
+/// read the bytes that a signature may describe at the given address:
+/// up to the largest function size, without leaving the section.
+fn read_function_bytes(pe: &PE, va: VA) -> Option<Vec<u8>> {
+    let end = pe
+        .module
+        .sections
+        .iter()
+        .find(|section| section.virtual_range.contains(&va))
+        .map(|section| std::cmp::min(section.virtual_range.end, va + MAX_FUNCTION_SIZE))?;
+
+    pe.module.address_space.read_bytes(va, (end - va) as usize).ok()
+}
+
+/// find the signatures that match the bytes at the given address,
+/// without considering references.
+fn match_function<'a>(pe: &PE, libraries: &'a [Library], va: VA) -> Vec<(&'a Library, &'a FlirtSignature)> {
+    let buf = match read_function_bytes(pe, va) {
+        Some(buf) => buf,
+        None => return vec![],
+    };
+
+    libraries
+        .iter()
+        .flat_map(|library| {
+            library
+                .sigs
+                .r#match(&buf)
+                .into_iter()
+                .filter(|sig| sig.get_name().is_some())
+                .map(move |sig| (library, sig))
+        })
+        .collect()
+}
+
+/// pick the best signature for the function at the given address
+/// from the candidate libraries, using the references to disambiguate
+/// (see `FlirtSignatureSet::match_with_resolver`).
+///
+/// if the best signatures disagree about the function name, then the match is ambiguous.
+fn select_candidate<'a>(
+    pe: &PE,
+    va: VA,
+    libraries: &[&'a Library],
+    names: &BTreeMap<VA, String>,
+) -> Option<(&'a Library, &'a FlirtSignature)> {
+    let buf = read_function_bytes(pe, va)?;
+
+    let best: Vec<(&Library, &FlirtSignature)> = libraries
+        .iter()
+        .flat_map(|&library| {
+            library
+                .sigs
+                .match_with_resolver(&buf, |offset| resolve_reference(pe, va + offset, names))
+                .into_iter()
+                .filter(|sig| sig.get_name().is_some())
+                .map(move |sig| (library, sig))
+        })
+        .collect();
+
+    let name = best.first()?.1.get_name();
+    if best.iter().all(|(_, sig)| sig.get_name() == name) {
+        Some(best[0])
+    } else {
+        debug!("flirt: ambiguous match: {:#x}: {} candidates", va, best.len());
+        None
+    }
+}
+
+/// record that signatures from the given library match at the given address.
+fn add_candidate<'a>(candidates: &mut BTreeMap<VA, Vec<&'a Library>>, va: VA, library: &'a Library) {
+    let libraries = candidates.entry(va).or_default();
+    if !libraries.iter().any(|&other| std::ptr::eq(other, library)) {
+        libraries.push(library);
+    }
+}
+
+/// describe the given PE so that we can select the signature files that apply.
+///
+/// the Microsoft linker emits a Rich header, so when we find one,
+/// we only consider libraries from MSVC (and those from unknown compilers),
+/// and prefer those from the releases of Visual Studio that the header lists.
+pub fn get_pe_target(pe: &PE) -> Result<Target> {
+    let mut app_types = match pe.module.arch {
+        Arch::X32 => AppTypes::BITS_32,
+        Arch::X64 => AppTypes::BITS_64,
+    };
+    if pe.header.coff_header.characteristics & IMAGE_FILE_DLL > 0 {
+        app_types |= AppTypes::DLL;
+    } else {
+        app_types |= AppTypes::EXE;
+    }
+
+    let mut compilers = vec![];
+    let mut msvc_versions = vec![];
+    if let Some(entries) = rich::get_rich_header(pe)? {
+        compilers.push(Compiler::Msvc);
+
+        msvc_versions.extend(entries.iter().filter_map(|entry| entry.visual_studio_version()));
+        msvc_versions.sort_unstable();
+        msvc_versions.dedup();
+    }
+
+    Ok(Target {
+        arch: sig::ARCH_X86,
+        file_types: FileTypes::PE,
+        os_types: OsTypes::WIN,
+        app_types,
+        compilers,
+        msvc_versions,
+    })
+}
+
+/// select the libraries from the given index that likely apply to the given PE.
+///
+/// we describe the PE (see `get_pe_target`), and then match the startup
+/// signatures against its entry point: the compilers of the matching
+/// libraries likely produced the PE.
+pub fn select_pe_libraries<'a>(pe: &PE, index: &'a LibraryIndex) -> Result<Vec<&'a LibraryInfo>> {
+    let mut target = get_pe_target(pe)?;
+
+    for va in find_pe_entrypoint(pe)?.into_iter() {
+        let buf = match read_function_bytes(pe, va) {
+            Some(buf) => buf,
+            None => continue,
+        };
+
+        for info in index.match_startup(&target, &buf)?.into_iter() {
+            debug!("flirt: startup signatures match entry point: {:#x}: {}", va, info.name);
+            if !target.compilers.contains(&info.compiler) && info.compiler != Compiler::Unknown {
+                target.compilers.push(info.compiler);
+            }
+        }
+    }
+
+    Ok(index.select(&target))
+}
+
+/// find the names we already know for addresses in the given PE:
+/// imports, thunks, and exports.
+/// the names are not demangled.
+pub fn find_pe_known_names(pe: &PE, functions: &[Function]) -> Result<BTreeMap<VA, String>> {
+    let mut names: BTreeMap<VA, String> = Default::default();
+    for function in functions.iter() {
+        let import = match function {
+            Function::Local(_) => continue,
+            Function::Import(import) => import,
+            Function::Thunk(thunk) => {
+                if let ImportedSymbol::Name(name) = &thunk.import.symbol {
+                    names.insert(thunk.address, name.to_string());
+                }
+                &thunk.import
+            }
+        };
+
+        if let ImportedSymbol::Name(name) = &import.symbol {
+            names.insert(import.address, name.to_string());
+        }
+    }
+    for exp in pe.pe()?.exports.iter() {
+        if let Some(name) = exp.name {
+            names
+                .entry(pe.module.address_space.base_address + exp.rva as u64)
+                .or_insert_with(|| name.to_string());
+        }
+    }
+
+    Ok(names)
+}
+
+/// find the local functions that match FLIRT signatures from the given
+/// libraries.
+pub fn find_pe_library_functions(
+    pe: &PE,
+    functions: &[Function],
+    libraries: &[Library],
+) -> Result<BTreeMap<VA, LibraryFunction>> {
+    // names that references may be verified against.
+    let names = find_pe_known_names(pe, functions)?;
+
+    let mut candidates: BTreeMap<VA, Vec<&Library>> = Default::default();
+    for function in functions.iter() {
+        if let Function::Local(va) = function {
+            for (library, _) in match_function(pe, libraries, *va).into_iter() {
+                add_candidate(&mut candidates, *va, library);
+            }
+        }
+    }
+    debug!("flirt: found {} candidate functions", candidates.len());
+
+    Ok(resolve_candidates(pe, &candidates, names))
+}
+
+/// label each of the given local functions with the library function that it matches, if any.
+/// thunks and imports are not included.
+pub fn label_pe_functions(pe: &PE, functions: &[Function], libraries: &[Library]) -> Result<Vec<LabeledFunction>> {
+    let mut library_functions = find_pe_library_functions(pe, functions, libraries)?;
+
+    Ok(functions
+        .iter()
+        .filter_map(|function| match function {
+            Function::Local(va) => Some(LabeledFunction {
+                address: *va,
+                library: library_functions.remove(va),
+            }),
+            _ => None,
+        })
+        .collect())
+}
+
+/// find the local functions that don't match FLIRT signatures from the given libraries,
+/// that is, the functions written by the program author.
+pub fn find_pe_user_functions(pe: &PE, functions: &[Function], libraries: &[Library]) -> Result<Vec<VA>> {
+    Ok(label_pe_functions(pe, functions, libraries)?
+        .into_iter()
+        .filter(|function| !function.is_library())
+        .map(|function| function.address)
+        .collect())
+}
+
+/// scan the executable sections of the given PE for the functions that match
+/// FLIRT signatures from the given libraries, at any offset.
+///
+/// unlike `find_pe_library_functions`, this doesn't rely upon function discovery,
+/// so it finds library functions whose starts were missed, such as those only
+/// reached through computed calls in statically linked code.
+/// the scan resumes after each match, so matches don't overlap.
+pub fn scan_pe_library_functions(
+    pe: &PE,
+    functions: &[Function],
+    libraries: &[Library],
+) -> Result<BTreeMap<VA, LibraryFunction>> {
+    let names = find_pe_known_names(pe, functions)?;
+
+    let mut candidates: BTreeMap<VA, Vec<&Library>> = Default::default();
+    for section in pe.module.sections.iter() {
+        if !section.permissions.intersects(Permissions::X) {
+            continue;
+        }
+
+        let start = section.virtual_range.start;
+        let buf = pe
+            .module
+            .address_space
+            .read_bytes(start, (section.virtual_range.end - start) as usize)?;
+
+        for library in libraries.iter() {
+            for m in library.sigs.scan(&buf, Overlap::Skip).into_iter() {
+                if m.sig.get_name().is_some() {
+                    add_candidate(&mut candidates, start + m.offset as u64, library);
+                }
+            }
+        }
+    }
+    debug!("flirt: found {} candidate functions by scanning", candidates.len());
+
+    Ok(resolve_candidates(pe, &candidates, names))
+}
+
+/// select the best signature for each candidate function.
+///
+/// since verifying a reference may depend upon another library function
+/// having been recognized, repeat until no new functions are recognized.
+fn resolve_candidates(
+    pe: &PE,
+    candidates: &BTreeMap<VA, Vec<&Library>>,
+    mut names: BTreeMap<VA, String>,
+) -> BTreeMap<VA, LibraryFunction> {
+    let mut ret: BTreeMap<VA, LibraryFunction> = Default::default();
+    loop {
+        let mut found = vec![];
+        for (&va, candidates) in candidates.iter() {
+            if ret.contains_key(&va) {
+                continue;
+            }
+
+            if let Some((library, sig)) = select_candidate(pe, va, candidates, &names) {
+                let m = FlirtMatch::new(sig, va);
+                let name = m.get_name().expect("candidates have names").to_string();
+                debug!("flirt: found library function: {:#x}: {}: {}", va, library.name, name);
+
+                let to_owned = |names: &[(VA, &str)]| -> Vec<(VA, String)> {
+                    names.iter().map(|&(va, name)| (va, name.to_string())).collect()
+                };
+
+                found.push(LibraryFunction {
+                    address: va,
+                    name,
+                    library: library.name.clone(),
+                    public_names: to_owned(&m.public_names),
+                    local_names: to_owned(&m.local_names),
+                    references: m
+                        .references
+                        .iter()
+                        .filter_map(|&(field, name)| {
+                            get_reference_target(pe, field).map(|target| (target, name.to_string()))
+                        })
+                        .collect(),
+                });
+            }
+        }
+
+        if found.is_empty() {
+            break;
+        }
+
+        for f in found.into_iter() {
+            // the other names defined by the function, like alternate entry points,
+            // may verify the references of the remaining candidates.
+            for (va, name) in f.public_names.iter().chain(f.local_names.iter()) {
+                names.entry(*va).or_insert_with(|| name.clone());
+            }
+            names.insert(f.address, f.name.clone());
+            ret.insert(f.address, f);
+        }
+    }
+
+    ret
+}
+
+/// find the size of the function: the contiguous run of basic blocks
+/// from its start, up to the largest size that FLIRT can describe.
+fn get_function_size(cfg: &CFG, va: VA) -> u64 {
+    let mut end = va;
+    for bb in cfg.basic_blocks.range(va..).map(|(_, bb)| bb) {
+        if bb.address > end {
+            break;
+        }
+        end = std::cmp::max(end, bb.address + bb.length);
+    }
+
+    std::cmp::min(end - va, MAX_FUNCTION_SIZE)
+}
+
+/// a field within an instruction that encodes an address,
+/// which may change from one build to the next.
+struct Field {
+    /// the offset of the field from the start of the function.
+    offset:  u64,
+    size:    u64,
+    /// the addresses that the field may reference.
+    targets: Vec<VA>,
+}
+
+/// find the fields within the function that encode addresses:
+///
+///   - locations fixed up by base relocations,
+///   - RIP-relative displacements, and
+///   - relative branch targets that leave the function.
+fn get_function_fields(
+    pe: &PE,
+    decoder: &zydis::Decoder,
+    relocs: &BTreeMap<VA, u64>,
+    cg: &CallGraph,
+    va: VA,
+    buf: &[u8],
+) -> Result<Vec<Field>> {
+    let size = buf.len() as u64;
+    let mut fields: Vec<Field> = vec![];
+
+    for (&address, &reloc_size) in relocs.range(va..va + size) {
+        let targets = match reloc_size {
+            4 => pe.module.address_space.read_u32(address).ok().map(|v| v as VA),
+            8 => pe.module.address_space.read_u64(address).ok(),
+            _ => None,
+        };
+
+        fields.push(Field {
+            offset:  address - va,
+            size:    reloc_size,
+            targets: targets.into_iter().collect(),
+        });
+    }
+
+    for (offset, insn) in dis::linear_disassemble(decoder, buf) {
+        let insn = match insn {
+            Ok(Some(insn)) => insn,
+            _ => continue,
+        };
+        let insn_va = va + offset as u64;
+        let next_va = insn_va + insn.length as u64;
+
+        let mut imm_index = 0;
+        for op in insn.operands.iter().take(insn.operand_count as usize) {
+            let field = match op.ty {
+                zydis::OperandType::MEMORY
+                    if op.mem.base == zydis::Register::RIP && op.mem.disp.has_displacement =>
+                {
+                    Some(Field {
+                        offset:  offset as u64 + insn.raw.disp.offset as u64,
+                        size:    insn.raw.disp.size as u64 / 8,
+                        targets: va_add_signed(next_va, op.mem.disp.displacement).into_iter().collect(),
+                    })
+                }
+                zydis::OperandType::IMMEDIATE => {
+                    let raw = &insn.raw.imm[std::cmp::min(imm_index, 1)];
+                    imm_index += 1;
+
+                    if !op.imm.is_relative {
+                        continue;
+                    }
+
+                    let imm = if op.imm.is_signed {
+                        util::u64_i64(op.imm.value)
+                    } else {
+                        op.imm.value as i64
+                    };
+
+                    match va_add_signed(next_va, imm) {
+                        // branches within the function don't change when the function moves.
+                        Some(target) if target >= va && target < va + size => None,
+                        target => Some(Field {
+                            offset:  offset as u64 + raw.offset as u64,
+                            size:    raw.size as u64 / 8,
+                            targets: target.into_iter().collect(),
+                        }),
+                    }
+                }
+                _ => None,
+            };
+
+            if let Some(mut field) = field {
+                // the call graph may resolve call targets through pointers.
+                if let Some(targets) = cg.calls_from.get(&insn_va) {
+                    field.targets.extend(targets.iter().cloned());
+                }
+
+                fields.push(field);
+            }
+        }
+    }
+
+    Ok(fields)
+}
+
+/// generate FLIRT signatures for the named functions in the given PE,
+/// such as those from exports or a PDB.
+///
+/// bytes that may change from one build to the next, such as relocated
+/// addresses, RIP-relative displacements, and call targets, are wildcards.
+/// when these fields reference a named address (like an import or another
+/// function), the name is recorded as a reference of the signature.
+///
+/// functions without a CFG are skipped.
+pub fn generate_pe_signatures(
+    pe: &PE,
+    cfgs: &BTreeMap<VA, CFG>,
+    cg: &CallGraph,
+    names: &BTreeMap<VA, String>,
+) -> Result<Vec<FlirtSignature>> {
+    let decoder = dis::get_disassembler(&pe.module)?;
+    let relocs: BTreeMap<VA, u64> = reloc::get_relocations(pe)?
+        .into_iter()
+        .map(|reloc| (reloc.address, reloc.typ.size()))
+        .collect();
+
+    let mut sigs = vec![];
+    for (&va, cfg) in cfgs.iter() {
+        let name = match names.get(&va) {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let size = get_function_size(cfg, va);
+        if size == 0 {
+            continue;
+        }
+        let buf = pe.module.address_space.read_bytes(va, size as usize)?;
+
+        let mut wildcards = vec![false; buf.len()];
+        let mut references: Vec<(i64, &str)> = vec![];
+        for field in get_function_fields(pe, &decoder, &relocs, cg, va, &buf)?.iter() {
+            for wildcard in wildcards.iter_mut().skip(field.offset as usize).take(field.size as usize) {
+                *wildcard = true;
+            }
+
+            if let Some(name) = field.targets.iter().find_map(|target| names.get(target)) {
+                references.push((field.offset as i64, name));
+            }
+        }
+        references.sort_unstable();
+        references.dedup();
+
+        debug!(
+            "flirt: generated signature: {:#x}: {}: {} references",
+            va,
+            name,
+            references.len()
+        );
+        sigs.push(FlirtSignature::from_function(&buf, &wildcards, name, &references));
+    }
+
+    Ok(sigs)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        analysis::pe::{flirt::*, Import},
+        rsrc::*,
+    };
+    use anyhow::Result;
+    use std::collections::BTreeMap;
+
+    fn get_libraries() -> Result<Vec<Library>> {
+        let buf = include_bytes!("../../../../flirt/sigs/sig/libcmt_15_msvc_x86.sig");
+        Ok(vec![Library {
+            name: String::from("libcmt_15_msvc_x86"),
+            sigs: FlirtSignatureSet::with_signatures(lancelot_flirt::sig::parse(buf)?),
+        }])
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let functions = crate::analysis::pe::find_functions(&pe)?;
+        let fns = find_pe_library_functions(&pe, &functions, &get_libraries()?)?;
+        assert_eq!(0, fns.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // nop.exe is statically linked against the CRT, and calls `__aulldvrm` from 0x4019EE.
+        // `?__scrt_uninitialize_type_info@@YAXXZ` is only reached by a conditional jump.
+        let mut functions = crate::analysis::pe::find_functions(&pe)?;
+        functions.push(Function::Local(0x401F8D));
+        functions.push(Function::Local(0x402D80));
+
+        let fns = find_pe_library_functions(&pe, &functions, &get_libraries()?)?;
+        assert_eq!(fns[&0x401F8D].name, "?__scrt_uninitialize_type_info@@YAXXZ");
+        assert_eq!(fns[&0x402D80].name, "__aulldvrm");
+        assert_eq!(fns[&0x402D80].library, "libcmt_15_msvc_x86");
+        // `main` is the user's code, not the CRT's.
+        assert!(!fns.contains_key(&0x401000));
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop_label() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let libraries = get_libraries()?;
+
+        let functions = vec![
+            Function::Local(0x401000),
+            Function::Local(0x402D80),
+            Function::Import(Import {
+                address: 0x406000,
+                dll:     "kernel32.dll".into(),
+                symbol:  ImportedSymbol::Name("ExitProcess".into()),
+            }),
+        ];
+
+        let labels = label_pe_functions(&pe, &functions, &libraries)?;
+        assert_eq!(labels.len(), 2);
+        assert_eq!(labels[0].address, 0x401000);
+        assert!(!labels[0].is_library());
+        assert_eq!(labels[1].address, 0x402D80);
+        assert_eq!(labels[1].library.as_ref().unwrap().name, "__aulldvrm");
+
+        assert_eq!(find_pe_user_functions(&pe, &functions, &libraries)?, vec![0x401000]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop_static() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+
+        // nop.exe is statically linked against the CRT.
+        // the slack at the end of .text is used for a couple more library functions:
+        //
+        //   0x405F50: ??2@YAPAXI@Z (operator new)
+        //   0x405FA0: ??_U@YAPAXI@Z (operator new[]), which just jumps to operator new:
+        //
+        //     55          push ebp
+        //     8B EC       mov  ebp, esp
+        //     5D          pop  ebp
+        //     E9 ...      jmp  ??2@YAPAXI@Z
+        //
+        // the bytes of operator new[] also match `??_U@YAPAXIABUnothrow_t@std@@@Z` and `__CRT_INIT@12`,
+        // which are only told apart by the name of the function that they jump to.
+        let operator_new = [
+            0x55, 0x8B, 0xEC, 0xEB, 0x1F, 0xFF, 0x75, 0x08, 0xE8, 0x00, 0x00, 0x00, 0x00, 0x59, 0x85, 0xC0,
+            0x75, 0x12, 0x83, 0x7D, 0x08, 0xFF, 0x75, 0x07, 0xE8, 0x00, 0x00, 0x00, 0x00, 0xEB, 0x05, 0xE8,
+        ];
+        buf[0x5F50..0x5F50 + operator_new.len()].copy_from_slice(&operator_new);
+        buf[0x5FA0..0x5FA5].copy_from_slice(b"\x55\x8B\xEC\x5D\xE9");
+        buf[0x5FA5..0x5FA9].copy_from_slice(&(0x405F50u32.wrapping_sub(0x405FA9)).to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let libraries = get_libraries()?;
+
+        let functions = vec![
+            Function::Local(0x401F8D),
+            Function::Local(0x402D80),
+            Function::Local(0x405F50),
+            Function::Local(0x405FA0),
+        ];
+        let fns = find_pe_library_functions(&pe, &functions, &libraries)?;
+        assert_eq!(fns.len(), 4);
+        assert_eq!(fns[&0x401F8D].name, "?__scrt_uninitialize_type_info@@YAXXZ");
+        assert_eq!(fns[&0x402D80].name, "__aulldvrm");
+        assert_eq!(fns[&0x405F50].name, "??2@YAPAXI@Z");
+        assert_eq!(fns[&0x405FA0].name, "??_U@YAPAXI@Z");
+        assert_eq!(fns[&0x405FA0].library, "libcmt_15_msvc_x86");
+        assert_eq!(
+            fns[&0x405FA0].references,
+            vec![(0x405F50, String::from("??2@YAPAXI@Z"))]
+        );
+
+        // when operator new isn't recognized, the match is ambiguous.
+        assert_eq!(match_function(&pe, &libraries, 0x405FA0).len(), 3);
+        let fns = find_pe_library_functions(&pe, &[Function::Local(0x405FA0)], &libraries)?;
+        assert!(fns.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop_scan() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let functions = crate::analysis::pe::find_functions(&pe)?;
+        let fns = scan_pe_library_functions(&pe, &functions, &get_libraries()?)?;
+        // these aren't found when matching only at the starts of discovered functions.
+        assert_eq!(2, fns.len());
+        assert_eq!(fns[&0x401F8D].name, "?__scrt_uninitialize_type_info@@YAXXZ");
+        assert_eq!(fns[&0x402D80].name, "__aulldvrm");
+        assert_eq!(
+            fns[&0x402D80].public_names,
+            vec![(0x402D80, String::from("__aulldvrm"))]
+        );
+        assert!(fns[&0x402D80].local_names.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32_target() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let target = get_pe_target(&pe)?;
+        assert_eq!(target.app_types, AppTypes::BITS_64 | AppTypes::DLL);
+        assert_eq!(target.compilers, vec![Compiler::Msvc]);
+        // built with Visual Studio 2017, though its import libraries come from 2008.
+        assert_eq!(target.msvc_versions, vec![2008, 2017]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn tiny_target() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let target = get_pe_target(&pe)?;
+        assert_eq!(target.app_types, AppTypes::BITS_32 | AppTypes::EXE);
+        assert!(target.compilers.is_empty());
+        assert!(target.msvc_versions.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32_select() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // k32 has a Rich header, so MSVC libraries are candidates,
+        // but it's an x64 DLL, and the only library is for x86.
+        let index = LibraryIndex::from_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/../flirt/sigs/sig"))?;
+        assert!(select_pe_libraries(&pe, &index)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop_select() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // the Rich header of nop lists the tools from Visual Studio .NET 2003.
+        let target = get_pe_target(&pe)?;
+        assert_eq!(target.compilers, vec![Compiler::Msvc]);
+        assert_eq!(target.msvc_versions, vec![2003]);
+
+        // there are no signatures for 2003, so the library from 2017 is still selected.
+        let index = LibraryIndex::from_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/../flirt/sigs/sig"))?;
+        let libraries = select_pe_libraries(&pe, &index)?;
+        assert_eq!(libraries.len(), 1);
+        assert_eq!(libraries[0].name, "libcmt_15_msvc_x86");
+        assert_eq!(libraries[0].msvc_version(), Some(2017));
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32_generate() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let functions = crate::analysis::pe::find_functions(&pe)?;
+        let names = find_pe_known_names(&pe, &functions)?;
+
+        let mut cfgs: BTreeMap<VA, CFG> = Default::default();
+        for function in functions.iter() {
+            if let Function::Local(va) = function {
+                if names.contains_key(va) {
+                    if let Ok(cfg) = crate::analysis::cfg::build_cfg(&pe.module, *va) {
+                        cfgs.insert(*va, cfg);
+                    }
+                }
+            }
+        }
+        let cg = crate::analysis::call_graph::build_call_graph(&pe.module, &cfgs)?;
+
+        let sigs = generate_pe_signatures(&pe, &cfgs, &cg, &names)?;
+        assert_eq!(sigs.len(), cfgs.len());
+        assert!(lancelot_flirt::pat::write(&sigs).is_ok());
+
+        // each function should be recognized by the signature generated from it.
+        let libraries = vec![Library {
+            name: String::from("k32"),
+            sigs: FlirtSignatureSet::with_signatures(sigs),
+        }];
+        for &va in cfgs.keys() {
+            assert!(match_function(&pe, &libraries, va)
+                .iter()
+                .any(|(_, sig)| sig.get_name() == names.get(&va).map(|name| name.as_str())));
+        }
+
+        Ok(())
+    }
+}
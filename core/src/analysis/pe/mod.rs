@@ -6,6 +6,7 @@ use log::debug;
 use crate::{
     analysis::{cfg, dis},
     aspace::AddressSpace,
+    demangle,
     loader::pe::{
         imports,
         imports::{read_best_thunk_data, IMAGE_THUNK_DATA},
@@ -14,12 +15,19 @@ use crate::{
     RVA, VA,
 };
 
+pub mod anomalies;
 pub mod call_targets;
+pub mod carve;
 pub mod control_flow_guard;
+pub mod embedded;
+pub mod entropy;
 pub mod entrypoints;
+pub mod exception_handlers;
 pub mod exports;
+pub mod flirt;
 pub mod patterns;
 pub mod pointers;
+pub mod rtti;
 pub mod runtime_functions;
 pub mod safeseh;
 
@@ -38,15 +46,22 @@ pub struct Import {
     pub symbol:  ImportedSymbol,
 }
 
-impl std::fmt::Display for Import {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+impl Import {
+    /// render the import like `dll!name`, demangling the name if possible.
+    pub fn render(&self, form: demangle::Form) -> String {
         match &self.symbol {
-            ImportedSymbol::Ordinal(ord) => write!(f, "{}!#{}", self.dll, ord),
-            ImportedSymbol::Name(name) => write!(f, "{}!{}", self.dll, name),
+            ImportedSymbol::Ordinal(ord) => format!("{}!#{}", self.dll, ord),
+            ImportedSymbol::Name(name) => format!("{}!{}", self.dll, demangle::demangle_or_raw(name, form)),
         }
     }
 }
 
+impl std::fmt::Display for Import {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.render(demangle::Form::Full))
+    }
+}
+
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd)]
 pub struct Thunk {
     /// the address of the function thunk
@@ -188,6 +203,8 @@ pub fn find_functions(pe: &PE) -> Result<Vec<Function>> {
     function_starts.extend(crate::analysis::pe::exports::find_pe_exports(&pe)?);
     function_starts.extend(crate::analysis::pe::safeseh::find_pe_safeseh_handlers(&pe)?);
     function_starts.extend(crate::analysis::pe::runtime_functions::find_pe_runtime_functions(&pe)?);
+    function_starts.extend(crate::analysis::pe::exception_handlers::find_pe_exception_handlers(&pe)?);
+    function_starts.extend(crate::analysis::pe::rtti::find_pe_virtual_methods(&pe)?.keys());
     function_starts.extend(crate::analysis::pe::control_flow_guard::find_pe_cfguard_functions(&pe)?);
     function_starts.extend(crate::analysis::pe::call_targets::find_pe_call_targets(&pe)?);
     function_starts.extend(crate::analysis::pe::patterns::find_function_prologues(&pe)?);
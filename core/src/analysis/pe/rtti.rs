@@ -0,0 +1,489 @@
+//! Recover C++ classes and their vtables from MSVC RTTI metadata.
+//!
+//! When RTTI is enabled, MSVC emits a pointer to an
+//! `RTTICompleteObjectLocator` immediately before each vtable:
+//!
+//! ```text
+//!     .rdata: dd offset ??_R4CFoo@@6B@     ; const CFoo::`RTTI Complete Object Locator'
+//!     .rdata: ??_7CFoo@@6B@                ; const CFoo::`vftable'
+//!     .rdata: dd offset sub_401000
+//!     .rdata: dd offset sub_401020
+//! ```
+//!
+//! The locator references the `TypeDescriptor` (which contains the mangled
+//! class name, like `.?AVCFoo@@`) and the `RTTIClassHierarchyDescriptor`
+//! (which enumerates the base classes).
+//!
+//! So, we scan the data sections for pointers to valid locators,
+//! and then read the vtable entries that follow until we encounter
+//! something that doesn't point to code.
+//! Each vtable entry is a virtual method, which we name after its class.
+//!
+//! On x32, the RTTI structures reference each other using VAs.
+//! On x64, they use RVAs, and the locator contains its own RVA,
+//! which makes it easy to validate.
+//!
+//! references:
+//!   - http://www.openrce.org/articles/full_view/23
+//!   - https://www.blackhat.com/presentations/bh-dc-07/Sabanal_Yason/Paper/bh-dc-07-Sabanal_Yason-WP.pdf
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use byteorder::ByteOrder;
+use log::debug;
+
+use crate::{arch::Arch, aspace::AddressSpace, demangle, loader::pe::PE, module::Permissions, VA};
+
+/// upper bound on the number of entries we'll read from any table.
+/// tables larger than this are assumed to be garbage.
+const MAX_ENTRY_COUNT: u32 = 0x400;
+
+const COL_SIGNATURE_X32: u32 = 0;
+const COL_SIGNATURE_X64: u32 = 1;
+
+#[derive(Debug, Clone)]
+pub struct Class {
+    /// address of the `TypeDescriptor`.
+    pub type_descriptor: VA,
+    /// the decorated name from the `TypeDescriptor`, like `.?AVCFoo@@`.
+    pub mangled_name:    String,
+    /// the human readable name, like `CFoo`.
+    pub name:            String,
+    /// human readable names of the base classes, in the order found in the
+    /// class hierarchy descriptor.
+    /// does not include the class itself.
+    pub bases:           Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct VTable {
+    /// address of the first virtual method pointer.
+    pub address: VA,
+    /// address of the `RTTICompleteObjectLocator`.
+    pub locator: VA,
+    /// offset of the vtable's subobject within the complete class.
+    /// non-zero for the secondary vtables of classes with multiple
+    /// inheritance.
+    pub offset:  u32,
+    pub class:   Class,
+    /// addresses of the virtual methods, in vtable order.
+    pub methods: Vec<VA>,
+}
+
+/// RTTI structures reference each other using absolute VAs on x32,
+/// and image-relative RVAs on x64.
+/// both are 32 bits wide.
+fn read_rtti_pointer(pe: &PE, va: VA) -> Result<VA> {
+    let v = pe.module.address_space.read_u32(va)? as VA;
+    match pe.module.arch {
+        Arch::X32 => Ok(v),
+        Arch::X64 => Ok(pe.module.address_space.base_address + v),
+    }
+}
+
+/// read the `TypeDescriptor` at the given address.
+///
+/// ```text
+/// 0x0     void *pVFTable   // type_info::`vftable'
+/// psize   void *spare
+/// psize*2 char name[]
+/// ```
+fn read_type_descriptor_name(pe: &PE, va: VA) -> Result<Option<String>> {
+    if !pe.module.probe_va(va, Permissions::R) {
+        return Ok(None);
+    }
+
+    let name_address = va + 2 * pe.module.arch.pointer_size() as VA;
+    let name = match pe.module.address_space.read_ascii(name_address, 4) {
+        Ok(name) => name,
+        Err(_) => return Ok(None),
+    };
+
+    if !name.starts_with(".?A") {
+        return Ok(None);
+    }
+
+    Ok(Some(name))
+}
+
+fn read_class(pe: &PE, type_descriptor: VA) -> Result<Option<(String, String)>> {
+    Ok(read_type_descriptor_name(pe, type_descriptor)?.map(|mangled_name| {
+        let name = demangle::demangle_or_raw(&mangled_name, demangle::Form::Short);
+        (mangled_name, name)
+    }))
+}
+
+/// read the names of the base classes from the `RTTIClassHierarchyDescriptor`
+/// at the given address.
+///
+/// ```text
+/// RTTIClassHierarchyDescriptor:
+///   0x0  u32 signature
+///   0x4  u32 attributes
+///   0x8  u32 numBaseClasses
+///   0xC  ptr pBaseClassArray
+///
+/// RTTIBaseClassArray:
+///   ptr  arrayOfBaseClassDescriptors[numBaseClasses]
+///
+/// RTTIBaseClassDescriptor:
+///   0x0  ptr pTypeDescriptor
+///   ...
+/// ```
+///
+/// the first entry of the base class array is the class itself.
+fn read_class_hierarchy(pe: &PE, va: VA) -> Result<Option<Vec<String>>> {
+    if !pe.module.probe_va(va, Permissions::R) {
+        return Ok(None);
+    }
+
+    let signature = pe.module.address_space.read_u32(va)?;
+    let base_class_count = pe.module.address_space.read_u32(va + 0x8)?;
+    if signature != 0 || base_class_count == 0 || base_class_count > MAX_ENTRY_COUNT {
+        return Ok(None);
+    }
+
+    let base_class_array = read_rtti_pointer(pe, va + 0xC)?;
+    if !pe.module.probe_va(base_class_array, Permissions::R) {
+        return Ok(None);
+    }
+
+    let mut bases = vec![];
+    for i in 1..base_class_count as VA {
+        let base_class_descriptor = read_rtti_pointer(pe, base_class_array + i * 4)?;
+        if !pe.module.probe_va(base_class_descriptor, Permissions::R) {
+            return Ok(None);
+        }
+
+        let type_descriptor = read_rtti_pointer(pe, base_class_descriptor)?;
+        match read_class(pe, type_descriptor)? {
+            Some((_, name)) => bases.push(name),
+            None => return Ok(None),
+        }
+    }
+
+    Ok(Some(bases))
+}
+
+/// read and validate the `RTTICompleteObjectLocator` at the given address.
+///
+/// ```text
+/// 0x00 u32 signature           // 0 on x32, 1 on x64
+/// 0x04 u32 offset              // offset of this vtable in the complete class
+/// 0x08 u32 cdOffset            // constructor displacement offset
+/// 0x0C ptr pTypeDescriptor
+/// 0x10 ptr pClassDescriptor
+/// 0x14 ptr pSelf               // x64 only
+/// ```
+///
+/// returns the offset and class.
+fn read_complete_object_locator(pe: &PE, va: VA) -> Result<Option<(u32, Class)>> {
+    if !pe.module.probe_va(va, Permissions::R) {
+        return Ok(None);
+    }
+
+    let signature = pe.module.address_space.read_u32(va)?;
+    match (pe.module.arch, signature) {
+        (Arch::X32, COL_SIGNATURE_X32) => (),
+        (Arch::X64, COL_SIGNATURE_X64) => {
+            if read_rtti_pointer(pe, va + 0x14)? != va {
+                return Ok(None);
+            }
+        }
+        _ => return Ok(None),
+    }
+
+    let offset = pe.module.address_space.read_u32(va + 0x4)?;
+    let type_descriptor = read_rtti_pointer(pe, va + 0xC)?;
+    let class_descriptor = read_rtti_pointer(pe, va + 0x10)?;
+
+    let (mangled_name, name) = match read_class(pe, type_descriptor)? {
+        Some(class) => class,
+        None => return Ok(None),
+    };
+
+    let bases = match read_class_hierarchy(pe, class_descriptor)? {
+        Some(bases) => bases,
+        None => return Ok(None),
+    };
+
+    Ok(Some((
+        offset,
+        Class {
+            type_descriptor,
+            mangled_name,
+            name,
+            bases,
+        },
+    )))
+}
+
+/// read the consecutive pointers to code that make up a vtable.
+fn read_vtable_methods(pe: &PE, va: VA) -> Vec<VA> {
+    let psize = pe.module.arch.pointer_size() as VA;
+
+    let mut methods = vec![];
+    for i in 0..MAX_ENTRY_COUNT as VA {
+        match pe.module.read_va_at_va(va + i * psize) {
+            Ok(method) if pe.module.probe_va(method, Permissions::X) => methods.push(method),
+            _ => break,
+        }
+    }
+
+    methods
+}
+
+/// find the vtables described by RTTI metadata.
+pub fn find_pe_vtables(pe: &PE) -> Result<Vec<VTable>> {
+    let psize = pe.module.arch.pointer_size();
+
+    let min_addr = pe.module.address_space.base_address;
+    let max_addr = pe
+        .module
+        .sections
+        .iter()
+        .map(|section| section.virtual_range.end)
+        .max()
+        .unwrap_or(min_addr);
+
+    // cache of locator address to its parsed contents (or invalid).
+    let mut locators: HashMap<VA, Option<(u32, Class)>> = Default::default();
+    let mut ret = vec![];
+
+    // vtables are found in initialized data, not code.
+    for section in pe
+        .module
+        .sections
+        .iter()
+        .filter(|section| !section.permissions.intersects(Permissions::X))
+    {
+        let vstart: VA = section.virtual_range.start;
+        let vsize = (section.virtual_range.end - section.virtual_range.start) as usize;
+        let sec_buf = pe.module.address_space.read_bytes(vstart, vsize)?;
+
+        for (i, b) in sec_buf.chunks_exact(psize).enumerate() {
+            let locator = match pe.module.arch {
+                Arch::X32 => byteorder::LittleEndian::read_u32(b) as VA,
+                Arch::X64 => byteorder::LittleEndian::read_u64(b) as VA,
+            };
+
+            // naive range filter that is very fast
+            if locator < min_addr || locator >= max_addr {
+                continue;
+            }
+
+            let entry = locators
+                .entry(locator)
+                .or_insert_with(|| read_complete_object_locator(pe, locator).unwrap_or(None));
+
+            if let Some((offset, class)) = entry {
+                let address = vstart + ((i + 1) * psize) as VA;
+                let methods = read_vtable_methods(pe, address);
+                if methods.is_empty() {
+                    continue;
+                }
+
+                debug!(
+                    "rtti: found vtable: {:#x} {} with {} methods",
+                    address,
+                    class.name,
+                    methods.len()
+                );
+
+                ret.push(VTable {
+                    address,
+                    locator,
+                    offset: *offset,
+                    class: class.clone(),
+                    methods,
+                });
+            }
+        }
+    }
+
+    Ok(ret)
+}
+
+/// find the virtual methods referenced by RTTI-described vtables,
+/// named like `CFoo::vfunc_0` after the first class and slot that
+/// references them.
+pub fn find_pe_virtual_methods(pe: &PE) -> Result<BTreeMap<VA, String>> {
+    let mut ret: BTreeMap<VA, String> = Default::default();
+
+    for vtable in find_pe_vtables(pe)?.iter() {
+        for (i, &method) in vtable.methods.iter().enumerate() {
+            ret.entry(method)
+                .or_insert_with(|| format!("{}::vfunc_{}", vtable.class.name, i));
+        }
+    }
+
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{arch::Arch, rsrc::*, RVA, VA};
+    use anyhow::Result;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    /// write the RTTI metadata for a class `CFoo` derived from `CBase`,
+    /// followed by a vtable with the given methods,
+    /// at the given file offset, which is mapped at the given RVA.
+    ///
+    /// returns the addresses of the locator and the vtable.
+    fn write_rtti(buf: &mut [u8], arch: Arch, base_address: VA, offset: usize, rva: RVA, methods: &[VA]) -> (VA, VA) {
+        let psize = arch.pointer_size();
+        let va = |delta: usize| base_address + rva + delta as RVA;
+        let ptr = |delta: usize| match arch {
+            Arch::X32 => va(delta) as u32,
+            Arch::X64 => (rva + delta as RVA) as u32,
+        };
+        let buf = &mut buf[offset..];
+
+        // TypeDescriptors, with the name following the vftable and spare pointers.
+        buf[2 * psize..2 * psize + 11].copy_from_slice(b".?AVCFoo@@\0");
+        buf[0x20 + 2 * psize..0x20 + 2 * psize + 12].copy_from_slice(b".?AVCBase@@\0");
+        // RTTIBaseClassDescriptors, of which only the TypeDescriptor is used.
+        LittleEndian::write_u32(&mut buf[0x40..], ptr(0x0));
+        LittleEndian::write_u32(&mut buf[0x50..], ptr(0x20));
+        // RTTIBaseClassArray, starting with the class itself.
+        LittleEndian::write_u32(&mut buf[0x60..], ptr(0x40));
+        LittleEndian::write_u32(&mut buf[0x64..], ptr(0x50));
+        // RTTIClassHierarchyDescriptor.
+        LittleEndian::write_u32(&mut buf[0x68..], 0);
+        LittleEndian::write_u32(&mut buf[0x6C..], 0);
+        LittleEndian::write_u32(&mut buf[0x70..], 2);
+        LittleEndian::write_u32(&mut buf[0x74..], ptr(0x60));
+        // RTTICompleteObjectLocator.
+        match arch {
+            Arch::X32 => LittleEndian::write_u32(&mut buf[0x78..], super::COL_SIGNATURE_X32),
+            Arch::X64 => LittleEndian::write_u32(&mut buf[0x78..], super::COL_SIGNATURE_X64),
+        }
+        LittleEndian::write_u32(&mut buf[0x7C..], 0);
+        LittleEndian::write_u32(&mut buf[0x80..], 0);
+        LittleEndian::write_u32(&mut buf[0x84..], ptr(0x0));
+        LittleEndian::write_u32(&mut buf[0x88..], ptr(0x68));
+        LittleEndian::write_u32(&mut buf[0x8C..], ptr(0x78));
+
+        // the locator pointer, then the vtable, terminated by a NULL.
+        let mut entries = vec![va(0x78)];
+        entries.extend(methods);
+        entries.push(0);
+        for (i, &entry) in entries.iter().enumerate() {
+            let entry_offset = 0x90 + i * psize;
+            match arch {
+                Arch::X32 => LittleEndian::write_u32(&mut buf[entry_offset..], entry as u32),
+                Arch::X64 => LittleEndian::write_u64(&mut buf[entry_offset..], entry),
+            }
+        }
+
+        (va(0x78), va(0x90 + psize))
+    }
+
+    #[test]
+    fn x32_vtable() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // unused space at the end of .rdata.
+        let (locator, address) = write_rtti(&mut buf, Arch::X32, 0x400000, 0x7400, 0x7400, &[0x401000, 0x401010]);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(1, vtables.len());
+        assert_eq!(vtables[0].address, 0x407494);
+        assert_eq!(vtables[0].address, address);
+        assert_eq!(vtables[0].locator, locator);
+        assert_eq!(vtables[0].offset, 0);
+        assert_eq!(vtables[0].class.type_descriptor, 0x407400);
+        assert_eq!(vtables[0].class.mangled_name, ".?AVCFoo@@");
+        assert_eq!(vtables[0].class.name, "CFoo");
+        assert_eq!(vtables[0].class.bases, vec!["CBase".to_string()]);
+        assert_eq!(vtables[0].methods, vec![0x401000, 0x401010]);
+
+        let methods = crate::analysis::pe::rtti::find_pe_virtual_methods(&pe)?;
+        assert_eq!(methods.len(), 2);
+        assert_eq!(methods[&0x401000], "CFoo::vfunc_0");
+        assert_eq!(methods[&0x401010], "CFoo::vfunc_1");
+
+        Ok(())
+    }
+
+    #[test]
+    fn x64_vtable() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        // unused space at the end of the file data of .data.
+        let (locator, address) = write_rtti(
+            &mut buf,
+            Arch::X64,
+            0x180000000,
+            0xA6230,
+            0xA8430,
+            &[0x180001000, 0x180001010, 0x180001020],
+        );
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(1, vtables.len());
+        assert_eq!(vtables[0].address, 0x1800A84C8);
+        assert_eq!(vtables[0].address, address);
+        assert_eq!(vtables[0].locator, locator);
+        assert_eq!(vtables[0].class.type_descriptor, 0x1800A8430);
+        assert_eq!(vtables[0].class.name, "CFoo");
+        assert_eq!(vtables[0].class.bases, vec!["CBase".to_string()]);
+        assert_eq!(vtables[0].methods, vec![0x180001000, 0x180001010, 0x180001020]);
+
+        let methods = crate::analysis::pe::rtti::find_pe_virtual_methods(&pe)?;
+        assert_eq!(methods.len(), 3);
+        assert_eq!(methods[&0x180001020], "CFoo::vfunc_2");
+
+        // the locator must reference itself on x64.
+        LittleEndian::write_u32(&mut buf[0xA6230 + 0x8C..], 0);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(0, crate::analysis::pe::rtti::find_pe_vtables(&pe)?.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(0, vtables.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn tiny() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(0, vtables.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(0, vtables.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let vtables = crate::analysis::pe::rtti::find_pe_vtables(&pe)?;
+        assert_eq!(0, vtables.len());
+
+        Ok(())
+    }
+}
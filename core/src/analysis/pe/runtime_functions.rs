@@ -17,7 +17,7 @@ use crate::{
     aspace::AddressSpace,
     loader::{pe, pe::PE},
     module::Permissions,
-    RVA, VA,
+    util, RVA, VA,
 };
 use byteorder::ByteOrder;
 
@@ -32,28 +32,32 @@ pub enum RuntimeFunctionError {
 }
 
 #[allow(dead_code)]
-enum UnwindInfoData {
-    ExceptionHandler { rva: RVA },
+pub(crate) enum UnwindInfoData {
+    None,
+    /// `rva` is the language-specific handler, such as `__C_specific_handler`,
+    /// and `data` is the address of the handler-specific data that follows it,
+    /// such as a scope table.
+    ExceptionHandler { rva: RVA, data: VA },
     ChainedUnwindInfo(RuntimeFunction),
 }
 
 #[allow(dead_code)]
-struct UnwindInfo {
-    version:               u8,
-    flags:                 u8,
-    prologue_size:         u8,
-    code_count:            u8,
-    frame_register:        u8,
-    frame_register_offset: u8,
-    unwind_codes:          Vec<u16>,
-    data:                  UnwindInfoData,
+pub(crate) struct UnwindInfo {
+    pub(crate) version:               u8,
+    pub(crate) flags:                 u8,
+    pub(crate) prologue_size:         u8,
+    pub(crate) code_count:            u8,
+    pub(crate) frame_register:        u8,
+    pub(crate) frame_register_offset: u8,
+    pub(crate) unwind_codes:          Vec<u16>,
+    pub(crate) data:                  UnwindInfoData,
 }
 
 #[allow(dead_code)]
-struct RuntimeFunction {
-    function_start:      VA,
-    function_end:        VA,
-    unwind_info_address: VA,
+pub(crate) struct RuntimeFunction {
+    pub(crate) function_start:      VA,
+    pub(crate) function_end:        VA,
+    pub(crate) unwind_info_address: VA,
 }
 
 /// Read the RUNTIME_FUNCTION structure at the given address,
@@ -107,9 +111,15 @@ fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
         .collect();
 
     // https://docs.microsoft.com/en-us/windows/win32/api/winnt/nf-winnt-rtlvirtualunwind
+    const UNW_FLAG_EHANDLER: u8 = 0x1;
+    const UNW_FLAG_UHANDLER: u8 = 0x2;
     const UNW_FLAG_CHAININFO: u8 = 0x4;
 
-    let data_address = offset + 4 + 2 * code_count as RVA;
+    // > For alignment purposes, this array always has an even number of entries,
+    // > and the final entry is potentially unused.
+    //
+    // https://docs.microsoft.com/en-us/cpp/build/exception-handling-x64?view=vs-2019#struct-unwind_info
+    let data_address = offset + 4 + 2 * util::align(code_count as u64, 2) as RVA;
     let data = if flags == UNW_FLAG_CHAININFO {
         // > If the UNW_FLAG_CHAININFO flag is set,
         // > then an unwind info structure is a secondary one,
@@ -130,10 +140,13 @@ fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
             Some(runtime_function) => UnwindInfoData::ChainedUnwindInfo(runtime_function),
             None => return Err(RuntimeFunctionError::InvalidUnwindInfo.into()),
         }
-    } else {
+    } else if flags & (UNW_FLAG_EHANDLER | UNW_FLAG_UHANDLER) > 0 {
         UnwindInfoData::ExceptionHandler {
-            rva: pe.module.address_space.read_u32(data_address)? as RVA,
+            rva:  pe.module.address_space.read_u32(data_address)? as RVA,
+            data: data_address + 4,
         }
+    } else {
+        UnwindInfoData::None
     };
 
     Ok(UnwindInfo {
@@ -148,7 +161,9 @@ fn read_unwind_info(pe: &PE, offset: VA) -> Result<UnwindInfo> {
     })
 }
 
-pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
+/// Read each RUNTIME_FUNCTION from the exception directory,
+/// along with its primary UNWIND_INFO (that is, after following any chains).
+pub(crate) fn read_pe_runtime_functions(pe: &PE) -> Result<Vec<(RuntimeFunction, UnwindInfo)>> {
     let mut ret = vec![];
 
     if !matches!(pe.module.arch, Arch::X64) {
@@ -176,10 +191,8 @@ pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
                     return Err(RuntimeFunctionError::InvalidRuntimeFunction.into());
                 }
 
-                let function = runtime_function.function_start;
-
-                debug!("pdata: found RUNTIME_FUNCTION: {:#x}", function);
-                ret.push(function);
+                debug!("pdata: found RUNTIME_FUNCTION: {:#x}", runtime_function.function_start);
+                ret.push((runtime_function, unwind_info));
             } else {
                 // just read an entry filled with zeros.
                 // assume this means we reached the end of the table.
@@ -191,6 +204,13 @@ pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
     Ok(ret)
 }
 
+pub fn find_pe_runtime_functions(pe: &PE) -> Result<Vec<VA>> {
+    Ok(read_pe_runtime_functions(pe)?
+        .into_iter()
+        .map(|(runtime_function, _)| runtime_function.function_start)
+        .collect())
+}
+
 #[cfg(test)]
 mod tests {
     use crate::rsrc::*;
@@ -0,0 +1,157 @@
+//! Demangle C++ symbol names, such as those found in import and export
+//! tables, FLIRT signatures, and RTTI metadata.
+//!
+//! Supports the MSVC (`?bar@CFoo@@QAEXH@Z`) and Itanium ABI
+//! (`_ZN3foo3barEi`) schemes, via the `msvc-demangler` and `cpp_demangle` crates.
+//!
+//! Two forms of output are supported:
+//!   - full, which includes the access, calling convention, return type, and
+//!     parameters, like `public: void __thiscall CFoo::bar(int)`, and
+//!   - short, which includes only the qualified name, like `CFoo::bar`.
+//!
+//! # Examples
+//!
+//! ```
+//! use lancelot::demangle::{demangle, Form};
+//!
+//! assert_eq!(
+//!     demangle("?bar@CFoo@@QAEXH@Z", Form::Full).unwrap(),
+//!     "public: void __thiscall CFoo::bar(int)"
+//! );
+//! assert_eq!(demangle("_ZN3foo3barEi", Form::Short).unwrap(), "foo::bar");
+//! assert_eq!(demangle("CreateFileA", Form::Full), None);
+//! ```
+use msvc_demangler::DemangleFlags;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Form {
+    /// the complete declaration, like `public: void __thiscall CFoo::bar(int)`.
+    Full,
+    /// only the qualified name, like `CFoo::bar`.
+    Short,
+}
+
+const TYPE_DESCRIPTOR_SUFFIX: &str = "::`RTTI Type Descriptor'";
+
+/// demangle the decorated type name found in an RTTI `TypeDescriptor`, like `.?AVCFoo@@`.
+///
+/// these aren't symbols, so wrap the type in the symbol of its `TypeDescriptor`,
+/// like `??_R0?AVCFoo@@@8`, and strip the trailing name of the descriptor.
+fn demangle_msvc_type_descriptor(name: &str, form: Form) -> Option<String> {
+    let symbol = format!("??_R0{}@8", &name[1..]);
+    let full = msvc_demangler::demangle(&symbol, DemangleFlags::COMPLETE).ok()?;
+    let ty = full.strip_suffix(TYPE_DESCRIPTOR_SUFFIX)?;
+
+    match form {
+        // like `class CFoo`.
+        Form::Full => Some(ty.to_string()),
+        // like `CFoo`.
+        Form::Short => Some(
+            ["class ", "struct ", "union ", "enum "]
+                .iter()
+                .find_map(|prefix| ty.strip_prefix(prefix))
+                .unwrap_or(ty)
+                .to_string(),
+        ),
+    }
+}
+
+fn demangle_msvc(name: &str, form: Form) -> Option<String> {
+    if name.starts_with(".?A") {
+        return demangle_msvc_type_descriptor(name, form);
+    }
+
+    let flags = match form {
+        Form::Full => DemangleFlags::COMPLETE,
+        Form::Short => DemangleFlags::NAME_ONLY,
+    };
+    msvc_demangler::demangle(name, flags).ok()
+}
+
+fn demangle_itanium(name: &str, form: Form) -> Option<String> {
+    let symbol = cpp_demangle::Symbol::new(name.as_bytes()).ok()?;
+    let options = match form {
+        Form::Full => cpp_demangle::DemangleOptions::new(),
+        Form::Short => cpp_demangle::DemangleOptions::new().no_params().no_return_type(),
+    };
+    symbol.demangle(&options).ok()
+}
+
+/// demangle the given symbol name, if its mangled using a supported scheme.
+/// returns `None` for names that aren't mangled, or can't be demangled.
+pub fn demangle(name: &str, form: Form) -> Option<String> {
+    if name.starts_with('?') || name.starts_with(".?") {
+        demangle_msvc(name, form)
+    } else if name.starts_with("_Z") || name.starts_with("__Z") {
+        demangle_itanium(name, form)
+    } else {
+        None
+    }
+}
+
+/// demangle the given symbol name, falling back to the name itself.
+///
+/// ```
+/// use lancelot::demangle::{demangle_or_raw, Form};
+///
+/// assert_eq!(demangle_or_raw("??_7type_info@@6B@", Form::Short), "type_info::`vftable'");
+/// assert_eq!(demangle_or_raw("CreateFileA", Form::Short), "CreateFileA");
+/// ```
+pub fn demangle_or_raw(name: &str, form: Form) -> String {
+    demangle(name, form).unwrap_or_else(|| name.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn full(name: &str) -> String {
+        demangle(name, Form::Full).unwrap()
+    }
+
+    fn short(name: &str) -> String {
+        demangle(name, Form::Short).unwrap()
+    }
+
+    #[test]
+    fn msvc() {
+        assert_eq!(full("?main@@YAHHPAPAD@Z"), "int __cdecl main(int,char * *)");
+        assert_eq!(short("?main@@YAHHPAPAD@Z"), "main");
+        assert_eq!(full("??1CFoo@@UAE@XZ"), "public: virtual __thiscall CFoo::~CFoo(void)");
+        assert_eq!(short("??1CFoo@@UAE@XZ"), "CFoo::~CFoo");
+        assert_eq!(full("??_7type_info@@6B@"), "const type_info::`vftable'");
+        assert_eq!(
+            short("?push_back@?$vector@HV?$allocator@H@std@@@std@@QAEXABH@Z"),
+            "std::vector<int,class std::allocator<int> >::push_back"
+        );
+    }
+
+    #[test]
+    fn msvc_type_descriptors() {
+        assert_eq!(full(".?AVCFoo@@"), "class CFoo");
+        assert_eq!(short(".?AVCFoo@@"), "CFoo");
+        assert_eq!(short(".?AUNode@@"), "Node");
+        assert_eq!(short(".?AVbad_alloc@std@@"), "std::bad_alloc");
+        assert_eq!(
+            short(".?AV?$basic_ios@DU?$char_traits@D@std@@@std@@"),
+            "std::basic_ios<char,struct std::char_traits<char> >"
+        );
+    }
+
+    #[test]
+    fn itanium() {
+        assert_eq!(full("_ZN3foo3barEPKcS1_"), "foo::bar(char const*, char const*)");
+        assert_eq!(short("_ZN3foo3barEPKcS1_"), "foo::bar");
+        assert_eq!(full("__Z3fooi"), "foo(int)");
+        assert_eq!(full("_Z3maxIiET_S0_S0_"), "int max<int>(int, int)");
+        assert_eq!(short("_ZNK3foo3getEv"), "foo::get");
+    }
+
+    #[test]
+    fn invalid() {
+        for name in ["foo", "?", "?foo@@YAH", ".?A", "_Z", "_Z3fo", "_ZN3foo"].iter() {
+            assert_eq!(demangle(name, Form::Full), None, "{}", name);
+            assert_eq!(demangle(name, Form::Short), None, "{}", name);
+        }
+    }
+}
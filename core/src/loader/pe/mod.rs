@@ -3,6 +3,8 @@ use log::debug;
 use thiserror::Error;
 
 pub mod imports;
+pub mod reloc;
+pub mod rich;
 pub mod rsrc;
 
 use crate::{
@@ -44,13 +46,68 @@ pub struct DataDirectory {
     pub size:    RVA,
 }
 
+/// A malformation found, and worked around, while loading a PE file.
+/// Malware routinely ships with malformed headers,
+/// so the loader maps what it can, like the Windows loader would,
+/// rather than failing.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    /// the section alignment is not a power of two, so the default was used.
+    InvalidSectionAlignment(u64),
+    /// the base address leaves no room for the image, so the default was used.
+    InvalidBaseAddress(VA),
+    /// the virtual address of the section is not aligned to the section alignment.
+    UnalignedSection(String),
+    /// the raw data of the section extends past the end of the file,
+    /// so only the available data is mapped.
+    TruncatedSection {
+        name:      String,
+        size:      u64,
+        available: u64,
+    },
+    /// the virtual size of the section extends past the end of the image,
+    /// so the section is truncated.
+    OversizedSection { name: String, size: u64 },
+    /// the section starts beyond the largest image we'll load,
+    /// so it's not mapped, and is left out of the module.
+    DistantSection { name: String, address: VA },
+    /// the second section overlaps the first in memory, and is mapped over it.
+    OverlappingSections(String, String),
+    /// the data directories, like the imports and exports, could not be parsed,
+    /// such as when they point past the end of the file.
+    InvalidDataDirectories(String),
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Anomaly::InvalidSectionAlignment(alignment) => write!(f, "invalid section alignment: {:#x}", alignment),
+            Anomaly::InvalidBaseAddress(base_address) => write!(f, "invalid base address: {:#x}", base_address),
+            Anomaly::UnalignedSection(name) => write!(f, "section not aligned: {}", name),
+            Anomaly::TruncatedSection { name, size, available } => write!(
+                f,
+                "section truncated: {}: {:#x} bytes, {:#x} available",
+                name, size, available
+            ),
+            Anomaly::OversizedSection { name, size } => write!(f, "section too large: {}: {:#x} bytes", name, size),
+            Anomaly::DistantSection { name, address } => {
+                write!(f, "section beyond the image: {}: {:#x}", name, address)
+            }
+            Anomaly::OverlappingSections(first, second) => write!(f, "sections overlap: {} and {}", first, second),
+            Anomaly::InvalidDataDirectories(e) => write!(f, "invalid data directories: {}", e),
+        }
+    }
+}
+
 /// A parsed and loaded PE file.
 /// The `buf` field contains the raw data.
 /// The `module` field contains an address space as the PE would be loaded.
 pub struct PE {
-    pub buf:    Vec<u8>,
-    pub module: Module,
-    pub header: goblin::pe::header::Header,
+    pub buf:       Vec<u8>,
+    pub module:    Module,
+    pub header:    goblin::pe::header::Header,
+    /// the malformations worked around while loading the PE.
+    pub anomalies: Vec<Anomaly>,
 }
 
 impl PE {
@@ -87,30 +144,143 @@ impl PE {
             _ => Ok(None),
         }
     }
-}
 
-fn get_pe(buf: &[u8]) -> Result<goblin::pe::PE> {
-    match goblin::Object::parse(buf)? {
-        goblin::Object::PE(pe) => {
-            if let Some(opt) = pe.header.optional_header {
-                if opt.data_directories.get_clr_runtime_header().is_some() {
-                    return Err(PEError::FormatNotSupported(".NET assembly".to_string()).into());
-                }
+    /// the file offsets of the certificate table, that is, the Authenticode signature.
+    /// unlike the other data directories, its address is a file offset,
+    /// because the table isn't mapped into memory.
+    pub fn get_certificate_table_range(&self) -> Option<std::ops::Range<u64>> {
+        let opt_header = self.header.optional_header?;
+        let directory = (*opt_header.data_directories.get_certificate_table())?;
+
+        let start = directory.virtual_address as u64;
+        let end = start + directory.size as u64;
+        if start == 0 || end > self.buf.len() as u64 {
+            return None;
+        }
+
+        Some(start..end)
+    }
+
+    /// the file offsets of the overlay, that is, the data after the sections,
+    /// which isn't mapped into memory.
+    ///
+    /// signing the file appends the certificate table to the end of the file,
+    /// and appending data to a signed file leaves it at the start of the overlay,
+    /// so in either case the certificate table is excluded.
+    pub fn get_overlay_range(&self) -> Option<std::ops::Range<u64>> {
+        let mut start = self
+            .module
+            .sections
+            .iter()
+            .map(|section| section.physical_range.end)
+            .max()
+            .unwrap_or_default();
+        let mut end = self.buf.len() as u64;
+
+        if let Some(certificate_table) = self.get_certificate_table_range() {
+            // the table is padded to a multiple of eight bytes.
+            let padded_end = std::cmp::min(util::align(certificate_table.end, 8), end);
+
+            if certificate_table.start <= start && certificate_table.end > start {
+                start = padded_end;
+            } else if certificate_table.start >= start && padded_end == end {
+                end = certificate_table.start;
             }
-            Ok(pe)
         }
-        goblin::Object::Elf(_) => Err(PEError::FormatNotSupported("elf".to_string()).into()),
-        goblin::Object::Archive(_) => Err(PEError::FormatNotSupported("archive".to_string()).into()),
-        goblin::Object::Mach(_) => Err(PEError::FormatNotSupported("macho".to_string()).into()),
-        goblin::Object::Unknown(_) => Err(PEError::FormatNotSupported("unknown".to_string()).into()),
+
+        if start < end {
+            Some(start..end)
+        } else {
+            None
+        }
+    }
+}
+
+/// goblin preallocates the export tables using the counts found in the export directory,
+/// so reject counts that cannot possibly fit within the file before handing it over.
+fn check_export_directory(buf: &[u8]) -> Result<()> {
+    let (header, sections) = match get_pe_headers(buf) {
+        Ok(headers) => headers,
+        // let goblin report the error.
+        Err(_) => return Ok(()),
+    };
+    let opt = match header.optional_header {
+        Some(opt) => opt,
+        None => return Ok(()),
+    };
+    let export_table = match opt.data_directories.get_export_table() {
+        Some(export_table) => *export_table,
+        None => return Ok(()),
+    };
+
+    let file_alignment = opt.windows_fields.file_alignment;
+    let offset = match goblin::pe::utils::find_offset(export_table.virtual_address as usize, &sections, file_alignment)
+    {
+        Some(offset) => offset,
+        None => return Ok(()),
+    };
+    let directory = match goblin::pe::export::ExportDirectoryTable::parse(buf, offset) {
+        Ok(directory) => directory,
+        Err(_) => return Ok(()),
+    };
+
+    // each export table entry is at least four bytes.
+    let max_entries = (buf.len() / 4) as u32;
+    if directory.address_table_entries > max_entries || directory.number_of_name_pointers > max_entries {
+        return Err(PEError::MalformedPEFile("invalid export directory".to_string()).into());
+    }
+
+    Ok(())
+}
+
+/// parse just the headers and section table of the PE,
+/// which is enough to load it, even when its data directories are corrupt.
+fn get_pe_headers(buf: &[u8]) -> Result<(goblin::pe::header::Header, Vec<goblin::pe::section_table::SectionTable>)> {
+    use goblin::{pe::header, Hint};
+
+    let mut magic = [0u8; 16];
+    if buf.len() < 16 {
+        return Err(PEError::FormatNotSupported("unknown".to_string()).into());
+    }
+    magic.copy_from_slice(&buf[..16]);
+
+    match goblin::peek_bytes(&magic)? {
+        Hint::PE => (),
+        Hint::Elf(_) => return Err(PEError::FormatNotSupported("elf".to_string()).into()),
+        Hint::Archive => return Err(PEError::FormatNotSupported("archive".to_string()).into()),
+        Hint::Mach(_) | Hint::MachFat(_) => return Err(PEError::FormatNotSupported("macho".to_string()).into()),
+        Hint::Unknown(_) => return Err(PEError::FormatNotSupported("unknown".to_string()).into()),
+    }
+
+    let header = header::Header::parse(buf)?;
+    if let Some(opt) = header.optional_header {
+        if opt.data_directories.get_clr_runtime_header().is_some() {
+            return Err(PEError::FormatNotSupported(".NET assembly".to_string()).into());
+        }
     }
+
+    let mut offset = header.dos_header.pe_pointer as usize
+        + header::SIZEOF_PE_MAGIC
+        + header::SIZEOF_COFF_HEADER
+        + header.coff_header.size_of_optional_header as usize;
+    let sections = header.coff_header.sections(buf, &mut offset)?;
+
+    Ok((header, sections))
+}
+
+fn get_pe(buf: &[u8]) -> Result<goblin::pe::PE> {
+    // reject other formats and .NET assemblies, like when loading.
+    get_pe_headers(buf)?;
+    check_export_directory(buf)?;
+
+    Ok(goblin::pe::PE::parse(buf)?)
 }
 
-fn load_pe_header(buf: &[u8], pe: &goblin::pe::PE, base_address: VA) -> Result<Section> {
-    let hdr_raw_size = match pe.header.optional_header {
-        Some(opt) => opt.windows_fields.size_of_headers,
+fn load_pe_header(buf: &[u8], header: &goblin::pe::header::Header, base_address: VA) -> Result<Section> {
+    let hdr_raw_size = match header.optional_header {
+        Some(opt) => opt.windows_fields.size_of_headers as usize,
         // assumption: header is at most 0x200 bytes.
-        _ => 0x200,
+        _ => std::cmp::min(0x200, buf.len()),
     };
 
     //   on disk:
@@ -130,7 +300,6 @@ fn load_pe_header(buf: &[u8], pe: &goblin::pe::PE, base_address: VA) -> Result<S
     //                     +-- raw size
     //                         no alignment
 
-    let hdr_raw_size = std::cmp::min(hdr_raw_size as usize, buf.len());
     let hdr_virt_size = util::align(hdr_raw_size as u64, 0x200);
 
     Ok(Section {
@@ -170,7 +339,12 @@ fn load_pe_section(
         .unwrap()
         .to_string();
 
-    let virtual_size = util::align(section.virtual_size as u64, section_alignment);
+    // like the Windows loader, fall back to the raw size when the virtual size is zero.
+    let virtual_size = match section.virtual_size {
+        0 => section.size_of_raw_data,
+        virtual_size => virtual_size,
+    };
+    let virtual_size = util::align(virtual_size as u64, section_alignment);
 
     let mut perms = Permissions::empty();
     if section.characteristics & IMAGE_SCN_MEM_READ > 0 {
@@ -192,7 +366,7 @@ fn load_pe_section(
     Ok(Section {
         physical_range: std::ops::Range {
             start: section.pointer_to_raw_data as u64,
-            end:   (section.pointer_to_raw_data + section.size_of_raw_data) as u64,
+            end:   section.pointer_to_raw_data as u64 + section.size_of_raw_data as u64,
         },
         virtual_range: std::ops::Range {
             start: base_address + section.virtual_address as u64,
@@ -203,73 +377,240 @@ fn load_pe_section(
     })
 }
 
+/// the largest image we'll load into memory.
+const MAX_IMAGE_SIZE: u64 = 0x4000_0000;
+
+/// the largest image we'll load from a file of the given size.
+///
+/// the headers may declare an image much larger than the file,
+/// but beyond the raw data, it's all zeros, and a tiny file shouldn't
+/// make us reserve a gigabyte of address space.
+/// so allow for generous uninitialized data, but no more.
+fn max_image_size(file_size: u64) -> u64 {
+    let size = std::cmp::max(0x400_0000, util::align(file_size, PAGE_SIZE).saturating_mul(0x40));
+    std::cmp::min(size, MAX_IMAGE_SIZE)
+}
+
+const PAGE_SIZE: u64 = 0x1000;
+
+/// clamp the raw data of the section to the file,
+/// and the virtual size of the section to the image.
+fn fixup_section(
+    section: &mut Section,
+    buf: &[u8],
+    base_address: VA,
+    section_alignment: u64,
+    image_size: u64,
+    anomalies: &mut Vec<Anomaly>,
+) {
+    let file_size = buf.len() as u64;
+    let max_image_size = max_image_size(file_size);
+    let prange = &mut section.physical_range;
+    if prange.end > file_size {
+        anomalies.push(Anomaly::TruncatedSection {
+            name:      section.name.clone(),
+            size:      prange.end - prange.start,
+            available: file_size.saturating_sub(prange.start),
+        });
+        prange.start = std::cmp::min(prange.start, file_size);
+        prange.end = file_size;
+    }
+
+    let vrange = &mut section.virtual_range;
+    if !(vrange.start - base_address).is_multiple_of(section_alignment) {
+        anomalies.push(Anomaly::UnalignedSection(section.name.clone()));
+    }
+
+    // sections may extend past the size of the image to map their raw data,
+    // which is bounded by the file size, but not otherwise.
+    let raw_end = vrange.start + util::align(prange.end - prange.start, section_alignment);
+    let max_end = std::cmp::min(
+        std::cmp::max(base_address + image_size, raw_end),
+        base_address + max_image_size,
+    );
+    if vrange.end > max_end {
+        anomalies.push(Anomaly::OversizedSection {
+            name: section.name.clone(),
+            size: vrange.end - vrange.start,
+        });
+        vrange.end = std::cmp::max(vrange.start, max_end);
+    }
+}
+
+/// write the raw data of the section into the pages that it spans.
+///
+/// sections may be unaligned or overlap, so pages may be shared:
+/// the data is written over that of any preceding sections,
+/// and the rest of each page is left as is, or zero when newly mapped.
+/// pages without any raw data are mapped as zeros without allocating them
+/// (see `PageMap::map_empty`), since the virtual size may be much larger than the file.
+fn map_section(
+    address_space: &mut RelativeAddressSpace,
+    buf: &[u8],
+    base_address: VA,
+    section: &Section,
+) -> Result<()> {
+    // the section range contains VAs,
+    // while we're writing to the RelativeAddressSpace.
+    // so shift down by `base_address`.
+    let rstart = section.virtual_range.start - base_address;
+    let rend = section.virtual_range.end - base_address;
+    if rstart == rend {
+        return Ok(());
+    }
+
+    // when psize > vsize, vsize wins, so we only read a subset of physical data.
+    // when vsize > psize, there will be NULL bytes padding the physical data.
+    let pbuf = &buf[section.physical_range.start as usize..section.physical_range.end as usize];
+    let pbuf = &pbuf[..std::cmp::min(pbuf.len(), (rend - rstart) as usize)];
+
+    let mut page = vec![0u8; PAGE_SIZE as usize];
+    for page_address in (rstart - rstart % PAGE_SIZE..rend).step_by(PAGE_SIZE as usize) {
+        let start = std::cmp::max(page_address, rstart);
+        let end = std::cmp::min(page_address + PAGE_SIZE, rstart + pbuf.len() as u64);
+        if start >= end {
+            if !address_space.map.probe(page_address) {
+                address_space.map.map_empty(page_address, PAGE_SIZE as usize)?;
+            }
+            continue;
+        }
+
+        if address_space.map.probe(page_address) {
+            address_space.map.slice_into(page_address, &mut page)?;
+        } else {
+            page.iter_mut().for_each(|b| *b = 0);
+        }
+
+        page[(start - page_address) as usize..(end - page_address) as usize]
+            .copy_from_slice(&pbuf[(start - rstart) as usize..(end - rstart) as usize]);
+
+        address_space.map.write(page_address, &page)?;
+    }
+
+    debug!(
+        "pe: address space: mapped {:#x} - {:#x} {:?}",
+        section.virtual_range.start, section.virtual_range.end, section.permissions
+    );
+
+    Ok(())
+}
+
+/// record the sections that overlap a preceding section in memory.
+fn find_overlapping_sections(sections: &[Section], anomalies: &mut Vec<Anomaly>) {
+    let mut sections: Vec<&Section> = sections
+        .iter()
+        .filter(|section| section.virtual_range.start < section.virtual_range.end)
+        .collect();
+    sections.sort_by_key(|section| section.virtual_range.start);
+
+    let mut last: Option<&Section> = None;
+    for section in sections.into_iter() {
+        if let Some(prior) = last {
+            if section.virtual_range.start < prior.virtual_range.end {
+                anomalies.push(Anomaly::OverlappingSections(prior.name.clone(), section.name.clone()));
+            }
+            if section.virtual_range.end <= prior.virtual_range.end {
+                continue;
+            }
+        }
+        last = Some(section);
+    }
+}
+
 // lots of further detail here: https://github.com/corkami/docs/blob/master/PE/PE.md
 fn load_pe(buf: &[u8]) -> Result<PE> {
-    let pe = get_pe(buf)?;
+    let (header, pe_sections) = get_pe_headers(buf)?;
+    let mut anomalies = vec![];
+
+    // the data directories are parsed on demand, see `PE::pe`,
+    // so note, but don't fail on, any that are corrupt.
+    if let Err(e) = get_pe(buf) {
+        anomalies.push(Anomaly::InvalidDataDirectories(e.to_string()));
+    }
 
-    let arch = match pe.is_64 {
+    let is_64 = match header.optional_header {
+        Some(opt) => opt.container()? == goblin::container::Container::Big,
+        None => false,
+    };
+    let arch = match is_64 {
         false => Arch::X32,
         true => Arch::X64,
     };
     debug!("pe: arch: {:?}", arch);
 
-    let (base_address, section_alignment) = match pe.header.optional_header {
+    let (mut base_address, mut section_alignment, image_size) = match header.optional_header {
         Some(opt) => (
             opt.windows_fields.image_base,
             opt.windows_fields.section_alignment as u64,
+            opt.windows_fields.size_of_image as u64,
         ),
         _ => {
             debug!("pe: base address: using default: 0x40:000");
-            (0x40_000, 0x1000)
+            // without a size of image, assume the image is no larger than the file.
+            (0x40_000, 0x1000, util::align(buf.len() as u64, PAGE_SIZE))
         }
     };
     debug!("pe: base address: {:#x}", base_address);
 
-    let mut sections = vec![load_pe_header(buf, &pe, base_address)?];
-    for section in pe.sections.iter() {
-        sections.push(load_pe_section(base_address, section_alignment as u64, section)?);
+    // the PE format requires a power of two.
+    if section_alignment < 2 || !section_alignment.is_power_of_two() {
+        anomalies.push(Anomaly::InvalidSectionAlignment(section_alignment));
+        section_alignment = 0x1000;
     }
 
-    let max_address = sections.iter().map(|sec| sec.virtual_range.end).max().unwrap();
-    let max_page_address = util::align(max_address, 0x1000) - base_address;
-    debug!("pe: address space: capacity: {:#x}", max_page_address);
+    // section addresses and sizes are u32 RVAs,
+    // so the image spans a bit more than 4GB at most.
+    if base_address.checked_add(1 << 34).is_none() {
+        anomalies.push(Anomaly::InvalidBaseAddress(base_address));
+        base_address = 0x40_000;
+    }
 
-    let mut address_space = RelativeAddressSpace::with_capacity(max_page_address);
+    let mut sections = vec![load_pe_header(buf, &header, base_address)?];
+    for section in pe_sections.iter() {
+        let section = load_pe_section(base_address, section_alignment, section)?;
+
+        // section addresses are u32 RVAs, so they may be far beyond any reasonable
+        // image, and we'd rather not allocate the address space to reach them.
+        if section.virtual_range.start >= base_address + max_image_size(buf.len() as u64) {
+            anomalies.push(Anomaly::DistantSection {
+                name:    section.name,
+                address: section.virtual_range.start,
+            });
+            continue;
+        }
 
-    for section in sections.iter() {
-        let pstart = section.physical_range.start as usize;
-        let pend = section.physical_range.end as usize;
-        let psize = pend - pstart;
-        let pbuf = &buf[pstart..pend];
-
-        // the section range contains VAs,
-        // while we're writing to the RelativeAddressSpace.
-        // so shift down by `base_address`.
-        let vstart = section.virtual_range.start;
-        let rstart = vstart - base_address;
-        let vsize = util::align(
-            section.virtual_range.end - section.virtual_range.start,
+        sections.push(section);
+    }
+
+    for section in sections.iter_mut() {
+        fixup_section(
+            section,
+            buf,
+            base_address,
             section_alignment,
+            image_size,
+            &mut anomalies,
         );
-        let vend = vstart + vsize;
-        let mut vbuf = vec![0u8; vsize as usize];
+    }
+    find_overlapping_sections(&sections, &mut anomalies);
 
-        if vsize as usize >= psize {
-            // vsize > psize, so there will be NULL bytes padding the physical data.
-            let dest = &mut vbuf[0..psize as usize];
-            dest.copy_from_slice(pbuf);
-        } else {
-            // psize > vsize, but vsize wins, so we only read a subset of physical data.
-            let src = &pbuf[0..vsize as usize];
-            vbuf.copy_from_slice(src);
-        }
+    for anomaly in anomalies.iter() {
+        debug!("pe: anomaly: {}", anomaly);
+    }
 
-        address_space.map.writezx(rstart, &vbuf)?;
+    // empty sections aren't mapped, so they don't need any room.
+    let max_address = sections
+        .iter()
+        .filter(|section| section.virtual_range.start < section.virtual_range.end)
+        .map(|section| section.virtual_range.end)
+        .max()
+        .unwrap_or(base_address);
+    let max_page_address = util::align(max_address - base_address, PAGE_SIZE);
+    debug!("pe: address space: capacity: {:#x}", max_page_address);
 
-        debug!(
-            "pe: address space: mapped {:#x} - {:#x} {:?}",
-            vstart, vend, section.permissions
-        );
+    let mut address_space = RelativeAddressSpace::with_capacity(max_page_address);
+    for section in sections.iter() {
+        map_section(&mut address_space, buf, base_address, section)?;
     }
 
     let module = Module {
@@ -282,14 +623,18 @@ fn load_pe(buf: &[u8]) -> Result<PE> {
     Ok(PE {
         buf: buf.to_vec(),
         module,
-        header: pe.header,
+        header,
+        anomalies,
     })
 }
 
 #[cfg(test)]
 mod tests {
     use anyhow::Result;
+    use byteorder::{ByteOrder, LittleEndian};
+    use proptest::prelude::*;
 
+    use super::Anomaly;
     use crate::{aspace::AddressSpace, rsrc::*};
 
     #[test]
@@ -380,4 +725,252 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn invalid_export_directory() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        let opt = pe.header.optional_header.unwrap();
+        let export_table = opt.data_directories.get_export_table().unwrap();
+        let sections = pe.pe()?.sections;
+        let offset = goblin::pe::utils::find_offset(
+            export_table.virtual_address as usize,
+            &sections,
+            opt.windows_fields.file_alignment,
+        )
+        .unwrap();
+
+        // ExportDirectoryTable.address_table_entries
+        buf[offset + 0x14..offset + 0x18].copy_from_slice(&0xFFFF_FFFFu32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(
+            pe.anomalies,
+            vec![Anomaly::InvalidDataDirectories(
+                "malformed PE file: invalid export directory".to_string()
+            )]
+        );
+        assert!(pe.pe().is_err());
+
+        Ok(())
+    }
+
+    // inputs found by fuzzing `PE::from_bytes`, see `fuzz/`.
+    #[test]
+    fn fuzz_regressions() -> Result<()> {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("fuzz");
+        d.push("regressions");
+        d.push("pe_from_bytes");
+
+        for entry in std::fs::read_dir(d)? {
+            let buf = std::fs::read(entry?.path())?;
+            let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+            assert!(!pe.anomalies.is_empty());
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn no_anomalies() -> Result<()> {
+        for &rsrc in [Rsrc::K32, Rsrc::TINY, Rsrc::NOP, Rsrc::MIMI].iter() {
+            let buf = get_buf(rsrc);
+            let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+            assert_eq!(pe.anomalies, vec![]);
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn truncated() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // truncate the file within .rdata, which spans 0x6000-0x8000 on disk.
+        buf.truncate(0x7000);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        // the imports are in .rdata.
+        assert!(matches!(pe.anomalies[0], Anomaly::InvalidDataDirectories(_)));
+        assert_eq!(
+            pe.anomalies[1..],
+            [
+                Anomaly::TruncatedSection {
+                    name:      ".rdata".to_string(),
+                    size:      0x2000,
+                    available: 0x1000,
+                },
+                Anomaly::TruncatedSection {
+                    name:      ".data".to_string(),
+                    size:      0x1000,
+                    available: 0x0,
+                },
+            ]
+        );
+
+        // the missing data is zero-filled.
+        assert_ne!(pe.module.address_space.read_u32(0x406000)?, 0x0);
+        assert_eq!(pe.module.address_space.read_u32(0x407000)?, 0x0);
+        assert_eq!(pe.module.address_space.read_u32(0x408000)?, 0x0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_section_alignment() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // IMAGE_OPTIONAL_HEADER.SectionAlignment
+        buf[0x110..0x114].copy_from_slice(&0x3u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(pe.anomalies, vec![Anomaly::InvalidSectionAlignment(0x3)]);
+        assert_eq!(0x4d, pe.module.address_space.read_u8(0x400000)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn distant_section() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // .data: IMAGE_SECTION_HEADER.VirtualAddress
+        buf[0x22C..0x230].copy_from_slice(&0xF000_0000u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(
+            pe.anomalies,
+            vec![Anomaly::DistantSection {
+                name:    ".data".to_string(),
+                address: 0xF040_0000,
+            }]
+        );
+        assert!(pe.module.sections.iter().all(|section| section.name != ".data"));
+        assert!(pe.module.address_space.read_u8(0xF040_0000).is_err());
+        assert_eq!(0x4d, pe.module.address_space.read_u8(0x400000)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn huge_image() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        // IMAGE_OPTIONAL_HEADER.SizeOfImage
+        buf[0x128..0x12C].copy_from_slice(&0xFFFF_F000u32.to_le_bytes());
+        // .data: IMAGE_SECTION_HEADER.VirtualSize
+        buf[0x228..0x22C].copy_from_slice(&0xFFFF_0000u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(
+            pe.anomalies,
+            vec![Anomaly::OversizedSection {
+                name: ".data".to_string(),
+                size: 0xFFFF_0000,
+            }]
+        );
+
+        // the image is bounded relative to the size of the file,
+        let data = pe
+            .module
+            .sections
+            .iter()
+            .find(|section| section.name == ".data")
+            .unwrap();
+        assert_eq!(
+            data.virtual_range.end,
+            0x400000 + super::max_image_size(buf.len() as u64)
+        );
+        assert_eq!(pe.module.address_space.read_u32(data.virtual_range.end - 4)?, 0x0);
+        assert!(pe.module.address_space.read_u8(data.virtual_range.end).is_err());
+
+        // and the zero pages don't take any memory.
+        assert!(pe.module.address_space.relative.map.allocated_count() <= buf.len() / 0x1000);
+
+        Ok(())
+    }
+
+    #[test]
+    fn overlay() -> Result<()> {
+        let mut buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(pe.get_certificate_table_range(), None);
+        assert_eq!(pe.get_overlay_range(), None);
+
+        buf.extend(&[0x41; 0x10]);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(pe.get_overlay_range(), Some(0x9000..0x9010));
+
+        Ok(())
+    }
+
+    #[test]
+    fn signed_overlay() -> Result<()> {
+        let mut buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(pe.get_certificate_table_range(), Some(0xAC400..0xAFEF8));
+        assert_eq!(pe.get_overlay_range(), None);
+
+        // data appended after signing.
+        buf.extend(&[0x41; 0x10]);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(pe.get_overlay_range(), Some(0xAFEF8..0xAFF08));
+
+        // data appended before signing.
+        let mut buf = get_buf(Rsrc::K32);
+        let mut certificate_table = buf.split_off(0xAC400);
+        buf.extend(&[0x41; 0x10]);
+        buf.append(&mut certificate_table);
+        // IMAGE_DIRECTORY_ENTRY_SECURITY.VirtualAddress
+        buf[0x190..0x194].copy_from_slice(&0xAC410u32.to_le_bytes());
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+        assert_eq!(pe.get_certificate_table_range(), Some(0xAC410..0xAFF08));
+        assert_eq!(pe.get_overlay_range(), Some(0xAC400..0xAC410));
+
+        Ok(())
+    }
+
+    /// the offsets of the fields in the headers of the given PE32 file that lay out the image:
+    /// the image base, alignments, and sizes from the optional header,
+    /// and the address, size, and file offset of each section.
+    fn get_layout_fields(buf: &[u8]) -> Vec<usize> {
+        let pe = LittleEndian::read_u32(&buf[0x3C..]) as usize;
+        let section_count = LittleEndian::read_u16(&buf[pe + 6..]) as usize;
+        let optional_header_size = LittleEndian::read_u16(&buf[pe + 20..]) as usize;
+
+        let opt = pe + 24;
+        // image base, section alignment, file alignment, size of image, and size of headers.
+        let mut fields = vec![opt + 28, opt + 32, opt + 36, opt + 56, opt + 60];
+        for i in 0..section_count {
+            let section = opt + optional_header_size + i * 40;
+            // virtual size, virtual address, size of raw data, and pointer to raw data.
+            fields.extend(&[section + 8, section + 12, section + 16, section + 20]);
+        }
+        fields
+    }
+
+    proptest! {
+        // corrupt headers may fail to load, but must not panic.
+        #[test]
+        fn from_bytes_corrupt_headers(patches in prop::collection::vec((0usize..0x400, any::<u8>()), 1..8)) {
+            let mut buf = get_buf(Rsrc::NOP);
+            for (offset, value) in patches.into_iter() {
+                buf[offset] = value;
+            }
+            let _ = crate::loader::pe::PE::from_bytes(&buf);
+        }
+
+        // nor may arbitrary layouts, like sections far beyond the end of the image.
+        #[test]
+        fn from_bytes_corrupt_layout(patches in prop::collection::vec((0usize..0x100, any::<u32>()), 1..4)) {
+            let mut buf = get_buf(Rsrc::NOP);
+            let fields = get_layout_fields(&buf);
+            for (index, value) in patches.into_iter() {
+                LittleEndian::write_u32(&mut buf[fields[index % fields.len()]..], value);
+            }
+
+            // and however large the image claims to be, only the raw data takes memory.
+            if let Ok(pe) = crate::loader::pe::PE::from_bytes(&buf) {
+                let file_pages = buf.len() / 0x1000 + 1;
+                let allocated = pe.module.address_space.relative.map.allocated_count();
+                prop_assert!(allocated <= pe.module.sections.len() * (file_pages + 1));
+            }
+        }
+    }
 }
@@ -0,0 +1,161 @@
+//! Parse the PE base relocation table.
+//!
+//! The table describes the locations of absolute addresses within the image
+//! that the loader must fix up when the image isn't loaded at its preferred
+//! base address. It consists of blocks, one per 4KB page, that contain:
+//!
+//!   u32       page RVA
+//!   u32       block size, including this header
+//!   u16[]     entries: 4-bit type and 12-bit offset into the page
+//!
+//! references:
+//!   - https://docs.microsoft.com/en-us/windows/win32/debug/pe-format#the-reloc-section-image-only
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+use log::debug;
+
+use crate::{
+    aspace::AddressSpace,
+    loader::pe::{IMAGE_DIRECTORY_ENTRY_BASERELOC, PE},
+    RVA, VA,
+};
+
+const IMAGE_REL_BASED_ABSOLUTE: u16 = 0;
+const IMAGE_REL_BASED_HIGH: u16 = 1;
+const IMAGE_REL_BASED_LOW: u16 = 2;
+const IMAGE_REL_BASED_HIGHLOW: u16 = 3;
+const IMAGE_REL_BASED_HIGHADJ: u16 = 4;
+const IMAGE_REL_BASED_DIR64: u16 = 10;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RelocationType {
+    /// the high 16 bits of a 32-bit address.
+    High,
+    /// the low 16 bits of a 32-bit address.
+    Low,
+    /// a 32-bit address.
+    HighLow,
+    /// the high 16 bits of a 32-bit address, adjusted by the following entry.
+    HighAdj,
+    /// a 64-bit address.
+    Dir64,
+}
+
+impl RelocationType {
+    /// the number of bytes fixed up by the relocation.
+    pub fn size(&self) -> u64 {
+        match self {
+            RelocationType::High | RelocationType::Low | RelocationType::HighAdj => 2,
+            RelocationType::HighLow => 4,
+            RelocationType::Dir64 => 8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Relocation {
+    /// the address of the bytes fixed up by the relocation.
+    pub address: VA,
+    pub typ:     RelocationType,
+}
+
+/// fetch the base relocations from the given PE, ordered by address.
+/// unknown relocation types are skipped.
+pub fn get_relocations(pe: &PE) -> Result<Vec<Relocation>> {
+    let directory = match pe.get_data_directory(IMAGE_DIRECTORY_ENTRY_BASERELOC)? {
+        Some(directory) if directory.size > 0 => directory,
+        _ => return Ok(vec![]),
+    };
+
+    let buf = pe
+        .module
+        .address_space
+        .read_bytes(directory.address, directory.size as usize)?;
+    let base_address = pe.module.address_space.base_address;
+
+    let mut relocations = vec![];
+    let mut offset = 0usize;
+    while offset + 8 <= buf.len() {
+        let page = LittleEndian::read_u32(&buf[offset..]) as RVA;
+        let block_size = LittleEndian::read_u32(&buf[offset + 4..]) as usize;
+        if block_size < 8 {
+            debug!("reloc: invalid block size: {:#x}", block_size);
+            break;
+        }
+
+        let end = std::cmp::min(offset + block_size, buf.len());
+        let mut entries = buf[offset + 8..end].chunks_exact(2).map(LittleEndian::read_u16);
+        while let Some(entry) = entries.next() {
+            let typ = match entry >> 12 {
+                // padding, to align the next block.
+                IMAGE_REL_BASED_ABSOLUTE => continue,
+                IMAGE_REL_BASED_HIGH => RelocationType::High,
+                IMAGE_REL_BASED_LOW => RelocationType::Low,
+                IMAGE_REL_BASED_HIGHLOW => RelocationType::HighLow,
+                IMAGE_REL_BASED_HIGHADJ => {
+                    // the next entry holds the low 16 bits of the adjustment.
+                    entries.next();
+                    RelocationType::HighAdj
+                }
+                IMAGE_REL_BASED_DIR64 => RelocationType::Dir64,
+                typ => {
+                    debug!("reloc: unsupported relocation type: {:#x}", typ);
+                    continue;
+                }
+            };
+
+            relocations.push(Relocation {
+                address: base_address + page + (entry & 0xFFF) as RVA,
+                typ,
+            });
+        }
+
+        offset += block_size;
+    }
+
+    relocations.sort_by_key(|reloc| reloc.address);
+
+    Ok(relocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{loader::pe::reloc::*, rsrc::*};
+    use anyhow::Result;
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let relocs = get_relocations(&pe)?;
+        assert_eq!(relocs.len(), 276);
+        assert_eq!(relocs[0].address, 0x1_8007_6008);
+        assert!(relocs.iter().all(|reloc| reloc.typ == RelocationType::Dir64));
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert_eq!(get_relocations(&pe)?.len(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let relocs = get_relocations(&pe)?;
+        assert_eq!(relocs.len(), 11457);
+        assert_eq!(relocs[0].address, 0x401002);
+        assert!(relocs.iter().all(|reloc| reloc.typ == RelocationType::HighLow));
+
+        Ok(())
+    }
+}
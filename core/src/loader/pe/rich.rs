@@ -0,0 +1,215 @@
+//! Parse the undocumented Rich header, which the Microsoft linker places
+//! between the DOS stub and the PE header.
+//!
+//! The header records the tools that produced the objects that were linked
+//! into the image, like the compiler, assembler, and linker:
+//!
+//!   u32       "DanS", XOR'd with the key
+//!   u32[3]    padding, zero when XOR'd with the key
+//!   entry[]   u32 comp.id (product ID << 16 | build number), u32 use count,
+//!             each XOR'd with the key
+//!   u32       "Rich"
+//!   u32       the key, a checksum of the DOS header and entries
+//!
+//! Since only the Microsoft toolchain emits it, its presence is a good
+//! indication that the program was built by MSVC.
+//! And the product IDs and build numbers tell which releases of Visual Studio
+//! were used (see `RichEntry::visual_studio_version`).
+//!
+//! references:
+//!   - https://www.ntcore.com/files/richsign.htm
+//!   - https://bytepointer.com/articles/the_microsoft_rich_header.htm
+//!   - https://github.com/dishather/richprint/blob/master/comp_id.txt
+use anyhow::Result;
+use byteorder::{ByteOrder, LittleEndian};
+
+use crate::loader::pe::PE;
+
+const RICH: u32 = 0x6863_6952; // "Rich"
+const DANS: u32 = 0x536E_6144; // "DanS"
+
+/// the Rich header follows the DOS header and stub.
+const DOS_HEADER_SIZE: usize = 0x40;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RichEntry {
+    /// the ID of the tool, like the C++ compiler from a specific version
+    /// of Visual Studio.
+    pub product_id: u16,
+    /// the build number of the tool, like 30729 for Visual Studio 2008 SP1.
+    pub build:      u16,
+    /// the number of objects produced by this tool.
+    pub count:      u32,
+}
+
+impl RichEntry {
+    /// guess the release of Visual Studio, like 2008, that shipped the tool.
+    /// `None` when unknown, like for the entry that counts the imported
+    /// symbols.
+    ///
+    /// each release from Visual Studio .NET 2003 through 2013 has its own range
+    /// of product IDs. the releases since 2015 share the 14.x toolset, and
+    /// so the same product IDs, but have distinct build numbers, as do the
+    /// releases before 2003, whose product IDs were reused by later tools
+    /// (like MASM 7.10).
+    pub fn visual_studio_version(&self) -> Option<u16> {
+        match self.product_id {
+            0x0000..=0x0001 => None,
+            0x0002..=0x0059 => match self.build {
+                8168 | 8447 => Some(1998),
+                9466 => Some(2002),
+                3052 | 3077 | 4035 | 6030 => Some(2003),
+                _ => None,
+            },
+            0x005A..=0x006C => Some(2003),
+            0x006D..=0x0082 => Some(2005),
+            0x0083..=0x0097 => Some(2008),
+            0x0098..=0x00C6 => Some(2010),
+            0x00C7..=0x00DA => Some(2012),
+            0x00DB..=0x00FE => Some(2013),
+            _ => match self.build {
+                0..=24999 => Some(2015),
+                25000..=27499 => Some(2017),
+                27500..=30699 => Some(2019),
+                _ => Some(2022),
+            },
+        }
+    }
+}
+
+/// fetch the entries of the Rich header from the given PE,
+/// or `None` if it doesn't have one.
+pub fn get_rich_header(pe: &PE) -> Result<Option<Vec<RichEntry>>> {
+    let pe_offset = match pe.buf.get(0x3C..0x40) {
+        Some(buf) => LittleEndian::read_u32(buf) as usize,
+        None => return Ok(None),
+    };
+    let buf = match pe.buf.get(..std::cmp::min(pe_offset, pe.buf.len())) {
+        Some(buf) if buf.len() > DOS_HEADER_SIZE => buf,
+        _ => return Ok(None),
+    };
+
+    // the header is dword aligned.
+    let dwords: Vec<u32> = buf.chunks_exact(4).map(LittleEndian::read_u32).collect();
+    let rich = match dwords
+        .iter()
+        .enumerate()
+        .skip(DOS_HEADER_SIZE / 4)
+        .find(|&(_, &v)| v == RICH)
+    {
+        Some((i, _)) if i + 1 < dwords.len() => i,
+        _ => return Ok(None),
+    };
+    let key = dwords[rich + 1];
+
+    let dans = match (DOS_HEADER_SIZE / 4..rich).rev().find(|&i| dwords[i] ^ key == DANS) {
+        Some(i) => i,
+        None => return Ok(None),
+    };
+
+    // skip the padding after "DanS".
+    let entries = match dwords.get(dans + 4..rich) {
+        Some(entries) => entries,
+        None => return Ok(None),
+    };
+
+    Ok(Some(
+        entries
+            .chunks_exact(2)
+            .map(|entry| {
+                let comp_id = entry[0] ^ key;
+                RichEntry {
+                    product_id: (comp_id >> 16) as u16,
+                    build:      comp_id as u16,
+                    count:      entry[1] ^ key,
+                }
+            })
+            .collect(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{loader::pe::rich::*, rsrc::*};
+    use anyhow::Result;
+
+    #[test]
+    fn k32() -> Result<()> {
+        let buf = get_buf(Rsrc::K32);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let entries = get_rich_header(&pe)?.unwrap();
+        assert_eq!(entries.len(), 9);
+        // Visual Studio 2017 (15.4).
+        assert_eq!(entries[0].visual_studio_version(), Some(2017));
+        assert_eq!(
+            entries[0],
+            RichEntry {
+                product_id: 257,
+                build:      25711,
+                count:      4,
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn nop() -> Result<()> {
+        let buf = get_buf(Rsrc::NOP);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let entries = get_rich_header(&pe)?.unwrap();
+        assert_eq!(entries.len(), 6);
+        assert_eq!(entries.iter().filter(|entry| entry.build == 3077).count(), 4);
+        // Visual Studio .NET 2003, including MASM 7.10, whose product ID (0xF) predates
+        // 2003.
+        assert!(entries
+            .iter()
+            .filter(|entry| entry.product_id != 1)
+            .all(|entry| entry.visual_studio_version() == Some(2003)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn tiny() -> Result<()> {
+        let buf = get_buf(Rsrc::TINY);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        assert!(get_rich_header(&pe)?.is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn mimi() -> Result<()> {
+        let buf = get_buf(Rsrc::MIMI);
+        let pe = crate::loader::pe::PE::from_bytes(&buf)?;
+
+        let entries = get_rich_header(&pe)?.unwrap();
+        assert_eq!(entries.len(), 15);
+        // the count of imported symbols.
+        assert_eq!(
+            entries[10],
+            RichEntry {
+                product_id: 1,
+                build:      0,
+                count:      620,
+            }
+        );
+        assert_eq!(entries[10].visual_studio_version(), None);
+
+        // mostly Visual Studio 2008, with a few objects from 2003, 2005, 2010, and
+        // 2012.
+        let mut versions: Vec<u16> = entries
+            .iter()
+            .filter_map(|entry| entry.visual_studio_version())
+            .collect();
+        versions.sort_unstable();
+        versions.dedup();
+        assert_eq!(versions, vec![2003, 2005, 2008, 2010, 2012]);
+
+        Ok(())
+    }
+}
@@ -8,12 +8,17 @@
 #![allow(non_camel_case_types)]
 #![allow(non_upper_case_globals)]
 
+use std::collections::HashSet;
+
 use anyhow::Result;
 use byteorder::{ByteOrder, LittleEndian};
 use log::debug;
 
 use crate::{aspace::AddressSpace, loader::pe::PE, RVA};
 
+/// the resource tree has three levels of directories: type, name, and language.
+const MAX_DIRECTORY_DEPTH: usize = 3;
+
 pub struct ResourceSectionData {
     buf: Vec<u8>,
 }
@@ -40,6 +45,68 @@ impl ResourceSectionData {
         ResourceNode::read(self, 0x0)
     }
 
+    /// collect the data entries of the resource tree along with their paths,
+    /// like `RT_VERSION/0x1/0x409`.
+    /// when the type of the entry is recognized, the path starts with its name,
+    /// otherwise, with its ID like `0x0`.
+    ///
+    /// directories nested deeper than the type, name, and language levels,
+    /// or that were already visited, such as in a cycle, are skipped.
+    pub fn data_entries(&self) -> Result<Vec<(String, ResourceDataDescriptor)>> {
+        let mut ret = vec![];
+        let mut visited: HashSet<usize> = Default::default();
+        visited.insert(0x0);
+        self.collect_data_entries("", self.root()?, 1, &mut visited, &mut ret)?;
+        Ok(ret)
+    }
+
+    fn collect_data_entries(
+        &self,
+        prefix: &str,
+        node: ResourceNode,
+        depth: usize,
+        visited: &mut HashSet<usize>,
+        ret: &mut Vec<(String, ResourceDataDescriptor)>,
+    ) -> Result<()> {
+        for (entry, child) in node.children(self)?.into_iter() {
+            let path = match entry.id(self)? {
+                NodeIdentifier::ID(id) => {
+                    if prefix.is_empty() {
+                        match ResourceDataType::from_u32(id) {
+                            Some(dt) => format!("{:?}", dt),
+                            None => format!("{:#x}", id),
+                        }
+                    } else {
+                        format!("{}/{:#x}", prefix, id)
+                    }
+                }
+                NodeIdentifier::Name(s) => {
+                    if prefix.is_empty() {
+                        s
+                    } else {
+                        format!("{}/{}", prefix, s)
+                    }
+                }
+            };
+
+            match child {
+                NodeChild::Node(node) => {
+                    let offset = entry.child_offset();
+                    if depth >= MAX_DIRECTORY_DEPTH {
+                        debug!("rsrc: {}: directory too deep at {:#x}", path, offset);
+                    } else if !visited.insert(offset) {
+                        debug!("rsrc: {}: directory already visited at {:#x}", path, offset);
+                    } else {
+                        self.collect_data_entries(&path, node, depth + 1, visited, ret)?
+                    }
+                }
+                NodeChild::Data(d) => ret.push((path, d)),
+            }
+        }
+
+        Ok(())
+    }
+
     pub fn from_pe(pe: &PE) -> Result<Option<ResourceSectionData>> {
         let opt_header = match pe.header.optional_header {
             None => return Ok(None),
@@ -122,8 +189,13 @@ impl ResourceNodeEntry {
         }
     }
 
+    /// the offset of the child directory or data entry within the section.
+    fn child_offset(&self) -> usize {
+        (self.offset & 0x7FFF_FFFF) as usize
+    }
+
     pub fn child(&self, rsrc: &ResourceSectionData) -> Result<NodeChild> {
-        let offset = (self.offset & 0x7FFF_FFFF) as usize;
+        let offset = self.child_offset();
         if self.is_branch_node() {
             Ok(NodeChild::Node(ResourceNode::read(rsrc, offset)?))
         } else {
@@ -313,3 +385,72 @@ pub enum NodeChild {
     Node(ResourceNode),
     Data(ResourceDataDescriptor),
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::Result;
+
+    /// a directory with the given (id, offset) entries.
+    fn directory(entries: &[(u32, u32)]) -> Vec<u8> {
+        let mut buf = vec![0u8; 16];
+        // IMAGE_RESOURCE_DIRECTORY.NumberOfIdEntries
+        buf[14..16].copy_from_slice(&(entries.len() as u16).to_le_bytes());
+        for &(id, offset) in entries.iter() {
+            buf.extend(&id.to_le_bytes());
+            buf.extend(&offset.to_le_bytes());
+        }
+        buf
+    }
+
+    /// a chain of directories, each with a single entry leading to the next,
+    /// and a data descriptor at the end.
+    fn chain(ids: &[u32]) -> ResourceSectionData {
+        let mut buf = vec![];
+        for (i, &id) in ids.iter().enumerate() {
+            let next = ((i + 1) * 0x18) as u32;
+            if i == ids.len() - 1 {
+                buf.extend(directory(&[(id, next)]));
+            } else {
+                buf.extend(directory(&[(id, 0x8000_0000 | next)]));
+            }
+        }
+        // IMAGE_RESOURCE_DATA_ENTRY
+        buf.extend(&0x1000u32.to_le_bytes());
+        buf.extend(&0x10u32.to_le_bytes());
+        buf.extend(&[0u8; 8]);
+        ResourceSectionData { buf }
+    }
+
+    #[test]
+    fn data_entries() -> Result<()> {
+        let rsrc = chain(&[16, 1, 0x409]);
+        let entries = rsrc.data_entries()?;
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, "RT_VERSION/0x1/0x409");
+        assert_eq!(entries[0].1.rva, 0x1000);
+        assert_eq!(entries[0].1.size, 0x10);
+
+        Ok(())
+    }
+
+    #[test]
+    fn too_deep() -> Result<()> {
+        let rsrc = chain(&[16, 1, 0x409, 0x0]);
+        assert!(rsrc.data_entries()?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn self_referencing_directory() -> Result<()> {
+        // the root directory, with an entry pointing back at the root,
+        // and one pointing at a directory that points at itself.
+        let mut buf = directory(&[(16, 0x8000_0000), (3, 0x8000_0020)]);
+        buf.extend(directory(&[(1, 0x8000_0020)]));
+        let rsrc = ResourceSectionData { buf };
+        assert!(rsrc.data_entries()?.is_empty());
+
+        Ok(())
+    }
+}
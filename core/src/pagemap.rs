@@ -1,3 +1,5 @@
+use std::sync::Arc;
+
 use anyhow::Result;
 use thiserror::Error;
 
@@ -52,9 +54,14 @@ impl<T: Default + Copy> Default for Page<T> {
 /// contiguous indices. At the moment, indices are `RVA`.
 ///
 /// Lookups should be quick, as they boil down to just a couple dereferences.
+///
+/// Pages are allocated as they're mapped, and all the pages mapped by `map_empty`
+/// share a single page of default values until they're modified,
+/// so large, sparse maps (like a section of uninitialized data) are cheap.
 #[derive(Clone)]
 pub struct PageMap<T: Default + Copy> {
-    pages: Vec<Option<Page<T>>>,
+    pages: Vec<Option<Arc<Page<T>>>>,
+    empty: Arc<Page<T>>,
 }
 
 impl<T: Default + Copy> PageMap<T> {
@@ -63,7 +70,10 @@ impl<T: Default + Copy> PageMap<T> {
         let mut pages = Vec::with_capacity(page_count);
         pages.resize_with(page_count, || None);
 
-        PageMap { pages }
+        PageMap {
+            pages,
+            empty: Default::default(),
+        }
     }
 
     pub fn from_items(items: &[T]) -> PageMap<T> {
@@ -88,7 +98,7 @@ impl<T: Default + Copy> PageMap<T> {
             return Err(PageMapError::NotMapped.into());
         }
 
-        self.pages[page(rva)] = Some(Page::new(items));
+        self.pages[page(rva)] = Some(Arc::new(Page::new(items)));
 
         Ok(())
     }
@@ -112,12 +122,51 @@ impl<T: Default + Copy> PageMap<T> {
     }
 
     /// map the default value (probably zero) at the given address for the given
-    /// size.
+    /// size, without allocating the pages until they're modified.
     ///
     /// same error conditions as `map`.
     /// see example under `probe`.
+    ///
+    /// ```
+    /// use lancelot::pagemap::PageMap;
+    ///
+    /// let mut d: PageMap<u8> = PageMap::with_capacity(0x4000_0000);
+    /// d.map_empty(0x0, 0x4000_0000).expect("failed to map");
+    /// assert_eq!(d.get(0x3FFF_FFFF), Some(0x0));
+    /// assert_eq!(d.allocated_count(), 0);
+    ///
+    /// *d.get_mut(0x1000).unwrap() = 0x1;
+    /// assert_eq!(d.get(0x1000), Some(0x1));
+    /// assert_eq!(d.get(0x2000), Some(0x0));
+    /// assert_eq!(d.allocated_count(), 1);
+    /// ```
     pub fn map_empty(&mut self, rva: RVA, size: usize) -> Result<()> {
-        self.write(rva, &vec![Default::default(); size])
+        if page_offset(rva) != 0 {
+            panic!("invalid map address");
+        }
+        if !size.is_multiple_of(PAGE_SIZE) {
+            panic!("items must be page aligned");
+        }
+        for i in 0..size / PAGE_SIZE {
+            let index = page(rva) + i;
+            if index > self.pages.len() - 1 {
+                return Err(PageMapError::NotMapped.into());
+            }
+            self.pages[index] = Some(self.empty.clone());
+        }
+        Ok(())
+    }
+
+    /// the number of mapped pages that have their own memory,
+    /// rather than sharing the page of default values (see `map_empty`).
+    pub fn allocated_count(&self) -> usize {
+        self.pages
+            .iter()
+            .filter(|page| match page {
+                Some(page) => !Arc::ptr_eq(page, &self.empty),
+                None => false,
+            })
+            .count()
     }
 
     /// map the given items at the given address, padding with the default value
@@ -224,8 +273,8 @@ impl<T: Default + Copy> PageMap<T> {
         let page = match &mut self.pages[page(rva)] {
             // page is not mapped
             None => return None,
-            // page is mapped
-            Some(page) => page,
+            // page is mapped, and may be shared, so copy it before modifying it.
+            Some(page) => Arc::make_mut(page),
         };
 
         Some(&mut page.elements[page_offset(rva)])
@@ -0,0 +1,128 @@
+//! Compare the prefix tree used by `PatternSet` against the `RegexSet`
+//! that it replaced, both to build the matcher and to match it at each offset
+//! of a buffer. Also, compare loading signatures from a cache against
+//! parsing and compiling them.
+//!
+//! run via: `cargo bench -p lancelot-flirt`
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+
+use lancelot_flirt::{
+    cache,
+    pattern_set::{Pattern, PatternSet, Symbol, WILDCARD},
+    sig, FlirtSignatureSet,
+};
+
+/// a small linear congruential generator, so the inputs are deterministic.
+struct Lcg(u32);
+
+impl Lcg {
+    fn next(&mut self) -> u8 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12345);
+        (self.0 >> 16) as u8
+    }
+}
+
+/// the patterns from the MSVC libcmt signatures, about 300 of them.
+fn get_libcmt_patterns() -> Vec<Pattern> {
+    let sigs = sig::parse(include_bytes!("../sigs/sig/libcmt_15_msvc_x86.sig")).unwrap();
+    sigs.iter().map(|sig| sig.into()).collect()
+}
+
+/// many 32-byte patterns, each with a few wildcards, like a large library.
+fn get_synthetic_patterns(count: usize) -> Vec<Pattern> {
+    let mut rng = Lcg(0x1234_5678);
+    (0..count)
+        .map(|_| {
+            Pattern(
+                (0..0x20)
+                    .map(|_| match rng.next() {
+                        0..=0x1F => WILDCARD,
+                        // a small alphabet, so that patterns share prefixes, like real code.
+                        v => Symbol::from(v % 0x10),
+                    })
+                    .collect(),
+            )
+        })
+        .collect()
+}
+
+fn get_buf() -> Vec<u8> {
+    let mut rng = Lcg(0x8765_4321);
+    (0..0x1000).map(|_| rng.next() % 0x10).collect()
+}
+
+fn build_regex_set(patterns: &[Pattern]) -> regex::bytes::RegexSet {
+    regex::bytes::RegexSet::new(patterns.iter().map(|pattern| format!("{}", pattern))).unwrap()
+}
+
+fn bench_build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+
+    for (name, patterns) in [
+        ("libcmt", get_libcmt_patterns()),
+        ("synthetic", get_synthetic_patterns(5000)),
+    ]
+    .iter()
+    {
+        group.bench_function(format!("{}/prefix tree", name), |b| {
+            b.iter(|| PatternSet::from_patterns(black_box(patterns.clone())))
+        });
+        group.bench_function(format!("{}/regex set", name), |b| {
+            b.iter(|| build_regex_set(black_box(patterns)))
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_match(c: &mut Criterion) {
+    let mut group = c.benchmark_group("match");
+    group.sample_size(10);
+    let buf = get_buf();
+
+    for (name, patterns) in [
+        ("libcmt", get_libcmt_patterns()),
+        ("synthetic", get_synthetic_patterns(5000)),
+    ]
+    .iter()
+    {
+        let pattern_set = PatternSet::from_patterns(patterns.clone());
+        group.bench_function(format!("{}/prefix tree", name), |b| {
+            b.iter(|| {
+                (0..buf.len())
+                    .map(|offset| pattern_set.r#match(black_box(&buf[offset..])).len())
+                    .sum::<usize>()
+            })
+        });
+
+        let re = build_regex_set(patterns);
+        group.bench_function(format!("{}/regex set", name), |b| {
+            b.iter(|| {
+                (0..buf.len())
+                    .map(|offset| re.matches(black_box(&buf[offset..])).iter().count())
+                    .sum::<usize>()
+            })
+        });
+    }
+
+    group.finish();
+}
+
+fn bench_load(c: &mut Criterion) {
+    let mut group = c.benchmark_group("load");
+    let buf = include_bytes!("../sigs/sig/libcmt_15_msvc_x86.sig");
+    let cached = cache::write(&FlirtSignatureSet::with_signatures(sig::parse(buf).unwrap()), &[]).unwrap();
+    // like a memory-mapped file, the cache outlives the signatures loaded from it.
+    let cached: &'static [u8] = Box::leak(cached.into_boxed_slice());
+
+    group.bench_function("libcmt/sig", |b| {
+        b.iter(|| FlirtSignatureSet::with_signatures(sig::parse(black_box(buf)).unwrap()))
+    });
+    group.bench_function("libcmt/cache", |b| b.iter(|| cache::load(black_box(cached)).unwrap()));
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_build, bench_match, bench_load);
+criterion_main!(benches);
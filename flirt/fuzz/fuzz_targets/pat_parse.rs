@@ -0,0 +1,9 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    if let Ok(buf) = std::str::from_utf8(data) {
+        let _ = lancelot_flirt::pat::parse(buf);
+        let _ = lancelot_flirt::pat::parse_lenient(buf);
+    }
+});
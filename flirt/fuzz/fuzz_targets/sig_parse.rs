@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = lancelot_flirt::sig::parse(data);
+    let _ = lancelot_flirt::sig::parse_lenient(data);
+});
@@ -0,0 +1,42 @@
+    # assemble with:
+    #
+    #     llvm-mc -triple=i686-pc-windows-msvc -filetype=obj example.asm -o example.obj
+    #     llvm-lib /out:example.lib example.obj
+    .intel_syntax noprefix
+    .text
+
+    .def _check_cookie; .scl 2; .type 32; .endef
+    .globl _check_cookie
+_check_cookie:
+    push ebp
+    mov ebp, esp
+    mov eax, dword ptr [___security_cookie]
+    xor eax, ebp
+    cmp eax, dword ptr [ebp + 8]
+    jne 1f
+    call _helper
+    pop ebp
+    ret
+1:
+    push 2
+    call _report_failure
+    add esp, 4
+    pop ebp
+    ret
+
+    .def _helper; .scl 3; .type 32; .endef
+_helper:
+    mov ecx, 0x12345678
+    imul eax, ecx
+    add eax, 0x11223344
+    call _report_failure
+    inc eax
+    ret
+
+    .section .text$mn,"xr",one_only,_fail_fast
+    .def _fail_fast; .scl 2; .type 32; .endef
+    .globl _fail_fast
+_fail_fast:
+    push 7
+    call _report_failure
+    int3
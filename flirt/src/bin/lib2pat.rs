@@ -0,0 +1,72 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+
+fn run(paths: &[&str]) -> Result<()> {
+    let mut sigs = vec![];
+    for path in paths.iter() {
+        let buf = std::fs::read(path)?;
+        sigs.extend(lancelot_flirt::coff::from_bytes(&buf)?);
+    }
+
+    print!("{}", lancelot_flirt::pat::write(&sigs)?);
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("lib2pat")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("generate a FLIRT .pat file from COFF .obj files and .lib archives")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("input")
+                .required(true)
+                .multiple(true)
+                .index(1)
+                .help("path to .obj or .lib file"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    if let Err(e) = run(&matches.values_of("input").unwrap().collect::<Vec<_>>()) {
+        println!("error: {:}", e);
+    }
+}
@@ -0,0 +1,134 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+use log::warn;
+
+fn run(pat_path: &str, sig_path: &str, exc_path: Option<&str>, library_name: &str, compress: bool) -> Result<()> {
+    let buf = std::fs::read_to_string(pat_path)?;
+    let sigs = lancelot_flirt::pat::parse(&buf)?;
+
+    // like sigmake, by default, look for the exclusions next to the output .sig file.
+    let exc_path = match exc_path {
+        Some(exc_path) => std::path::PathBuf::from(exc_path),
+        None => std::path::Path::new(sig_path).with_extension("exc"),
+    };
+    let exclusions = if exc_path.exists() {
+        lancelot_flirt::collision::Exclusions::parse(&std::fs::read_to_string(&exc_path)?)?
+    } else {
+        Default::default()
+    };
+
+    let resolution = lancelot_flirt::collision::resolve(sigs, &exclusions)?;
+    if !resolution.collisions.is_empty() {
+        std::fs::write(
+            &exc_path,
+            lancelot_flirt::collision::write_exclusions(&resolution.collisions),
+        )?;
+        warn!(
+            "{} collisions, edit {} and run again",
+            resolution.collisions.len(),
+            exc_path.display()
+        );
+        return Ok(());
+    }
+
+    let options = lancelot_flirt::sig::WriteOptions {
+        library_name: library_name.to_string(),
+        compress,
+        ..Default::default()
+    };
+    let buf = lancelot_flirt::sig::write(&resolution.sigs, &options)?;
+
+    std::fs::write(sig_path, &buf)?;
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("pat2sig")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("compile a FLIRT .pat file into a .sig file")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("name")
+                .short("n")
+                .long("name")
+                .takes_value(true)
+                .default_value("")
+                .help("library name to embed in the .sig file"),
+        )
+        .arg(
+            clap::Arg::with_name("compress")
+                .short("z")
+                .long("compress")
+                .help("compress the .sig file"),
+        )
+        .arg(
+            clap::Arg::with_name("exc")
+                .short("e")
+                .long("exc")
+                .takes_value(true)
+                .help("path to exclusions file used to resolve collisions (default: .sig path with .exc extension)"),
+        )
+        .arg(
+            clap::Arg::with_name("pat")
+                .required(true)
+                .index(1)
+                .help("path to .pat file"),
+        )
+        .arg(
+            clap::Arg::with_name("sig")
+                .required(true)
+                .index(2)
+                .help("path to output .sig file"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    if let Err(e) = run(
+        matches.value_of("pat").unwrap(),
+        matches.value_of("sig").unwrap(),
+        matches.value_of("exc"),
+        matches.value_of("name").unwrap(),
+        matches.is_present("compress"),
+    ) {
+        println!("error: {:}", e);
+    }
+}
@@ -0,0 +1,92 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+use log::info;
+
+fn run(cache_path: &str, paths: &[&str]) -> Result<()> {
+    let mut sigs = vec![];
+    let mut libraries = vec![];
+    for path in paths.iter() {
+        libraries.push(lancelot_flirt::library::LibraryInfo::from_path(path)?);
+
+        let buf = std::fs::read(path)?;
+        if path.ends_with(".pat") {
+            sigs.extend(lancelot_flirt::pat::parse(&String::from_utf8(buf)?)?);
+        } else {
+            sigs.extend(lancelot_flirt::sig::parse(&buf)?);
+        }
+    }
+
+    let count = sigs.len();
+    let sigs = lancelot_flirt::FlirtSignatureSet::with_signatures(sigs);
+    std::fs::write(cache_path, lancelot_flirt::cache::write(&sigs, &libraries)?)?;
+    info!("wrote {} signatures to {}", count, cache_path);
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("sig2cache")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("compile FLIRT .sig and .pat files into a signature cache")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("cache")
+                .required(true)
+                .index(1)
+                .help("path to output cache file"),
+        )
+        .arg(
+            clap::Arg::with_name("input")
+                .required(true)
+                .multiple(true)
+                .index(2)
+                .help("path to .sig or .pat file"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    if let Err(e) = run(
+        matches.value_of("cache").unwrap(),
+        &matches.values_of("input").unwrap().collect::<Vec<_>>(),
+    ) {
+        println!("error: {:}", e);
+    }
+}
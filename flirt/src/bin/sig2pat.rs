@@ -6,9 +6,8 @@ extern crate log;
 fn run(sig_path: &str) -> Result<()> {
     let buf = std::fs::read(sig_path)?;
 
-    for sig in lancelot_flirt::sig::parse(&buf)?.iter() {
-        println!("{:}", sig);
-    }
+    let sigs = lancelot_flirt::sig::parse(&buf)?;
+    print!("{}", lancelot_flirt::pat::write(&sigs)?);
 
     Ok(())
 }
@@ -0,0 +1,83 @@
+use anyhow::Result;
+extern crate chrono;
+extern crate clap;
+extern crate log;
+use lancelot_flirt::library::LibraryIndex;
+
+fn run(path: &str) -> Result<()> {
+    let index = LibraryIndex::from_directory(path)?;
+
+    for info in index.libraries.iter() {
+        println!("{}", info.path.display());
+        println!("  name:        {}", info.name);
+        println!("  description: {}", info.description);
+        println!("  compiler:    {}", info.compiler);
+        if let Some(arch) = info.arch {
+            println!("  arch:        {}", arch);
+        }
+        println!("  file types:  {:?}", info.file_types);
+        println!("  os types:    {:?}", info.os_types);
+        println!("  app types:   {:?}", info.app_types);
+        if info.startup {
+            println!("  startup:     true");
+        }
+        println!("  signatures:  {}", info.count);
+    }
+
+    Ok(())
+}
+
+fn main() {
+    better_panic::install();
+
+    // while the macro form of clap is more readable,
+    // it doesn't seem to allow us to use dynamically-generated values,
+    // such as the defaults pulled from env vars, etc.
+    let matches = clap::App::new("siglibs")
+        .author("Willi Ballenthin <willi.ballenthin@gmail.com>")
+        .about("index the FLIRT .sig and .pat files within a directory tree")
+        .arg(
+            clap::Arg::with_name("verbose")
+                .short("v")
+                .long("verbose")
+                .multiple(true)
+                .help("log verbose messages"),
+        )
+        .arg(
+            clap::Arg::with_name("input")
+                .required(true)
+                .index(1)
+                .help("path to directory containing .sig and .pat files"),
+        )
+        .get_matches();
+
+    let log_level = match matches.occurrences_of("verbose") {
+        0 => log::LevelFilter::Info,
+        1 => log::LevelFilter::Debug,
+        2 => log::LevelFilter::Trace,
+        _ => log::LevelFilter::Trace,
+    };
+
+    fern::Dispatch::new()
+        .format(move |out, message, record| {
+            out.finish(format_args!(
+                "{} [{:5}] {} {}",
+                chrono::Local::now().format("%Y-%m-%d %H:%M:%S"),
+                record.level(),
+                if log_level == log::LevelFilter::Trace {
+                    record.target()
+                } else {
+                    ""
+                },
+                message
+            ))
+        })
+        .level(log_level)
+        .chain(std::io::stderr())
+        .apply()
+        .expect("failed to configure logging");
+
+    if let Err(e) = run(matches.value_of("input").unwrap()) {
+        println!("error: {:}", e);
+    }
+}
@@ -0,0 +1,754 @@
+//! Serialize a compiled `FlirtSignatureSet` into a binary cache file,
+//! so that tools don't have to parse and compile many .sig/.pat files
+//! each time they start.
+//!
+//! The cache contains the metadata of the source libraries (see `library`),
+//! the byte patterns, the prefix tree used to match them (see `pattern_set`),
+//! and the signatures that share each pattern.
+//! The tree is stored as arrays of fixed-size records in the same layout used
+//! in memory, so it's matched in place, rather than recompiled or copied.
+//!
+//! Loading a cache (see `load`) takes ownership of the buffer, which may be a
+//! memory-mapped file, and makes one pass over it to validate the tree and
+//! find the offsets of the patterns and signatures, because a corrupt cache
+//! could otherwise make matching panic or never finish.
+//! The signatures that share a pattern are decoded the first time the pattern
+//! matches, so most signatures are never decoded at all.
+//!
+//! layout, all little endian:
+//!
+//!   [u8; 8]   magic: `LNCFLIRT`
+//!   u32       format version
+//!   u32       library count
+//!   library[] see below
+//!   u32       pattern count
+//!   pattern[] u16 length, u16[] symbols (0x100 is a wildcard)
+//!   tree      see `PatternSet::write_tree`
+//!   group[]   one per pattern, in order: u32 count, signature[]
+//!
+//! each library, which is informational and doesn't affect matching:
+//!
+//!   string    path, name, and description, each u16 length and UTF-8 bytes
+//!   u8        has arch, followed by u8 arch
+//!   u32       file types
+//!   u16       OS types
+//!   u16       application types
+//!   u8        startup
+//!   u8        compiler (0: msvc, 1: borland, 2: gcc, 3: watcom, 4: unknown)
+//!   u64       signature count
+//!
+//! and each signature:
+//!
+//!   u8        number of bytes covered by the CRC16
+//!   u16       CRC16
+//!   u64       function size
+//!   u16       name count
+//!   name[]    u8 type (0: public, 1: local, 2: reference), i64 offset,
+//!             u16 length, UTF-8 bytes
+//!   u8        has footer, followed by the footer, like a pattern
+//!   u16       tail byte count
+//!   tail[]    u64 offset, u8 value
+//!
+//! The format version changes whenever this layout does,
+//! and caches with an unexpected version are rejected, rather than migrated.
+//! Just rebuild them from the source signatures.
+use std::{ops::Range, sync::OnceLock};
+
+use anyhow::Result;
+use nom::{
+    bytes::complete::{tag, take},
+    multi::count,
+    number::complete::{le_i64, le_u16, le_u32, le_u64, le_u8},
+    IResult,
+};
+use thiserror::Error;
+
+use super::{
+    library::{Compiler, LibraryInfo},
+    pattern_set::{Pattern, Symbol, TreeRef, WILDCARD},
+    sig::{AppTypes, FileTypes, OsTypes},
+    ByteSignature, FlirtSignature, FlirtSignatureSet, Name, SigElement, Symbol as NameSymbol, TailByte,
+};
+
+#[derive(Debug, Error)]
+pub enum CacheError {
+    #[error("The file is not a signature cache")]
+    NotCache,
+    #[error("The signature cache version is not supported: {0}")]
+    UnsupportedVersion(u32),
+    #[error("The signature cache is corrupt")]
+    CorruptCache,
+    #[error("The signature cannot be written to the cache: {0}")]
+    UnsupportedSignature(String),
+}
+
+const MAGIC: &[u8] = b"LNCFLIRT";
+const VERSION: u32 = 2;
+
+/// the contents of a signature cache.
+pub struct Cache {
+    /// the metadata of the files from which the signatures were compiled.
+    pub libraries: Vec<LibraryInfo>,
+    pub sigs:      FlirtSignatureSet,
+}
+
+/// is the given buffer (probably) a signature cache?
+pub fn is_cache(buf: &[u8]) -> bool {
+    buf.starts_with(MAGIC)
+}
+
+fn write_string(out: &mut Vec<u8>, s: &str) -> Result<()> {
+    let buf = s.as_bytes();
+    if buf.len() > 0xFFFF {
+        return Err(CacheError::UnsupportedSignature(format!("string too long: {}", s)).into());
+    }
+    out.extend(&(buf.len() as u16).to_le_bytes());
+    out.extend(buf);
+    Ok(())
+}
+
+fn write_symbols(out: &mut Vec<u8>, symbols: &[Symbol]) -> Result<()> {
+    if symbols.len() > 0xFFFF {
+        return Err(CacheError::UnsupportedSignature(String::from("pattern too long")).into());
+    }
+    out.extend(&(symbols.len() as u16).to_le_bytes());
+    for symbol in symbols.iter() {
+        out.extend(&symbol.0.to_le_bytes());
+    }
+    Ok(())
+}
+
+fn write_library(out: &mut Vec<u8>, library: &LibraryInfo) -> Result<()> {
+    write_string(out, &library.path.to_string_lossy())?;
+    write_string(out, &library.name)?;
+    write_string(out, &library.description)?;
+
+    match library.arch {
+        Some(arch) => {
+            out.push(1);
+            out.push(arch);
+        }
+        None => out.push(0),
+    }
+    out.extend(&library.file_types.bits().to_le_bytes());
+    out.extend(&library.os_types.bits().to_le_bytes());
+    out.extend(&library.app_types.bits().to_le_bytes());
+    out.push(library.startup as u8);
+    out.push(match library.compiler {
+        Compiler::Msvc => 0,
+        Compiler::Borland => 1,
+        Compiler::Gcc => 2,
+        Compiler::Watcom => 3,
+        Compiler::Unknown => 4,
+    });
+    out.extend(&(library.count as u64).to_le_bytes());
+
+    Ok(())
+}
+
+fn write_signature(out: &mut Vec<u8>, sig: &FlirtSignature) -> Result<()> {
+    out.push(sig.size_of_bytes_crc16);
+    out.extend(&sig.crc16.to_le_bytes());
+    out.extend(&sig.size_of_function.to_le_bytes());
+
+    if sig.names.len() > 0xFFFF {
+        return Err(CacheError::UnsupportedSignature(format!("too many names: {}", sig)).into());
+    }
+    out.extend(&(sig.names.len() as u16).to_le_bytes());
+    for symbol in sig.names.iter() {
+        let (typ, name) = match symbol {
+            NameSymbol::Public(name) => (0u8, name),
+            NameSymbol::Local(name) => (1u8, name),
+            NameSymbol::Reference(name) => (2u8, name),
+        };
+        out.push(typ);
+        out.extend(&name.offset.to_le_bytes());
+        write_string(out, &name.name)?;
+    }
+
+    match &sig.footer {
+        Some(footer) => {
+            out.push(1);
+            let symbols: Vec<Symbol> = footer
+                .0
+                .iter()
+                .map(|elem| match elem {
+                    SigElement::Wildcard => WILDCARD,
+                    SigElement::Byte(v) => Symbol::from(*v),
+                })
+                .collect();
+            write_symbols(out, &symbols)?;
+        }
+        None => out.push(0),
+    }
+
+    if sig.tail_bytes.len() > 0xFFFF {
+        return Err(CacheError::UnsupportedSignature(format!("too many tail bytes: {}", sig)).into());
+    }
+    out.extend(&(sig.tail_bytes.len() as u16).to_le_bytes());
+    for tail_byte in sig.tail_bytes.iter() {
+        out.extend(&tail_byte.offset.to_le_bytes());
+        out.push(tail_byte.value);
+    }
+
+    Ok(())
+}
+
+/// serialize the given compiled signatures into a cache,
+/// along with the metadata of the libraries from which they were compiled.
+///
+/// ```
+/// use lancelot_flirt::{cache, library::LibraryInfo, FlirtSignatureSet};
+/// let library = LibraryInfo::from_path("sigs/sig/libcmt_15_msvc_x86.sig").unwrap();
+/// let sigs = library.load().unwrap();
+///
+/// let buf = cache::write(&sigs, &[library]).unwrap();
+/// let cached = cache::parse(&buf).unwrap();
+/// assert_eq!(cached.libraries[0].name, "libcmt_15_msvc_x86");
+/// assert_eq!(cached.libraries[0].count, 352);
+/// assert_eq!(cache::write(&cached.sigs, &cached.libraries).unwrap(), buf);
+/// ```
+pub fn write(sigs: &FlirtSignatureSet, libraries: &[LibraryInfo]) -> Result<Vec<u8>> {
+    let mut out = vec![];
+    out.extend(MAGIC);
+    out.extend(&VERSION.to_le_bytes());
+
+    out.extend(&(libraries.len() as u32).to_le_bytes());
+    for library in libraries.iter() {
+        write_library(&mut out, library)?;
+    }
+
+    let groups = sigs.groups();
+    out.extend(&(groups.len() as u32).to_le_bytes());
+    for (pattern, _) in groups.iter() {
+        write_symbols(&mut out, &pattern.0)?;
+    }
+
+    sigs.write_tree(&mut out);
+
+    for (_, group) in groups.iter() {
+        out.extend(&(group.len() as u32).to_le_bytes());
+        for sig in group.iter() {
+            write_signature(&mut out, sig)?;
+        }
+    }
+
+    Ok(out)
+}
+
+fn symbol(input: &[u8]) -> IResult<&[u8], Symbol> {
+    let (input, v) = le_u16(input)?;
+    if v > WILDCARD.0 {
+        return Err(nom::Err::Error((input, nom::error::ErrorKind::Verify)));
+    }
+    Ok((input, Symbol(v)))
+}
+
+fn pattern(input: &[u8]) -> IResult<&[u8], Pattern> {
+    let (input, length) = le_u16(input)?;
+    let (input, symbols) = count(symbol, length as usize)(input)?;
+    Ok((input, Pattern(symbols)))
+}
+
+fn byte_signature(pattern: &Pattern) -> ByteSignature {
+    ByteSignature(
+        pattern
+            .0
+            .iter()
+            .map(|&symbol| {
+                if symbol == WILDCARD {
+                    SigElement::Wildcard
+                } else {
+                    SigElement::Byte(symbol.0 as u8)
+                }
+            })
+            .collect(),
+    )
+}
+
+/// parse a pattern in place, validating its symbols without decoding them.
+fn skip_pattern(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, length) = le_u16(input)?;
+    let (input, buf) = take(length as usize * 2)(input)?;
+    if buf
+        .chunks_exact(2)
+        .any(|v| u16::from_le_bytes([v[0], v[1]]) > WILDCARD.0)
+    {
+        return Err(nom::Err::Error((input, nom::error::ErrorKind::Verify)));
+    }
+    Ok((input, ()))
+}
+
+fn str_ref(input: &[u8]) -> IResult<&[u8], &str> {
+    let (input, length) = le_u16(input)?;
+    let (input, buf) = take(length)(input)?;
+    match std::str::from_utf8(buf) {
+        Ok(s) => Ok((input, s)),
+        Err(_) => Err(nom::Err::Error((input, nom::error::ErrorKind::Verify))),
+    }
+}
+
+fn string(input: &[u8]) -> IResult<&[u8], String> {
+    let (input, s) = str_ref(input)?;
+    Ok((input, s.to_string()))
+}
+
+fn name_type(input: &[u8]) -> IResult<&[u8], u8> {
+    let (input, typ) = le_u8(input)?;
+    if typ > 2 {
+        return Err(nom::Err::Error((input, nom::error::ErrorKind::Verify)));
+    }
+    Ok((input, typ))
+}
+
+fn skip_name(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, _) = name_type(input)?;
+    let (input, _) = le_i64(input)?;
+    let (input, _) = str_ref(input)?;
+    Ok((input, ()))
+}
+
+fn name(input: &[u8]) -> IResult<&[u8], NameSymbol> {
+    let (input, typ) = name_type(input)?;
+    let (input, offset) = le_i64(input)?;
+    let (input, name) = string(input)?;
+    let name = Name { offset, name };
+    let symbol = match typ {
+        0 => NameSymbol::Public(name),
+        1 => NameSymbol::Local(name),
+        _ => NameSymbol::Reference(name),
+    };
+    Ok((input, symbol))
+}
+
+fn library(input: &[u8]) -> IResult<&[u8], LibraryInfo> {
+    let (input, path) = string(input)?;
+    let (input, name) = string(input)?;
+    let (input, description) = string(input)?;
+
+    let (input, has_arch) = le_u8(input)?;
+    let (input, arch) = if has_arch != 0 {
+        let (input, arch) = le_u8(input)?;
+        (input, Some(arch))
+    } else {
+        (input, None)
+    };
+
+    let (input, file_types) = le_u32(input)?;
+    let (input, os_types) = le_u16(input)?;
+    let (input, app_types) = le_u16(input)?;
+    let (input, startup) = le_u8(input)?;
+    let (input, compiler) = le_u8(input)?;
+    let compiler = match compiler {
+        0 => Compiler::Msvc,
+        1 => Compiler::Borland,
+        2 => Compiler::Gcc,
+        3 => Compiler::Watcom,
+        4 => Compiler::Unknown,
+        _ => return Err(nom::Err::Error((input, nom::error::ErrorKind::Verify))),
+    };
+    let (input, count) = le_u64(input)?;
+
+    Ok((
+        input,
+        LibraryInfo {
+            path: path.into(),
+            name,
+            description,
+            arch,
+            file_types: FileTypes::from_bits_truncate(file_types),
+            os_types: OsTypes::from_bits_truncate(os_types),
+            app_types: AppTypes::from_bits_truncate(app_types),
+            startup: startup != 0,
+            compiler,
+            count: count as usize,
+        },
+    ))
+}
+
+fn tail_byte(input: &[u8]) -> IResult<&[u8], TailByte> {
+    let (input, offset) = le_u64(input)?;
+    let (input, value) = le_u8(input)?;
+    Ok((input, TailByte { offset, value }))
+}
+
+/// parse a signature in place, validating everything that `signature` would decode.
+fn skip_signature(input: &[u8]) -> IResult<&[u8], ()> {
+    let (input, _) = take(1 + 2 + 8usize)(input)?;
+
+    let (mut input, name_count) = le_u16(input)?;
+    for _ in 0..name_count {
+        input = skip_name(input)?.0;
+    }
+
+    let (input, has_footer) = le_u8(input)?;
+    let (input, _) = if has_footer != 0 {
+        skip_pattern(input)?
+    } else {
+        (input, ())
+    };
+
+    let (input, tail_byte_count) = le_u16(input)?;
+    let (input, _) = take(tail_byte_count as usize * (8 + 1))(input)?;
+
+    Ok((input, ()))
+}
+
+fn signature<'a>(input: &'a [u8], pattern: &Pattern) -> IResult<&'a [u8], FlirtSignature> {
+    let (input, size_of_bytes_crc16) = le_u8(input)?;
+    let (input, crc16) = le_u16(input)?;
+    let (input, size_of_function) = le_u64(input)?;
+
+    let (input, name_count) = le_u16(input)?;
+    let (input, names) = count(name, name_count as usize)(input)?;
+
+    let (input, has_footer) = le_u8(input)?;
+    let (input, footer) = if has_footer != 0 {
+        let (input, footer) = self::pattern(input)?;
+        (input, Some(byte_signature(&footer)))
+    } else {
+        (input, None)
+    };
+
+    let (input, tail_byte_count) = le_u16(input)?;
+    let (input, tail_bytes) = count(tail_byte, tail_byte_count as usize)(input)?;
+
+    Ok((
+        input,
+        FlirtSignature {
+            byte_sig: byte_signature(pattern),
+            size_of_bytes_crc16,
+            crc16,
+            size_of_function,
+            names,
+            footer,
+            tail_bytes,
+        },
+    ))
+}
+
+/// the signatures of a cache, matched in place within the buffer from which it was loaded.
+pub(crate) struct CachedSignatures {
+    buf:      Box<dyn AsRef<[u8]> + Send + Sync>,
+    /// the offset of each pattern.
+    patterns: Vec<usize>,
+    /// the records of the prefix tree.
+    tree:     Range<usize>,
+    /// the offset of the signatures that share each pattern, and their count.
+    groups:   Vec<(usize, u32)>,
+    /// the signatures that share each pattern, decoded the first time the pattern matches.
+    decoded:  Vec<OnceLock<Vec<FlirtSignature>>>,
+}
+
+impl CachedSignatures {
+    fn buf(&self) -> &[u8] {
+        (*self.buf).as_ref()
+    }
+
+    /// the number of patterns.
+    pub(crate) fn len(&self) -> usize {
+        self.patterns.len()
+    }
+
+    pub(crate) fn pattern(&self, index: usize) -> Pattern {
+        // the cache was validated when loaded, so it can't fail to parse here.
+        pattern(&self.buf()[self.patterns[index]..]).expect("valid cache").1
+    }
+
+    pub(crate) fn tree(&self) -> TreeRef<'_> {
+        TreeRef::from_valid(self.tree_bytes())
+    }
+
+    pub(crate) fn tree_bytes(&self) -> &[u8] {
+        &self.buf()[self.tree.clone()]
+    }
+
+    /// the signatures that share the pattern with the given index.
+    pub(crate) fn group(&self, index: usize) -> &[FlirtSignature] {
+        self.decoded[index].get_or_init(|| {
+            let pattern = self.pattern(index);
+            let (offset, count) = self.groups[index];
+            let mut input = &self.buf()[offset..];
+            (0..count)
+                .map(|_| {
+                    let (input_, sig) = signature(input, &pattern).expect("valid cache");
+                    input = input_;
+                    sig
+                })
+                .collect()
+        })
+    }
+
+    /// the number of patterns whose signatures have been decoded.
+    #[cfg(test)]
+    fn decoded_count(&self) -> usize {
+        self.decoded.iter().filter(|group| group.get().is_some()).count()
+    }
+}
+
+/// the libraries, and the offsets of the patterns, tree, and signatures, within a cache.
+struct Index {
+    libraries: Vec<LibraryInfo>,
+    patterns:  Vec<usize>,
+    tree:      Range<usize>,
+    groups:    Vec<(usize, u32)>,
+}
+
+fn index(buf: &[u8]) -> IResult<&[u8], Option<Index>> {
+    let offset = |input: &[u8]| buf.len() - input.len();
+
+    let (input, _) = tag(MAGIC)(buf)?;
+    let (input, _) = le_u32(input)?;
+
+    let (mut input, library_count) = le_u32(input)?;
+    // don't trust the count to preallocate the libraries.
+    let mut libraries = vec![];
+    for _ in 0..library_count {
+        let (input_, library) = library(input)?;
+        input = input_;
+        libraries.push(library);
+    }
+
+    let (mut input, pattern_count) = le_u32(input)?;
+    // don't trust the count to preallocate the patterns.
+    let mut patterns = vec![];
+    for _ in 0..pattern_count {
+        patterns.push(offset(input));
+        input = skip_pattern(input)?.0;
+    }
+
+    let tree_start = offset(input);
+    let mut input = match TreeRef::read(input, patterns.len())? {
+        (input, Some(_)) => input,
+        (input, None) => return Ok((input, None)),
+    };
+    let tree = tree_start..offset(input);
+
+    let mut groups = vec![];
+    for _ in 0..pattern_count {
+        let (input_, sig_count) = le_u32(input)?;
+        input = input_;
+        groups.push((offset(input), sig_count));
+
+        for _ in 0..sig_count {
+            input = skip_signature(input)?.0;
+        }
+    }
+
+    Ok((
+        input,
+        Some(Index {
+            libraries,
+            patterns,
+            tree,
+            groups,
+        }),
+    ))
+}
+
+/// load the compiled signatures, and the metadata of their libraries, from the given cache,
+/// taking ownership of the buffer, such as a `Vec<u8>` or a memory-mapped file.
+///
+/// the tree and signatures are validated once, and then matched in place,
+/// so a corrupt cache is an error rather than a panic or a hang while matching.
+///
+/// ```
+/// use lancelot_flirt::{cache, library::LibraryInfo};
+/// let library = LibraryInfo::from_path("sigs/sig/libcmt_15_msvc_x86.sig").unwrap();
+/// let buf = cache::write(&library.load().unwrap(), &[library]).unwrap();
+///
+/// let cached = cache::load(buf.clone()).unwrap();
+/// assert_eq!(cached.libraries[0].name, "libcmt_15_msvc_x86");
+/// assert_eq!(cache::write(&cached.sigs, &cached.libraries).unwrap(), buf);
+/// ```
+pub fn load<B>(buf: B) -> Result<Cache>
+where
+    B: AsRef<[u8]> + Send + Sync + 'static,
+{
+    let index = {
+        let buf = buf.as_ref();
+        if !is_cache(buf) {
+            return Err(CacheError::NotCache.into());
+        }
+
+        match le_u32::<(&[u8], nom::error::ErrorKind)>(&buf[MAGIC.len()..]) {
+            Ok((_, VERSION)) => {}
+            Ok((_, version)) => return Err(CacheError::UnsupportedVersion(version).into()),
+            Err(_) => return Err(CacheError::CorruptCache.into()),
+        }
+
+        match index(buf) {
+            Ok((_, Some(index))) => index,
+            _ => return Err(CacheError::CorruptCache.into()),
+        }
+    };
+
+    let decoded = index.patterns.iter().map(|_| OnceLock::new()).collect();
+    Ok(Cache {
+        libraries: index.libraries,
+        sigs:      FlirtSignatureSet::from_cache(CachedSignatures {
+            buf: Box::new(buf),
+            patterns: index.patterns,
+            tree: index.tree,
+            groups: index.groups,
+            decoded,
+        }),
+    })
+}
+
+/// like `load`, but copies the given buffer, for callers that don't own it.
+pub fn parse(buf: &[u8]) -> Result<Cache> {
+    load(buf.to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // __EH_prolog3_catch_align, from apds.dll / 4FD932C41DF96D019DC265E26E94B81B
+    const EH_PROLOG3_CATCH_ALIGN: [u8; 103] = [
+        0x51, 0x8B, 0x4C, 0x24, 0x0C, 0x89, 0x5C, 0x24, 0x0C, 0x8D, 0x5C, 0x24, 0x0C, 0x50, 0x8D, 0x44, 0x24, 0x08,
+        0xF7, 0xD9, 0x23, 0xC1, 0x8D, 0x60, 0xF8, 0x8B, 0x43, 0xF0, 0x89, 0x04, 0x24, 0x8B, 0x43, 0xF8, 0x50, 0x8B,
+        0x43, 0xFC, 0x8B, 0x4B, 0xF4, 0x89, 0x6C, 0x24, 0x0C, 0x8D, 0x6C, 0x24, 0x0C, 0xC7, 0x44, 0x24, 0x08, 0xFF,
+        0xFF, 0xFF, 0xFF, 0x51, 0x53, 0x2B, 0xE0, 0x56, 0x57, 0xA1, 0xD4, 0xAD, 0x19, 0x01, 0x33, 0xC5, 0x50, 0x89,
+        0x65, 0xF0, 0x8B, 0x43, 0x04, 0x89, 0x45, 0x04, 0xFF, 0x75, 0xF4, 0x64, 0xA1, 0x00, 0x00, 0x00, 0x00, 0x89,
+        0x45, 0xF4, 0x8D, 0x45, 0xF4, 0x64, 0xA3, 0x00, 0x00, 0x00, 0x00, 0xF2, 0xC3,
+    ];
+
+    fn get_sigs() -> Result<FlirtSignatureSet> {
+        let buf = include_bytes!("../sigs/sig/libcmt_15_msvc_x86.sig");
+        Ok(FlirtSignatureSet::with_signatures(crate::sig::parse(buf)?))
+    }
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let pat_buf = "\
+518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 20 6562 0067 :0000 __EH_prolog3_catch_align :0010@ _local ^0040 ___security_cookie ........33C5508965F08B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3 (0050: 04)
+---
+";
+        let sigs = FlirtSignatureSet::with_signatures(crate::pat::parse(pat_buf)?);
+        let cached = parse(&write(&sigs, &[])?)?;
+        assert!(cached.libraries.is_empty());
+
+        let groups = cached.sigs.groups();
+        assert_eq!(groups.len(), 1);
+        assert_eq!(crate::pat::write(groups[0].1)?, pat_buf);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_libraries() -> Result<()> {
+        let library = crate::library::LibraryInfo::from_path(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/sigs/sig/libcmt_15_msvc_x86.sig"
+        ))?;
+        let cached = parse(&write(&library.load()?, std::slice::from_ref(&library))?)?;
+
+        assert_eq!(cached.libraries.len(), 1);
+        let cached = &cached.libraries[0];
+        assert_eq!(cached.path, library.path);
+        assert_eq!(cached.name, "libcmt_15_msvc_x86");
+        assert_eq!(cached.description, library.description);
+        assert_eq!(cached.arch, Some(crate::sig::ARCH_X86));
+        assert_eq!(cached.file_types, library.file_types);
+        assert_eq!(cached.os_types, library.os_types);
+        assert_eq!(cached.app_types, library.app_types);
+        assert_eq!(cached.startup, library.startup);
+        assert_eq!(cached.compiler, crate::library::Compiler::Msvc);
+        assert_eq!(cached.count, 352);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match() -> Result<()> {
+        let sigs = get_sigs()?;
+        let cached = parse(&write(&sigs, &[])?)?.sigs;
+
+        let buf = EH_PROLOG3_CATCH_ALIGN;
+
+        let expected: Vec<&str> = sigs.r#match(&buf).iter().filter_map(|sig| sig.get_name()).collect();
+        let found: Vec<&str> = cached.r#match(&buf).iter().filter_map(|sig| sig.get_name()).collect();
+        assert_eq!(found, vec!["__EH_prolog3_catch_align"]);
+        assert_eq!(found, expected);
+
+        let scanned: Vec<(usize, &str)> = cached
+            .scan(&buf, crate::Overlap::All)
+            .iter()
+            .filter_map(|m| m.sig.get_name().map(|name| (m.offset, name)))
+            .collect();
+        assert_eq!(scanned, vec![(0, "__EH_prolog3_catch_align")]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_lazy() -> Result<()> {
+        let buf = write(&get_sigs()?, &[])?;
+        let cached = load(buf)?.sigs;
+        let decoded = || match &cached.0 {
+            crate::Signatures::Cached(cached) => cached.decoded_count(),
+            _ => unreachable!(),
+        };
+
+        // nothing is decoded up front,
+        assert_eq!(decoded(), 0);
+
+        // then only the signatures of the patterns that match,
+        assert_eq!(cached.r#match(&EH_PROLOG3_CATCH_ALIGN).len(), 1);
+        let count = decoded();
+        assert!(count > 0 && count < 8);
+
+        // and only once.
+        assert_eq!(cached.r#match(&EH_PROLOG3_CATCH_ALIGN).len(), 1);
+        assert_eq!(decoded(), count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid() -> Result<()> {
+        let buf = write(&get_sigs()?, &[])?;
+
+        assert!(parse(b"IDASGN").is_err());
+
+        let mut future = buf.clone();
+        future[8] = 0xFF;
+        assert!(parse(&future).is_err());
+
+        // truncated caches are errors, not panics.
+        for &size in [12, 16, 0x100, 0x1000, buf.len() - 1].iter() {
+            assert!(parse(&buf[..size]).is_err());
+        }
+
+        // as are caches with out-of-range indices in the tree.
+        let sigs = FlirtSignatureSet::with_signatures(crate::pat::parse(
+            "AABB............................................................ 00 0000 0020 :0000 _foo\n---\n",
+        )?);
+        let buf = write(&sigs, &[])?;
+        assert!(parse(&buf).is_ok());
+        // magic, version, library count, pattern count, pattern (length and 32 symbols), and node count.
+        let nodes = 8 + 4 + 4 + 4 + 2 + 2 * 0x20 + 4;
+
+        // the number of edges of the root node.
+        let mut corrupt = buf.clone();
+        corrupt[nodes + 4..nodes + 8].copy_from_slice(&0xFFFFu32.to_le_bytes());
+        assert!(parse(&corrupt).is_err());
+
+        // and caches whose nodes don't form a tree, which could make matching take forever.
+        // the wildcard transition of the node after `AA BB` points back to itself,
+        let wildcard = nodes + 2 * 20 + 8;
+        assert_eq!(&buf[wildcard..wildcard + 4], &3u32.to_le_bytes());
+        let mut corrupt = buf.clone();
+        corrupt[wildcard..wildcard + 4].copy_from_slice(&2u32.to_le_bytes());
+        assert!(parse(&corrupt).is_err());
+
+        // or skips ahead, so that a node has two parents.
+        let mut corrupt = buf.clone();
+        corrupt[wildcard..wildcard + 4].copy_from_slice(&4u32.to_le_bytes());
+        assert!(parse(&corrupt).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,310 @@
+//! Generate FLIRT signatures from COFF object files and .lib archives,
+//! like the `pcf` tool from the FLAIR SDK.
+//!
+//! Each code section with a public symbol becomes a module:
+//!
+//!   - the first 32 bytes form the pattern,
+//!   - up to the next 255 bytes, until the first relocation, are checksummed,
+//!   - the remaining bytes form the footer,
+//!
+//! and bytes that are fixed up by COFF relocations are wildcards.
+//! Symbols defined in the section become public and local names,
+//! and relocations against other symbols become referenced names.
+//!
+//! With function-level linking (`/Gy`), each function is placed into its own
+//! COMDAT section, so there is one module per function.
+//!
+//! references:
+//!   - https://hex-rays.com/products/ida/tech/flirt/in_depth/
+//!   - https://docs.microsoft.com/en-us/windows/win32/debug/pe-format#coff-relocations-object-only
+use anyhow::Result;
+use goblin::pe::{
+    header::{COFF_MACHINE_X86, COFF_MACHINE_X86_64},
+    relocation::*,
+    section_table::{SectionTable, IMAGE_SCN_CNT_CODE, IMAGE_SCN_MEM_EXECUTE},
+    symbol::{IMAGE_SYM_CLASS_EXTERNAL, IMAGE_SYM_CLASS_LABEL, IMAGE_SYM_CLASS_STATIC, IMAGE_SYM_CLASS_WEAK_EXTERNAL},
+    Coff,
+};
+use log::{debug, warn};
+use thiserror::Error;
+
+use super::{FlirtSignature, Name, Symbol};
+
+#[derive(Debug, Error)]
+pub enum CoffError {
+    #[error("The COFF machine type is not supported: {0:#x}")]
+    UnsupportedMachine(u16),
+    #[error("The COFF relocation type is not supported: {0:#x}")]
+    UnsupportedRelocation(u16),
+}
+
+/// FLIRT signatures can describe modules up to 0x8000 bytes long.
+const MAX_MODULE_SIZE: usize = 0x8000;
+
+const AR_MAGIC: &[u8] = b"!<arch>\n";
+
+/// the number of bytes fixed up by a relocation of the given type.
+fn get_relocation_size(machine: u16, typ: u16) -> Result<usize> {
+    let size = match machine {
+        COFF_MACHINE_X86 => match typ {
+            IMAGE_REL_I386_ABSOLUTE => 0,
+            IMAGE_REL_I386_SECREL7 => 1,
+            IMAGE_REL_I386_DIR16 | IMAGE_REL_I386_REL16 | IMAGE_REL_I386_SEG12 | IMAGE_REL_I386_SECTION => 2,
+            IMAGE_REL_I386_DIR32
+            | IMAGE_REL_I386_DIR32NB
+            | IMAGE_REL_I386_SECREL
+            | IMAGE_REL_I386_TOKEN
+            | IMAGE_REL_I386_REL32 => 4,
+            _ => return Err(CoffError::UnsupportedRelocation(typ).into()),
+        },
+        COFF_MACHINE_X86_64 => match typ {
+            IMAGE_REL_AMD64_ABSOLUTE | IMAGE_REL_AMD64_PAIR => 0,
+            IMAGE_REL_AMD64_SECREL7 => 1,
+            IMAGE_REL_AMD64_SECTION => 2,
+            IMAGE_REL_AMD64_ADDR32
+            | IMAGE_REL_AMD64_ADDR32NB
+            | IMAGE_REL_AMD64_REL32
+            | IMAGE_REL_AMD64_REL32_1
+            | IMAGE_REL_AMD64_REL32_2
+            | IMAGE_REL_AMD64_REL32_3
+            | IMAGE_REL_AMD64_REL32_4
+            | IMAGE_REL_AMD64_REL32_5
+            | IMAGE_REL_AMD64_SECREL
+            | IMAGE_REL_AMD64_TOKEN
+            | IMAGE_REL_AMD64_SREL32
+            | IMAGE_REL_AMD64_SSPAN32 => 4,
+            IMAGE_REL_AMD64_ADDR64 => 8,
+            _ => return Err(CoffError::UnsupportedRelocation(typ).into()),
+        },
+        _ => return Err(CoffError::UnsupportedMachine(machine).into()),
+    };
+
+    Ok(size)
+}
+
+fn get_symbol_name(coff: &Coff, index: usize) -> Option<(String, goblin::pe::symbol::Symbol)> {
+    let (name, symbol) = coff.symbols.get(index)?;
+    let name = match name {
+        Some(name) => name,
+        None => symbol.name(&coff.strings).ok()?,
+    };
+    Some((name.to_string(), symbol))
+}
+
+/// create the signature for the code section with the given (zero-based) index,
+/// or `None` if the section doesn't define any public names.
+fn get_section_signature(
+    buf: &[u8],
+    coff: &Coff,
+    section_index: usize,
+    section: &SectionTable,
+) -> Result<Option<FlirtSignature>> {
+    let section_number = (section_index + 1) as i16;
+    let start = section.pointer_to_raw_data as usize;
+    let size = section.size_of_raw_data as usize;
+    let data = match buf.get(start..start + size) {
+        Some(data) => data,
+        None => return Ok(None),
+    };
+
+    let mut names: Vec<Symbol> = vec![];
+    for (_, name, symbol) in coff.symbols.iter() {
+        if symbol.section_number != section_number || symbol.is_section_definition() {
+            continue;
+        }
+
+        let name = match name {
+            Some(name) => name,
+            None => symbol.name(&coff.strings)?,
+        };
+        let name = Name {
+            offset: symbol.value as i64,
+            name:   name.to_string(),
+        };
+
+        match symbol.storage_class {
+            IMAGE_SYM_CLASS_EXTERNAL => names.push(Symbol::Public(name)),
+            IMAGE_SYM_CLASS_STATIC | IMAGE_SYM_CLASS_LABEL => names.push(Symbol::Local(name)),
+            _ => continue,
+        }
+    }
+
+    if !names.iter().any(|name| matches!(name, Symbol::Public(_))) {
+        return Ok(None);
+    }
+
+    if size > MAX_MODULE_SIZE {
+        warn!(
+            "coff: section too large: {}: {:#x} bytes",
+            section.name().unwrap_or("(unknown)"),
+            size
+        );
+        return Ok(None);
+    }
+
+    names.sort_by_key(|name| match name {
+        Symbol::Public(name) => (name.offset, 0),
+        Symbol::Local(name) => (name.offset, 1),
+        Symbol::Reference(name) => (name.offset, 2),
+    });
+
+    let mut wildcards = vec![false; size];
+    let mut references: Vec<Symbol> = vec![];
+    for reloc in section.relocations(buf)? {
+        let offset = match reloc.virtual_address.checked_sub(section.virtual_address) {
+            Some(offset) if (offset as usize) < size => offset as usize,
+            _ => {
+                debug!("coff: relocation out of range: {:#x}", reloc.virtual_address);
+                continue;
+            }
+        };
+        let reloc_size = get_relocation_size(coff.header.machine, reloc.typ)?;
+        for wildcard in wildcards.iter_mut().skip(offset).take(reloc_size) {
+            *wildcard = true;
+        }
+
+        if reloc_size == 0 {
+            continue;
+        }
+
+        // references to symbols defined in this section, or to sections
+        // themselves (like string literals in `.rdata`), don't have useful names.
+        if let Some((name, symbol)) = get_symbol_name(coff, reloc.symbol_table_index as usize) {
+            if symbol.section_number == section_number {
+                continue;
+            }
+
+            if symbol.storage_class == IMAGE_SYM_CLASS_EXTERNAL || symbol.storage_class == IMAGE_SYM_CLASS_WEAK_EXTERNAL
+            {
+                references.push(Symbol::Reference(Name {
+                    offset: offset as i64,
+                    name,
+                }));
+            }
+        }
+    }
+    names.extend(references);
+
+    Ok(Some(FlirtSignature::from_parts(data, &wildcards, names)))
+}
+
+/// generate signatures for the public functions in the given COFF object file.
+pub fn from_object(buf: &[u8]) -> Result<Vec<FlirtSignature>> {
+    let coff = Coff::parse(buf)?;
+
+    let mut sigs = vec![];
+    for (i, section) in coff.sections.iter().enumerate() {
+        if section.characteristics & (IMAGE_SCN_CNT_CODE | IMAGE_SCN_MEM_EXECUTE) == 0 {
+            continue;
+        }
+
+        if let Some(sig) = get_section_signature(buf, &coff, i, section)? {
+            sigs.push(sig);
+        }
+    }
+
+    Ok(sigs)
+}
+
+/// generate signatures for the public functions in the COFF object files
+/// found in the given .lib archive.
+///
+/// members that aren't COFF object files, such as short import library
+/// entries, are skipped.
+pub fn from_archive(buf: &[u8]) -> Result<Vec<FlirtSignature>> {
+    let archive = goblin::archive::Archive::parse(buf)?;
+
+    let mut sigs = vec![];
+    // there may be many members with the same name,
+    // so use the summary (which includes all the members) rather than lookup by name.
+    for (name, member, _) in archive.summarize().into_iter() {
+        let start = member.offset as usize;
+        let member_buf = match buf.get(start..start + member.size()) {
+            Some(member_buf) => member_buf,
+            None => {
+                debug!("coff: {}: member out of range", name);
+                continue;
+            }
+        };
+
+        // short import library entries start with IMAGE_FILE_MACHINE_UNKNOWN and 0xFFFF.
+        if member_buf.starts_with(&[0x00, 0x00, 0xFF, 0xFF]) {
+            continue;
+        }
+
+        match from_object(member_buf) {
+            Ok(member_sigs) => sigs.extend(member_sigs),
+            Err(e) => debug!("coff: {}: skipping member: {}", name, e),
+        }
+    }
+
+    Ok(sigs)
+}
+
+/// generate signatures from either a COFF object file or a .lib archive.
+///
+/// ```
+/// use lancelot_flirt::{coff, pat};
+/// let buf = include_bytes!("../sigs/coff/example.lib");
+/// let sigs = coff::from_bytes(buf).unwrap();
+/// assert_eq!(sigs.len(), 2);
+/// assert_eq!(
+///     pat::write_signature(&sigs[1]).unwrap(),
+///     "6A07E8........CC................................................ 00 0000 0008 :0000 _fail_fast ^0003 _report_failure"
+/// );
+/// ```
+pub fn from_bytes(buf: &[u8]) -> Result<Vec<FlirtSignature>> {
+    if buf.starts_with(AR_MAGIC) {
+        from_archive(buf)
+    } else {
+        from_object(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object() -> Result<()> {
+        let buf = include_bytes!("../sigs/coff/example.obj");
+        let sigs = from_object(buf)?;
+        assert_eq!(sigs.len(), 2);
+
+        assert_eq!(
+            crate::pat::write_signature(&sigs[0])?,
+            "5589E5A1........31E83B45087507E8........5DC36A02E8........83C404 10 E991 0036 \
+             :0000 _check_cookie :0022@ _helper ^0004 ___security_cookie ^0019 _report_failure ^0030 _report_failure \
+             ........40C3"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_archive() -> Result<()> {
+        let obj = from_object(include_bytes!("../sigs/coff/example.obj"))?;
+        let lib = from_archive(include_bytes!("../sigs/coff/example.lib"))?;
+        assert_eq!(crate::pat::write(&obj)?, crate::pat::write(&lib)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_match() -> Result<()> {
+        let buf = include_bytes!("../sigs/coff/example.obj");
+        let sigs = crate::FlirtSignatureSet::with_signatures(from_object(buf)?);
+
+        // the .text section, as linked into an image, with relocations applied.
+        let code: Vec<u8> = vec![
+            0x55, 0x89, 0xE5, 0xA1, 0x00, 0x30, 0x40, 0x00, 0x31, 0xE8, 0x3B, 0x45, 0x08, 0x75, 0x07, 0xE8, 0x0E, 0x00,
+            0x00, 0x00, 0x5D, 0xC3, 0x6A, 0x02, 0xE8, 0x20, 0x00, 0x00, 0x00, 0x83, 0xC4, 0x04, 0x5D, 0xC3, 0xB9, 0x78,
+            0x56, 0x34, 0x12, 0x0F, 0xAF, 0xC1, 0x05, 0x44, 0x33, 0x22, 0x11, 0xE8, 0x05, 0x00, 0x00, 0x00, 0x40, 0xC3,
+        ];
+        let matches = sigs.r#match(&code);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].get_name(), Some("_check_cookie"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,421 @@
+//! Detect and resolve collisions among signatures, like `sigmake` does
+//! when compiling .pat files into a .sig file.
+//!
+//! Signatures collide when they have the same pattern, CRC16, function size,
+//! and referenced names, but different public names. The .sig format doesn't store footers,
+//! so the matcher can't tell these apart, unless:
+//!
+//!   1. we pick tail bytes from the footers that differentiate them, or
+//!   2. the user picks which signature to keep, via an exclusions file.
+//!
+//! Collisions that can't be resolved using tail bytes are rendered into an
+//! exclusions (.exc) file, with one group of colliding signatures per
+//! paragraph. The user prefixes the lines to keep with `+` (or `-`),
+//! deletes the header comment, and compiles again. Groups without a
+//! selection are excluded entirely.
+//!
+//! references:
+//!   - https://hex-rays.com/products/ida/tech/flirt/in_depth/
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::Result;
+use thiserror::Error;
+
+use super::{FlirtSignature, SigElement, TailByte};
+
+#[derive(Debug, Error)]
+pub enum CollisionError {
+    #[error("The exclusions file has not been edited (delete the header comment)")]
+    NotEdited,
+    #[error("Multiple signatures selected for collision: {0}")]
+    MultipleSelection(String),
+}
+
+/// the first line of a generated exclusions file,
+/// which must be deleted before the file is used.
+const EXCLUSIONS_HEADER: &str = ";--------- (delete these lines to allow sigmake to read this file)";
+
+/// render the attributes of the signature that are encoded in the tree of a
+/// .sig file: pattern (padded to 32 bytes), CRC16, and size.
+fn get_key(sig: &FlirtSignature) -> String {
+    let mut pattern = sig.byte_sig.to_string().to_uppercase();
+    while pattern.len() < 0x40 {
+        pattern.push_str("..");
+    }
+
+    format!(
+        "{:02X} {:04X} {:04X} {}",
+        sig.size_of_bytes_crc16, sig.crc16, sig.size_of_function, pattern
+    )
+}
+
+/// render the attributes that the matcher uses to tell signatures apart:
+/// the key plus the referenced names.
+/// signatures that reference different names don't collide.
+fn get_group_key(sig: &FlirtSignature) -> String {
+    let mut key = get_key(sig);
+    for (offset, name) in sig.get_references().iter() {
+        key.push_str(&format!(" ^{:04X} {}", offset, name));
+    }
+    key
+}
+
+/// render the line that describes the given signature in an exclusions file.
+fn get_line(sig: &FlirtSignature) -> String {
+    format!("{}\t{}", sig.get_name().unwrap_or(""), get_key(sig))
+}
+
+/// fetch the byte at the given offset (from the start of the function),
+/// if its known and not a wildcard.
+fn get_byte(sig: &FlirtSignature, offset: u64) -> Option<u8> {
+    if let Some(tail_byte) = sig.tail_bytes.iter().find(|tail_byte| tail_byte.offset == offset) {
+        return Some(tail_byte.value);
+    }
+
+    let footer_start = (sig.byte_sig.0.len() + sig.size_of_bytes_crc16 as usize) as u64;
+    let elem = if offset < sig.byte_sig.0.len() as u64 {
+        sig.byte_sig.0.get(offset as usize)
+    } else if offset >= footer_start {
+        sig.footer
+            .as_ref()
+            .and_then(|footer| footer.0.get((offset - footer_start) as usize))
+    } else {
+        // the bytes covered by the CRC16 aren't recorded.
+        None
+    };
+
+    match elem {
+        Some(SigElement::Byte(v)) => Some(*v),
+        _ => None,
+    }
+}
+
+/// pick tail bytes for the signature at the given index that differentiate it
+/// from all the others in the group.
+/// returns `None` when some other signature can't be told apart.
+fn find_tail_bytes(group: &[FlirtSignature], index: usize) -> Option<Vec<TailByte>> {
+    let sig = &group[index];
+    let mut remaining: Vec<&FlirtSignature> = group
+        .iter()
+        .enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, other)| other)
+        .collect();
+
+    let mut tail_bytes = vec![];
+    while !remaining.is_empty() {
+        // greedily pick the offset that differentiates the most signatures,
+        // preferring the lowest offset, so the results are deterministic.
+        let (count, offset) = (0..sig.size_of_function)
+            .filter_map(|offset| get_byte(sig, offset).map(|v| (offset, v)))
+            .map(|(offset, v)| {
+                let count = remaining
+                    .iter()
+                    .filter(|other| matches!(get_byte(other, offset), Some(w) if w != v))
+                    .count();
+                (count, std::cmp::Reverse(offset))
+            })
+            .max()?;
+        let offset = offset.0;
+        if count == 0 {
+            return None;
+        }
+
+        let value = get_byte(sig, offset).expect("offset has a known byte");
+        remaining.retain(|other| !matches!(get_byte(other, offset), Some(w) if w != value));
+        tail_bytes.push(TailByte { offset, value });
+    }
+
+    tail_bytes.sort_by_key(|tail_byte| tail_byte.offset);
+    Some(tail_bytes)
+}
+
+/// the selections made by the user in an exclusions (.exc) file.
+#[derive(Debug, Default)]
+pub struct Exclusions {
+    /// all the signatures listed in the file, without their prefix.
+    listed:   HashSet<String>,
+    /// the signatures that have been selected, without their prefix.
+    selected: HashSet<String>,
+}
+
+impl Exclusions {
+    /// parse the content of an edited exclusions file.
+    ///
+    /// lines prefixed by `+` or `-` select a signature,
+    /// and lines starting with `;` are comments.
+    /// the file must not contain the header comment, which indicates that
+    /// it hasn't been edited yet.
+    pub fn parse(buf: &str) -> Result<Exclusions> {
+        let mut exclusions: Exclusions = Default::default();
+
+        for line in buf.lines() {
+            let line = line.trim_end();
+            if line == EXCLUSIONS_HEADER {
+                return Err(CollisionError::NotEdited.into());
+            }
+
+            if line.is_empty() || line.starts_with(';') {
+                continue;
+            }
+
+            match line.strip_prefix('+').or_else(|| line.strip_prefix('-')) {
+                Some(line) => {
+                    exclusions.listed.insert(line.to_string());
+                    exclusions.selected.insert(line.to_string());
+                }
+                None => {
+                    exclusions.listed.insert(line.to_string());
+                }
+            }
+        }
+
+        Ok(exclusions)
+    }
+}
+
+/// the result of resolving collisions among a set of signatures.
+pub struct Resolution {
+    /// the signatures that can be compiled into a .sig file,
+    /// with tail bytes added to differentiate the colliding ones.
+    pub sigs:       Vec<FlirtSignature>,
+    /// the groups of colliding signatures that could not be resolved,
+    /// and have been excluded from `sigs`.
+    pub collisions: Vec<Vec<FlirtSignature>>,
+}
+
+/// find the groups of signatures that collide:
+/// those with the same pattern, CRC16, size, and references, but different names.
+///
+/// ```
+/// use lancelot_flirt::{collision, pat};
+///
+/// let pat_buf = "\
+/// 0F281D........0F59D80F2825........0F280D........660F5BD30F283D.. 00 0000 02C0 :0000 ___sse4_expf4
+/// 0F281D........0F59D80F2825........0F280D........660F5BD30F283D.. 00 0000 02C0 :0000 ___sse4_exp2f4
+/// 0F281D........0F59D80F2825........0F280D........660F5BD30F283D.. 00 0000 02C0 :0000 ___sse4_exp2f4
+/// 0F281D........0F59D80F2825........0F280D........660F5BD30F283D.. 00 0000 0300 :0000 ___sse4_exp10f4
+/// ---";
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// let collisions = collision::find_collisions(&sigs);
+/// assert_eq!(collisions.len(), 1);
+/// assert_eq!(collisions[0].len(), 3);
+/// ```
+pub fn find_collisions(sigs: &[FlirtSignature]) -> Vec<Vec<&FlirtSignature>> {
+    let mut groups: BTreeMap<usize, Vec<&FlirtSignature>> = Default::default();
+    let mut indices: HashMap<String, usize> = Default::default();
+
+    for (i, sig) in sigs.iter().enumerate() {
+        let index = *indices.entry(get_group_key(sig)).or_insert(i);
+        groups.entry(index).or_default().push(sig);
+    }
+
+    groups
+        .into_values()
+        .filter(|group| group.iter().any(|sig| sig.get_name() != group[0].get_name()))
+        .collect()
+}
+
+/// resolve the collisions among the given signatures.
+///
+/// signatures with the same key and name are duplicates, and only the first is
+/// kept. colliding signatures are differentiated using tail bytes from their
+/// footers, when possible. otherwise, the selections from the exclusions file
+/// are used. the remaining collisions are excluded from the result, and should
+/// be rendered into an exclusions file via `write_exclusions`.
+///
+/// the order of the signatures is preserved.
+///
+/// ```
+/// use lancelot_flirt::{collision, pat};
+///
+/// let pat_buf = "\
+/// 558BEC51........................................................ 00 0000 0025 :0000 _foo 8B4508C9C3
+/// 558BEC51........................................................ 00 0000 0025 :0000 _bar 8B450CC9C3
+/// ---";
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// let resolution = collision::resolve(sigs, &Default::default()).unwrap();
+/// assert_eq!(resolution.collisions.len(), 0);
+/// assert_eq!(pat::write(&resolution.sigs).unwrap(), "\
+/// 558BEC51........................................................ 00 0000 0025 :0000 _foo 8B4508C9C3 (0022: 08)
+/// 558BEC51........................................................ 00 0000 0025 :0000 _bar 8B450CC9C3 (0022: 0C)
+/// ---
+/// ");
+/// ```
+pub fn resolve(sigs: Vec<FlirtSignature>, exclusions: &Exclusions) -> Result<Resolution> {
+    let mut groups: Vec<Vec<FlirtSignature>> = vec![];
+    let mut indices: HashMap<String, usize> = Default::default();
+
+    for sig in sigs.into_iter() {
+        let key = get_group_key(&sig);
+        match indices.get(&key) {
+            Some(&index) => {
+                let group = &mut groups[index];
+                if group.iter().all(|other| other.get_name() != sig.get_name()) {
+                    group.push(sig);
+                }
+            }
+            None => {
+                indices.insert(key, groups.len());
+                groups.push(vec![sig]);
+            }
+        }
+    }
+
+    let mut resolution = Resolution {
+        sigs:       vec![],
+        collisions: vec![],
+    };
+
+    for mut group in groups.into_iter() {
+        if group.len() == 1 {
+            resolution.sigs.extend(group);
+            continue;
+        }
+
+        let tail_bytes: Option<Vec<Vec<TailByte>>> = (0..group.len()).map(|i| find_tail_bytes(&group, i)).collect();
+        if let Some(tail_bytes) = tail_bytes {
+            for (sig, tail_bytes) in group.iter_mut().zip(tail_bytes) {
+                for tail_byte in tail_bytes.into_iter() {
+                    if sig.tail_bytes.iter().all(|existing| existing.offset != tail_byte.offset) {
+                        sig.tail_bytes.push(tail_byte);
+                    }
+                }
+                sig.tail_bytes.sort_by_key(|tail_byte| tail_byte.offset);
+            }
+
+            resolution.sigs.extend(group);
+            continue;
+        }
+
+        let selected: Vec<usize> = group
+            .iter()
+            .enumerate()
+            .filter(|(_, sig)| exclusions.selected.contains(&get_line(sig)))
+            .map(|(i, _)| i)
+            .collect();
+
+        match selected.len() {
+            // the user hasn't seen this collision yet.
+            0 if !group.iter().any(|sig| exclusions.listed.contains(&get_line(sig))) => {
+                resolution.collisions.push(group)
+            }
+            // the user chose to exclude the entire group.
+            0 => continue,
+            1 => resolution.sigs.push(group.swap_remove(selected[0])),
+            _ => return Err(CollisionError::MultipleSelection(get_line(&group[selected[0]])).into()),
+        }
+    }
+
+    Ok(resolution)
+}
+
+/// render the given collisions into an exclusions (.exc) file,
+/// like the one produced by `sigmake`.
+///
+/// ```
+/// use lancelot_flirt::{collision, pat};
+///
+/// let pat_buf = "\
+/// 558BEC51........................................................ 00 0000 0025 :0000 _foo 8B45..C9C3
+/// 558BEC51........................................................ 00 0000 0025 :0000 _bar 8B45..C9C3
+/// ---";
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// let resolution = collision::resolve(sigs, &Default::default()).unwrap();
+/// assert_eq!(resolution.sigs.len(), 0);
+///
+/// let exc = collision::write_exclusions(&resolution.collisions);
+/// assert!(exc.contains("_foo\t00 0000 0025 558BEC51"));
+///
+/// // select `_bar`.
+/// let exc = exc.lines().skip(3).collect::<Vec<_>>().join("\n").replace("_bar", "+_bar");
+/// let exclusions = collision::Exclusions::parse(&exc).unwrap();
+///
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// let resolution = collision::resolve(sigs, &exclusions).unwrap();
+/// assert_eq!(resolution.sigs.len(), 1);
+/// assert_eq!(resolution.sigs[0].get_name(), Some("_bar"));
+/// ```
+pub fn write_exclusions(collisions: &[Vec<FlirtSignature>]) -> String {
+    let mut out = String::new();
+    out.push_str(EXCLUSIONS_HEADER);
+    out.push('\n');
+    out.push_str(";--------- add '+' at the start of a line to select a module\n");
+    out.push_str(";--------- do nothing if you want to exclude all modules\n");
+
+    for group in collisions.iter() {
+        out.push('\n');
+        for sig in group.iter() {
+            out.push_str(&get_line(sig));
+            out.push('\n');
+        }
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_libcmt() -> Result<()> {
+        let buf = include_bytes!("../sigs/sig/libcmt_15_msvc_x86.sig");
+        let sigs = crate::sig::parse(buf)?;
+        let count = sigs.len();
+
+        // sigmake already resolved the collisions in this file,
+        // so there's nothing left for the user to decide.
+        let resolution = resolve(sigs, &Default::default())?;
+        assert_eq!(resolution.collisions.len(), 0);
+        assert_eq!(resolution.sigs.len(), count);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_multiple_tail_bytes() -> Result<()> {
+        let buf = "\
+558BEC51........................................................ 00 0000 0025 :0000 _foo 8B4508C9C3
+558BEC51........................................................ 00 0000 0025 :0000 _bar 8B450CC9C3
+558BEC51........................................................ 00 0000 0025 :0000 _baz 8B450CC9C2
+---";
+        let resolution = resolve(crate::pat::parse(buf)?, &Default::default())?;
+        assert_eq!(resolution.collisions.len(), 0);
+        assert_eq!(
+            crate::pat::write(&resolution.sigs)?,
+            "\
+558BEC51........................................................ 00 0000 0025 :0000 _foo 8B4508C9C3 (0022: 08)
+558BEC51........................................................ 00 0000 0025 :0000 _bar 8B450CC9C3 (0022: 0C)(0024: C3)
+558BEC51........................................................ 00 0000 0025 :0000 _baz 8B450CC9C2 (0024: C2)
+---
+"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclude_group() -> Result<()> {
+        let buf = "\
+558BEC51........................................................ 00 0000 0025 :0000 _foo 8B45..C9C3
+558BEC51........................................................ 00 0000 0025 :0000 _bar 8B45..C9C3
+558BEC52........................................................ 00 0000 0025 :0000 _baz 8B45..C9C3
+---";
+        let resolution = resolve(crate::pat::parse(buf)?, &Default::default())?;
+        assert_eq!(resolution.sigs.len(), 1);
+        assert_eq!(resolution.collisions.len(), 1);
+
+        // an edited exclusions file without any selections excludes the group.
+        let exc = write_exclusions(&resolution.collisions).replace(EXCLUSIONS_HEADER, "");
+        assert!(Exclusions::parse(&write_exclusions(&resolution.collisions)).is_err());
+        let exclusions = Exclusions::parse(&exc)?;
+
+        let resolution = resolve(crate::pat::parse(buf)?, &exclusions)?;
+        assert_eq!(resolution.sigs.len(), 1);
+        assert_eq!(resolution.sigs[0].get_name(), Some("_baz"));
+        assert_eq!(resolution.collisions.len(), 0);
+
+        Ok(())
+    }
+}
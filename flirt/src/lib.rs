@@ -36,9 +36,16 @@ use log::trace;
 use regex::bytes::Regex;
 use std::collections::HashMap;
 
+pub mod cache;
+pub mod coff;
+pub mod collision;
+pub mod library;
+mod parse;
 pub mod pat;
 pub mod pattern_set;
 pub mod sig;
+#[cfg(test)]
+mod strategy;
 
 #[derive(Debug, Clone, Copy)]
 pub enum SigElement {
@@ -119,14 +126,17 @@ pub struct FlirtSignature {
 
     names: Vec<Symbol>,
 
-    // the .pat file format uses a ByteSignature-style to specify a footer mask
-    // which starts "at the end of the crc16 block."
-    // not sure how these are translated into tail bytes.
+    // the .pat file format uses a ByteSignature-style to specify the remaining
+    // bytes of the function, which starts "at the end of the crc16 block."
+    // relocated bytes are wildcards.
+    // sigmake doesn't store this in .sig files; instead, when signatures
+    // collide, it picks bytes from the footers that differentiate them,
+    // and stores these as tail bytes.
     footer: Option<ByteSignature>,
 
     // the .sig file format tracks (offset, byte value) pairs that differentiate
     // signatures with the same pattern/crc16/references.
-    // not sure how these are translated into the footer.
+    // the offsets are relative to the start of the function.
     tail_bytes: Vec<TailByte>,
 }
 
@@ -171,7 +181,93 @@ impl std::fmt::Debug for FlirtSignature {
     }
 }
 
+/// the pattern covers the first 32 bytes of a function.
+const PATTERN_SIZE: usize = 0x20;
+/// the CRC16 covers at most 255 bytes following the pattern.
+const MAX_CRC_SIZE: usize = 0xFF;
+
 impl FlirtSignature {
+    /// create a signature from the bytes of a function and its names.
+    ///
+    /// `wildcards` flags the bytes that may differ from one image to the next,
+    /// such as relocated addresses, and is parallel to `buf`.
+    /// the bytes following the pattern, up to the first wildcard, are checksummed,
+    /// and the remaining bytes form the footer.
+    ///
+    /// panics if `buf` and `wildcards` have different lengths.
+    fn from_parts(buf: &[u8], wildcards: &[bool], names: Vec<Symbol>) -> FlirtSignature {
+        assert_eq!(buf.len(), wildcards.len(), "wildcards must be parallel to the function bytes");
+
+        let elem = |i: usize| {
+            if wildcards[i] {
+                SigElement::Wildcard
+            } else {
+                SigElement::Byte(buf[i])
+            }
+        };
+
+        let size = buf.len();
+        let pattern_size = std::cmp::min(PATTERN_SIZE, size);
+        let crc_size = wildcards[pattern_size..]
+            .iter()
+            .take(MAX_CRC_SIZE)
+            .take_while(|&&wildcard| !wildcard)
+            .count();
+        let footer_start = pattern_size + crc_size;
+
+        FlirtSignature {
+            byte_sig: ByteSignature((0..pattern_size).map(elem).collect()),
+            size_of_bytes_crc16: crc_size as u8,
+            crc16: FlirtSignature::crc16(&buf[pattern_size..footer_start]),
+            size_of_function: size as u64,
+            names,
+            footer: if footer_start < size {
+                Some(ByteSignature((footer_start..size).map(elem).collect()))
+            } else {
+                None
+            },
+            tail_bytes: vec![],
+        }
+    }
+
+    /// create a signature for the function with the given bytes and name.
+    ///
+    /// `wildcards` flags the bytes that may differ from one image to the next,
+    /// such as relocated addresses, and is parallel to `buf`.
+    /// `references` are the names referenced by the function,
+    /// and the offsets (from the start of the function) of the references,
+    /// like those returned by `get_references`.
+    ///
+    /// # Panics
+    ///
+    ///   - `buf` and `wildcards` must have the same length.
+    ///
+    /// ```
+    /// use lancelot_flirt::*;
+    /// // E8 00 00 00 00  CALL $+5
+    /// // C3              RET
+    /// let buf = b"\xE8\x00\x00\x00\x00\xC3";
+    /// let wildcards = [false, true, true, true, true, false];
+    /// let sig = FlirtSignature::from_function(buf, &wildcards, "_foo", &[(1, "_bar")]);
+    /// assert_eq!(sig.get_name(), Some("_foo"));
+    /// assert_eq!(sig.get_references(), vec![(1, "_bar")]);
+    /// assert!(sig.create_matcher().r#match(b"\xE8\x11\x22\x33\x44\xC3"));
+    /// ```
+    pub fn from_function(buf: &[u8], wildcards: &[bool], name: &str, references: &[(i64, &str)]) -> FlirtSignature {
+        let mut names = vec![Symbol::Public(Name {
+            offset: 0,
+            name:   name.to_string(),
+        })];
+        names.extend(references.iter().map(|&(offset, name)| {
+            Symbol::Reference(Name {
+                offset,
+                name: name.to_string(),
+            })
+        }));
+
+        FlirtSignature::from_parts(buf, wildcards, names)
+    }
+
     pub fn create_matcher(&self) -> FlirtSignatureMatcher {
         FlirtSignatureMatcher::new(self)
     }
@@ -188,6 +284,43 @@ impl FlirtSignature {
         None
     }
 
+    /// get the names referenced by the function,
+    /// and the offsets (from the start of the function) of the references.
+    pub fn get_references(&self) -> Vec<(i64, &str)> {
+        self.names
+            .iter()
+            .filter_map(|name| match name {
+                Symbol::Reference(name) => Some((name.offset, name.name.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// get the public names defined by the function,
+    /// and their offsets (from the start of the function).
+    /// the name at offset zero is the name of the function.
+    pub fn get_public_names(&self) -> Vec<(i64, &str)> {
+        self.names
+            .iter()
+            .filter_map(|name| match name {
+                Symbol::Public(name) => Some((name.offset, name.name.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// get the local (`@`) names defined by the function,
+    /// and their offsets (from the start of the function).
+    pub fn get_local_names(&self) -> Vec<(i64, &str)> {
+        self.names
+            .iter()
+            .filter_map(|name| match name {
+                Symbol::Local(name) => Some((name.offset, name.name.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
     /// compute the IDA-specific CRC16 checksum for the given bytes.
     ///
     /// This is ported from flair tools flair/crc16.cpp
@@ -242,6 +375,29 @@ impl FlirtSignature {
         true
     }
 
+    /// return true if the buffer is large enough to contain the function.
+    pub fn match_size(&self, buf: &[u8]) -> bool {
+        buf.len() as u64 >= self.size_of_function
+    }
+
+    /// return true if the footer matches (if there is one).
+    ///
+    /// the footer starts at the end of the CRC16 region,
+    /// and may contain wildcards for relocated bytes.
+    pub fn match_footer(&self, buf: &[u8]) -> bool {
+        let footer = match &self.footer {
+            Some(footer) => footer,
+            None => return true,
+        };
+
+        let start = self.byte_sig.0.len() + (self.size_of_bytes_crc16 as usize);
+        footer.0.iter().enumerate().all(|(i, elem)| match (elem, buf.get(start + i)) {
+            (SigElement::Wildcard, Some(_)) => true,
+            (SigElement::Byte(expected), Some(v)) => expected == v,
+            (_, None) => false,
+        })
+    }
+
     /// return true if all tail bytes match (if there are any).
     pub fn match_tail_bytes(&self, buf: &[u8]) -> bool {
         !self
@@ -253,6 +409,82 @@ impl FlirtSignature {
             })
             .any(|b| !b)
     }
+
+    /// verify the names referenced by the function.
+    ///
+    /// the resolver is invoked with the offset (from the start of the
+    /// function) of each reference, and returns the name already known for
+    /// the referenced address, if any.
+    ///
+    /// returns `None` if any reference resolves to a different name,
+    /// otherwise, the number of references that were verified.
+    /// references that can't be resolved don't disqualify the signature.
+    pub fn verify_references<R>(&self, resolver: R) -> Option<usize>
+    where
+        R: Fn(u64) -> Option<String>,
+    {
+        let mut verified = 0;
+
+        for (offset, expected) in self.get_references().into_iter() {
+            if offset < 0 {
+                continue;
+            }
+
+            if let Some(name) = resolver(offset as u64) {
+                if normalize_name(&name) == normalize_name(expected) {
+                    verified += 1;
+                } else {
+                    trace!("flirt signature: reference fails: {} != {}", expected, name);
+                    return None;
+                }
+            }
+        }
+
+        Some(verified)
+    }
+
+    /// return true if no reference resolves to a different name.
+    /// see `verify_references`.
+    pub fn match_references<R>(&self, resolver: R) -> bool
+    where
+        R: Fn(u64) -> Option<String>,
+    {
+        self.verify_references(resolver).is_some()
+    }
+}
+
+/// normalize a symbol name so that names with different decorations
+/// can be compared.
+///
+/// signatures use the names found in the library,
+/// like `__imp__GetProcAddress@8` or `_memcpy`,
+/// while other sources, such as import tables, have names like
+/// `GetProcAddress`.
+///
+/// ```
+/// use lancelot_flirt::normalize_name;
+///
+/// assert_eq!(normalize_name("__imp__GetProcAddress@8"), "GetProcAddress");
+/// assert_eq!(normalize_name("GetProcAddress"), "GetProcAddress");
+/// assert_eq!(normalize_name("___security_cookie"), "security_cookie");
+/// assert_eq!(normalize_name("@__security_check_cookie@4"), "security_check_cookie");
+/// assert_eq!(normalize_name("??2@YAPAXI@Z"), "??2@YAPAXI@Z");
+/// ```
+pub fn normalize_name(name: &str) -> &str {
+    let name = name.strip_prefix("__imp_").unwrap_or(name);
+
+    if name.starts_with('?') {
+        // MSVC C++ mangled names are already unambiguous.
+        return name;
+    }
+
+    let name = name.trim_start_matches(&['_', '@'][..]);
+
+    // strip stdcall/fastcall decorations, like `@8`.
+    match name.rfind('@') {
+        Some(i) if i + 1 < name.len() && name[i + 1..].bytes().all(|b| b.is_ascii_digit()) => &name[..i],
+        _ => name,
+    }
 }
 
 pub struct FlirtSignatureMatcher<'a> {
@@ -303,6 +535,8 @@ impl<'a> FlirtSignatureMatcher<'a> {
         Regex::new(&pattern).expect("failed to compile regex")
     }
 
+    /// like IDA, match the pattern, CRC16, tail bytes, footer, and function size.
+    ///
     /// ```
     /// use lancelot_flirt;
     /// use lancelot_flirt::pat;
@@ -318,7 +552,7 @@ impl<'a> FlirtSignatureMatcher<'a> {
     /// #[allow(non_snake_case)]
     /// let __EH_prolog3_catch_align = &sigs[3];
     /// let m = __EH_prolog3_catch_align.create_matcher();
-    /// assert!(m.r#match(&[
+    /// let buf = [
     ///     // apds.dll / 4FD932C41DF96D019DC265E26E94B81B
     ///     // __EH_prolog3_catch_align
     ///
@@ -332,12 +566,20 @@ impl<'a> FlirtSignatureMatcher<'a> {
     ///     0xF4, 0x89, 0x6C, 0x24, 0x0C, 0x8D, 0x6C, 0x24,
     ///     0x0C, 0xC7, 0x44, 0x24, 0x08, 0xFF, 0xFF, 0xFF,
     ///     0xFF, 0x51, 0x53, 0x2B, 0xE0, 0x56, 0x57, 0xA1,
-    ///     // crc end
+    ///     // crc end, footer
     ///     0xD4, 0xAD, 0x19, 0x01, 0x33, 0xC5, 0x50, 0x89,
     ///     0x65, 0xF0, 0x8B, 0x43, 0x04, 0x89, 0x45, 0x04,
     ///     0xFF, 0x75, 0xF4, 0x64, 0xA1, 0x00, 0x00, 0x00,
     ///     0x00, 0x89, 0x45, 0xF4, 0x8D, 0x45, 0xF4, 0x64,
-    ///     0xA3, 0x00, 0x00, 0x00, 0x00, 0xC3]));
+    ///     0xA3, 0x00, 0x00, 0x00, 0x00, 0xC3];
+    ///
+    /// // the signature was made from a build of the function that ends with `BND RET` (`F2 C3`),
+    /// // so its footer and size don't match this one.
+    /// assert!(!m.r#match(&buf));
+    ///
+    /// let mut bnd_ret = buf[..buf.len() - 1].to_vec();
+    /// bnd_ret.extend(&[0xF2, 0xC3]);
+    /// assert!(m.r#match(&bnd_ret));
     /// ```
     pub fn r#match(&self, buf: &[u8]) -> bool {
         if !self.re.is_match(buf) {
@@ -350,18 +592,122 @@ impl<'a> FlirtSignatureMatcher<'a> {
             return false;
         }
 
+        if !self.sig.match_tail_bytes(buf) {
+            trace!("flirt signature: tail bytes fail");
+            return false;
+        }
+
+        if !self.sig.match_footer(buf) {
+            trace!("flirt signature: footer fails");
+            return false;
+        }
+
+        if !self.sig.match_size(buf) {
+            trace!("flirt signature: size fails");
+            return false;
+        }
+
         trace!("flirt signature: match");
         true
     }
 }
 
-pub struct FlirtSignatureSet {
-    sigs:    HashMap<pattern_set::Pattern, FlirtSignature>,
-    matcher: pattern_set::PatternSet,
+/// a signature that matches the function at some address,
+/// with the names of the signature mapped to addresses.
+///
+/// ```
+/// use lancelot_flirt::{pat, FlirtMatch};
+///
+/// let sigs = pat::parse("\
+/// 5589E5E8........5DC3 00 0000 000A :0000 _foo :0003@ $loop ^0004 _bar
+/// ---").unwrap();
+/// let m = FlirtMatch::new(&sigs[0], 0x401000);
+/// assert_eq!(m.get_name(), Some("_foo"));
+/// assert_eq!(m.public_names, vec![(0x401000, "_foo")]);
+/// assert_eq!(m.local_names, vec![(0x401003, "$loop")]);
+/// assert_eq!(m.references, vec![(0x401004, "_bar")]);
+/// ```
+#[derive(Debug)]
+pub struct FlirtMatch<'a> {
+    /// the address of the start of the function.
+    pub address:      u64,
+    pub sig:          &'a FlirtSignature,
+    /// the public names defined by the function, like the name of the function
+    /// and any alternate entry points.
+    pub public_names: Vec<(u64, &'a str)>,
+    /// the local names defined by the function, like labels.
+    pub local_names:  Vec<(u64, &'a str)>,
+    /// the names referenced by the function, and the addresses of the references.
+    /// the address of the referenced name must be computed from the bytes
+    /// at the reference, such as a relative displacement or absolute address.
+    pub references:   Vec<(u64, &'a str)>,
+}
+
+impl<'a> FlirtMatch<'a> {
+    pub fn new(sig: &'a FlirtSignature, address: u64) -> FlirtMatch<'a> {
+        // names may precede the start of the function,
+        // which is fine unless they'd precede address zero.
+        let map = |names: Vec<(i64, &'a str)>| -> Vec<(u64, &'a str)> {
+            names
+                .into_iter()
+                .filter_map(|(offset, name)| {
+                    let address = if offset < 0 {
+                        address.checked_sub(offset.unsigned_abs())
+                    } else {
+                        address.checked_add(offset as u64)
+                    };
+                    address.map(|address| (address, name))
+                })
+                .collect()
+        };
+
+        FlirtMatch {
+            address,
+            sig,
+            public_names: map(sig.get_public_names()),
+            local_names: map(sig.get_local_names()),
+            references: map(sig.get_references()),
+        }
+    }
+
+    pub fn get_name(&self) -> Option<&'a str> {
+        self.sig.get_name()
+    }
+}
+
+/// how `FlirtSignatureSet::scan` handles matches that overlap.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overlap {
+    /// report every match, even those that start within another match.
+    All,
+    /// skip the offsets within the bytes described by an earlier match,
+    /// like a linear sweep that resumes after each recognized function.
+    Skip,
+}
+
+/// a signature that matches at some offset within a scanned buffer.
+#[derive(Debug)]
+pub struct ScanMatch<'a> {
+    pub offset: usize,
+    pub sig:    &'a FlirtSignature,
+}
+
+pub struct FlirtSignatureSet(Signatures);
+
+enum Signatures {
+    /// signatures compiled in memory.
+    Compiled {
+        /// many signatures may share the same byte pattern,
+        /// and be distinguished only by CRC16, tail bytes, etc.
+        sigs:    HashMap<pattern_set::Pattern, Vec<FlirtSignature>>,
+        matcher: pattern_set::PatternSet,
+    },
+    /// signatures matched in place within a cache (see `cache::load`).
+    Cached(cache::CachedSignatures),
 }
 
-// translate from the FLIRT signature format to NFA matcher format (already
-// essentially the same).
+// translate from the FLIRT signature format to the prefix tree matcher format
+// (already essentially the same).
 impl std::convert::Into<pattern_set::Pattern> for &FlirtSignature {
     fn into(self) -> pattern_set::Pattern {
         pattern_set::Pattern(
@@ -379,14 +725,46 @@ impl std::convert::Into<pattern_set::Pattern> for &FlirtSignature {
 
 impl FlirtSignatureSet {
     pub fn with_signatures(sigs: Vec<FlirtSignature>) -> FlirtSignatureSet {
-        let sigs: HashMap<pattern_set::Pattern, FlirtSignature> =
-            sigs.into_iter().map(|sig| ((&sig).into(), sig)).collect();
+        let mut by_pattern: HashMap<pattern_set::Pattern, Vec<FlirtSignature>> = Default::default();
+        for sig in sigs.into_iter() {
+            by_pattern.entry((&sig).into()).or_default().push(sig);
+        }
+        let sigs = by_pattern;
 
         let patterns = sigs.keys().cloned().collect();
 
-        FlirtSignatureSet {
+        FlirtSignatureSet(Signatures::Compiled {
             sigs,
             matcher: pattern_set::PatternSet::from_patterns(patterns),
+        })
+    }
+
+    pub(crate) fn from_cache(sigs: cache::CachedSignatures) -> FlirtSignatureSet {
+        FlirtSignatureSet(Signatures::Cached(sigs))
+    }
+
+    /// the signatures that share each pattern, in the order of the patterns in the prefix tree.
+    pub(crate) fn groups(&self) -> Vec<(pattern_set::Pattern, &[FlirtSignature])> {
+        match &self.0 {
+            Signatures::Compiled { sigs, matcher } => matcher
+                .patterns()
+                .iter()
+                .map(|pattern| {
+                    let group = sigs.get(pattern).map(|group| &group[..]).unwrap_or(&[]);
+                    (pattern.clone(), group)
+                })
+                .collect(),
+            Signatures::Cached(cached) => (0..cached.len())
+                .map(|index| (cached.pattern(index), cached.group(index)))
+                .collect(),
+        }
+    }
+
+    /// serialize the prefix tree that matches the patterns (see `PatternSet::write_tree`).
+    pub(crate) fn write_tree(&self, out: &mut Vec<u8>) {
+        match &self.0 {
+            Signatures::Compiled { matcher, .. } => matcher.write_tree(out),
+            Signatures::Cached(cached) => out.extend(cached.tree_bytes()),
         }
     }
 
@@ -402,7 +780,7 @@ impl FlirtSignatureSet {
     /// ---";
     ///
     /// let sigs = lancelot_flirt::FlirtSignatureSet::with_signatures(pat::parse(pat_buf).unwrap());
-    /// let matches = sigs.r#match(&[
+    /// let buf = [
     ///     // apds.dll / 4FD932C41DF96D019DC265E26E94B81B
     ///     // __EH_prolog3_catch_align
     ///
@@ -416,23 +794,203 @@ impl FlirtSignatureSet {
     ///     0xF4, 0x89, 0x6C, 0x24, 0x0C, 0x8D, 0x6C, 0x24,
     ///     0x0C, 0xC7, 0x44, 0x24, 0x08, 0xFF, 0xFF, 0xFF,
     ///     0xFF, 0x51, 0x53, 0x2B, 0xE0, 0x56, 0x57, 0xA1,
-    ///     // crc end
+    ///     // crc end, footer
     ///     0xD4, 0xAD, 0x19, 0x01, 0x33, 0xC5, 0x50, 0x89,
     ///     0x65, 0xF0, 0x8B, 0x43, 0x04, 0x89, 0x45, 0x04,
     ///     0xFF, 0x75, 0xF4, 0x64, 0xA1, 0x00, 0x00, 0x00,
     ///     0x00, 0x89, 0x45, 0xF4, 0x8D, 0x45, 0xF4, 0x64,
-    ///     0xA3, 0x00, 0x00, 0x00, 0x00, 0xC3]);
+    ///     0xA3, 0x00, 0x00, 0x00, 0x00, 0xC3];
+    ///
+    /// // like IDA, the footer and function size must match, too,
+    /// // and the signatures were made from builds that end with `BND RET` (`F2 C3`).
+    /// assert!(sigs.r#match(&buf).is_empty());
     ///
+    /// let mut bnd_ret = buf[..buf.len() - 1].to_vec();
+    /// bnd_ret.extend(&[0xF2, 0xC3]);
+    /// let matches = sigs.r#match(&bnd_ret);
     /// assert_eq!(matches.len(), 1);
     /// assert_eq!(matches[0].get_name().unwrap(), "__EH_prolog3_catch_align");
+    ///
+    /// // so the footer picks between signatures that otherwise collide.
+    /// let pat_buf = "\
+    /// 5589E55D 00 0000 0006 :0000 _foo C3CC
+    /// 5589E55D 00 0000 0006 :0000 _bar C390
+    /// ---";
+    /// let sigs = lancelot_flirt::FlirtSignatureSet::with_signatures(pat::parse(pat_buf).unwrap());
+    /// let matches = sigs.r#match(b"\x55\x89\xE5\x5D\xC3\x90");
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].get_name().unwrap(), "_bar");
+    ///
+    /// assert!(sigs.r#match(b"\x55\x89\xE5\x5D\xC3\xC3").is_empty());
+    /// assert!(sigs.r#match(b"\x55\x89\xE5\x5D\xC3").is_empty());
     /// ```
     pub fn r#match(&self, buf: &[u8]) -> Vec<&FlirtSignature> {
-        self.matcher
-            .r#match(buf)
-            .iter()
-            .map(|&pattern| self.sigs.get(pattern).unwrap())
+        let candidates: Vec<&FlirtSignature> = match &self.0 {
+            Signatures::Compiled { sigs, matcher } => matcher
+                .r#match(buf)
+                .into_iter()
+                .flat_map(|pattern| sigs.get(pattern).unwrap())
+                .collect(),
+            Signatures::Cached(cached) => cached
+                .tree()
+                .r#match(buf)
+                .into_iter()
+                .flat_map(|index| cached.group(index as usize))
+                .collect(),
+        };
+
+        candidates
+            .into_iter()
             .filter(|&sig| sig.match_crc16(buf))
             .filter(|&sig| sig.match_tail_bytes(buf))
+            .filter(|&sig| sig.match_footer(buf))
+            .filter(|&sig| sig.match_size(buf))
+            .collect()
+    }
+
+    /// like `r#match`, but with the names of each matching signature mapped
+    /// to addresses, given that the buffer starts at the given address.
+    pub fn match_at(&self, buf: &[u8], address: u64) -> Vec<FlirtMatch<'_>> {
+        self.r#match(buf)
+            .into_iter()
+            .map(|sig| FlirtMatch::new(sig, address))
+            .collect()
+    }
+
+    /// find the signatures that match at every offset within the given buffer,
+    /// not just at its start, ordered by offset.
+    ///
+    /// this is useful to find library functions that weren't otherwise discovered,
+    /// such as in statically linked code.
+    ///
+    /// with `Overlap::Skip`, after signatures match at some offset,
+    /// the scan resumes after the largest of the matching functions.
+    ///
+    /// ```
+    /// use lancelot_flirt::{pat, FlirtSignatureSet, Overlap};
+    ///
+    /// let pat_buf = "\
+    /// 5589E55DC3 00 0000 0005 :0000 _foo
+    /// 89E55D 00 0000 0003 :0000 _bar
+    /// ---";
+    /// let sigs = FlirtSignatureSet::with_signatures(pat::parse(pat_buf).unwrap());
+    /// let buf = b"\xCC\x55\x89\xE5\x5D\xC3\xCC\x89\xE5\x5D";
+    ///
+    /// let matches = sigs.scan(buf, Overlap::All);
+    /// assert_eq!(matches.len(), 3);
+    /// assert_eq!(matches[0].offset, 1);
+    /// assert_eq!(matches[0].sig.get_name(), Some("_foo"));
+    /// assert_eq!(matches[1].offset, 2);
+    /// assert_eq!(matches[1].sig.get_name(), Some("_bar"));
+    /// assert_eq!(matches[2].offset, 7);
+    ///
+    /// // _bar at offset 2 is within _foo.
+    /// let matches = sigs.scan(buf, Overlap::Skip);
+    /// assert_eq!(matches.len(), 2);
+    /// assert_eq!(matches[0].offset, 1);
+    /// assert_eq!(matches[1].offset, 7);
+    /// ```
+    pub fn scan(&self, buf: &[u8], overlap: Overlap) -> Vec<ScanMatch<'_>> {
+        let first_bytes = match &self.0 {
+            Signatures::Compiled { matcher, .. } => matcher.first_bytes(),
+            Signatures::Cached(cached) => cached.tree().first_bytes(),
+        };
+
+        let mut ret = vec![];
+        let mut offset = 0;
+        while offset < buf.len() {
+            if !first_bytes[buf[offset] as usize] {
+                offset += 1;
+                continue;
+            }
+
+            let sigs = self.r#match(&buf[offset..]);
+            let size = sigs.iter().map(|sig| sig.size_of_function as usize).max().unwrap_or(0);
+
+            ret.extend(sigs.into_iter().map(|sig| ScanMatch { offset, sig }));
+
+            // always make progress, even if the signatures don't describe the function size.
+            offset += match overlap {
+                Overlap::Skip if size > 0 => size,
+                _ => 1,
+            };
+        }
+
+        ret
+    }
+
+    /// like `r#match`, but also verify the names referenced by each signature,
+    /// using the given resolver (see `FlirtSignature::verify_references`).
+    ///
+    /// when multiple signatures match, only those with the most verified
+    /// references are returned.
+    /// this is how signatures that share the same bytes are disambiguated,
+    /// like the many variants of `__EH_prolog3`.
+    ///
+    /// ```
+    /// use lancelot_flirt;
+    /// use lancelot_flirt::pat;
+    ///
+    /// let pat_buf = "\
+    /// 518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 20 6562 0067 :0000 __EH_prolog3_catch_align ^0040 ___security_cookie ........33C5508965F08B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3
+    /// 518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 20 6562 0067 :0000 __EH_prolog3_catch_other ^0040 ___other_cookie ........33C5508965F08B4304894504FF75F464A1000000008945F48D45F464A300000000F2C3
+    /// ---";
+    ///
+    /// let sigs = lancelot_flirt::FlirtSignatureSet::with_signatures(pat::parse(pat_buf).unwrap());
+    /// let buf = [
+    ///     // apds.dll / 4FD932C41DF96D019DC265E26E94B81B
+    ///     // __EH_prolog3_catch_align
+    ///
+    ///     // first 0x20
+    ///     0x51, 0x8B, 0x4C, 0x24, 0x0C, 0x89, 0x5C, 0x24,
+    ///     0x0C, 0x8D, 0x5C, 0x24, 0x0C, 0x50, 0x8D, 0x44,
+    ///     0x24, 0x08, 0xF7, 0xD9, 0x23, 0xC1, 0x8D, 0x60,
+    ///     0xF8, 0x8B, 0x43, 0xF0, 0x89, 0x04, 0x24, 0x8B,
+    ///     // crc16 start
+    ///     0x43, 0xF8, 0x50, 0x8B, 0x43, 0xFC, 0x8B, 0x4B,
+    ///     0xF4, 0x89, 0x6C, 0x24, 0x0C, 0x8D, 0x6C, 0x24,
+    ///     0x0C, 0xC7, 0x44, 0x24, 0x08, 0xFF, 0xFF, 0xFF,
+    ///     0xFF, 0x51, 0x53, 0x2B, 0xE0, 0x56, 0x57, 0xA1,
+    ///     // crc end, reference to ___security_cookie
+    ///     0xD4, 0xAD, 0x19, 0x01, 0x33, 0xC5, 0x50, 0x89,
+    ///     0x65, 0xF0, 0x8B, 0x43, 0x04, 0x89, 0x45, 0x04,
+    ///     0xFF, 0x75, 0xF4, 0x64, 0xA1, 0x00, 0x00, 0x00,
+    ///     0x00, 0x89, 0x45, 0xF4, 0x8D, 0x45, 0xF4, 0x64,
+    ///     0xA3, 0x00, 0x00, 0x00, 0x00, 0xF2, 0xC3];
+    ///
+    /// // both signatures match the bytes.
+    /// assert_eq!(sigs.r#match(&buf).len(), 2);
+    ///
+    /// // but only one references the name at offset 0x40.
+    /// let matches = sigs.match_with_resolver(&buf, |offset| match offset {
+    ///     0x40 => Some(String::from("___security_cookie")),
+    ///     _ => None,
+    /// });
+    /// assert_eq!(matches.len(), 1);
+    /// assert_eq!(matches[0].get_name().unwrap(), "__EH_prolog3_catch_align");
+    ///
+    /// // when the name is unknown, the match is ambiguous.
+    /// assert_eq!(sigs.match_with_resolver(&buf, |_| None).len(), 2);
+    /// ```
+    pub fn match_with_resolver<R>(&self, buf: &[u8], resolver: R) -> Vec<&FlirtSignature>
+    where
+        R: Fn(u64) -> Option<String>,
+    {
+        let scored: Vec<(usize, &FlirtSignature)> = self
+            .r#match(buf)
+            .into_iter()
+            .filter_map(|sig| sig.verify_references(&resolver).map(|score| (score, sig)))
+            .collect();
+
+        let best_score = match scored.iter().map(|&(score, _)| score).max() {
+            Some(score) => score,
+            None => return vec![],
+        };
+
+        scored
+            .into_iter()
+            .filter(|&(score, _)| score == best_score)
+            .map(|(_, sig)| sig)
             .collect()
     }
 }
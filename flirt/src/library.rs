@@ -0,0 +1,598 @@
+//! Index a directory tree of FLIRT .sig and .pat files, and select the ones
+//! that may apply to a given program.
+//!
+//! Signature collections, like the `sig/pc` directory from IDA Pro, contain
+//! hundreds of files for many compilers, versions, and platforms. Matching all
+//! of them against a program is slow and causes false positives, so we
+//! record the metadata for each file:
+//!
+//!   - the target processor, file formats, operating systems, and application
+//!     types, from the .sig header,
+//!   - the compiler, guessed from the library description and file name,
+//!   - whether the signatures describe startup code (the `STARTUP` feature),
+//!
+//! and use it to pick the candidates for a program (see `Target`).
+//!
+//! The descriptions and file names also hint at details that the header
+//! doesn't: many headers claim every application type, even for libraries named
+//! like `libcmt_15_msvc_x86`, so we take the bit width from the name when the
+//! header doesn't pin it down. And for MSVC libraries, the name often gives the
+//! release of Visual Studio (like `15` for 2017), which we compare against the
+//! releases that built the program.
+//!
+//! .pat files don't have a header, so they apply to all targets.
+//!
+//! Startup signatures describe the code at the entry point that a compiler
+//! links into each program, like `mainCRTStartup`, so a match at the entry
+//! point is strong evidence for the compiler that built the program
+//! (see `LibraryIndex::match_startup`).
+use std::path::{Path, PathBuf};
+
+use anyhow::Result;
+use log::{debug, warn};
+
+use crate::{
+    sig::{AppTypes, FileTypes, OsTypes, SigFile},
+    FlirtSignature, FlirtSignatureSet,
+};
+
+/// the compiler that produced the library described by a signature file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Compiler {
+    Msvc,
+    Borland,
+    Gcc,
+    Watcom,
+    Unknown,
+}
+
+impl std::fmt::Display for Compiler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Compiler::Msvc => write!(f, "msvc"),
+            Compiler::Borland => write!(f, "borland"),
+            Compiler::Gcc => write!(f, "gcc"),
+            Compiler::Watcom => write!(f, "watcom"),
+            Compiler::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// guess the compiler from the description of the library, like
+/// `Visual C++ 6.0 runtime`, or failing that, its file name, like `bc31rtl`.
+///
+/// the file name prefixes are those used by the signatures shipped with IDA Pro.
+fn guess_compiler(description: &str, name: &str) -> Compiler {
+    let description = description.to_lowercase();
+    let name = name.to_lowercase();
+
+    // check for Borland first, since its libraries may mention the
+    // "Visual Component Library".
+    let patterns = [
+        (Compiler::Borland, &["borland", "delphi", "c++builder", "turbo"][..]),
+        (Compiler::Msvc, &["microsoft", "msvc", "visual c", "vc++", "mfc"][..]),
+        (Compiler::Gcc, &["gcc", "gnu", "mingw", "cygwin"][..]),
+        (Compiler::Watcom, &["watcom"][..]),
+    ];
+    for (compiler, needles) in patterns.iter() {
+        if needles.iter().any(|needle| description.contains(needle)) {
+            return *compiler;
+        }
+    }
+
+    // the Borland visual component library, not Visual C++.
+    if name.starts_with("vcl") {
+        return Compiler::Borland;
+    }
+
+    let prefixes = [
+        (Compiler::Msvc, &["msvc", "vc", "ms", "mfc", "libcmt"][..]),
+        (Compiler::Borland, &["bc", "bds", "b32"][..]),
+        (Compiler::Gcc, &["gcc", "mingw", "cygwin"][..]),
+        (Compiler::Watcom, &["wa", "wc"][..]),
+    ];
+    for (compiler, needles) in prefixes.iter() {
+        if needles.iter().any(|needle| name.starts_with(needle)) {
+            return *compiler;
+        }
+    }
+
+    Compiler::Unknown
+}
+
+/// the releases of Visual Studio, by year, and the product versions (like `15`
+/// for 2017) and toolset versions (like `141` for 2017) used to name their
+/// libraries.
+const VISUAL_STUDIO_VERSIONS: &[(u16, &[&str])] = &[
+    (1998, &["6", "60"]),
+    (2002, &["7", "70"]),
+    (2003, &["71"]),
+    (2005, &["8", "80"]),
+    (2008, &["9", "90"]),
+    (2010, &["10", "100"]),
+    (2012, &["11", "110"]),
+    (2013, &["12", "120"]),
+    (2015, &["14", "140"]),
+    (2017, &["15", "141"]),
+    (2019, &["16", "142"]),
+    (2022, &["17", "143"]),
+];
+
+/// guess the release of Visual Studio, like 2017, that shipped an MSVC library,
+/// from its description, like `Visual C++ 2008 runtime`, or its file name, like
+/// `libcmt_15_msvc_x86`.
+fn guess_msvc_version(description: &str, name: &str) -> Option<u16> {
+    let text = format!("{} {}", description, name).to_lowercase();
+    let tokens: Vec<&str> = text.split(|c: char| !c.is_ascii_alphanumeric()).collect();
+
+    // prefer an explicit year.
+    for token in tokens.iter() {
+        if let Ok(year) = token.trim_start_matches("vs").parse::<u16>() {
+            if VISUAL_STUDIO_VERSIONS.iter().any(|&(y, _)| y == year) {
+                return Some(year);
+            }
+        }
+    }
+
+    for token in tokens.iter() {
+        let version = token.trim_start_matches("ms").trim_start_matches("vc");
+        for (year, versions) in VISUAL_STUDIO_VERSIONS.iter() {
+            if versions.contains(&version) {
+                return Some(*year);
+            }
+        }
+    }
+
+    None
+}
+
+/// guess the bit width of a library from its description and file name,
+/// like `MSVC C Standard Library for x86 (/MT)` or `libcmt_15_msvc_x86`.
+fn guess_bits(description: &str, name: &str) -> Option<AppTypes> {
+    let text = format!("{} {}", description, name).to_lowercase();
+
+    // check for 64-bit first, since `x86_64` contains `x86`.
+    if ["x64", "amd64", "x86_64", "win64", "64-bit", "64bit"]
+        .iter()
+        .any(|needle| text.contains(needle))
+    {
+        Some(AppTypes::BITS_64)
+    } else if ["x86", "i386", "win32", "32-bit", "32bit"]
+        .iter()
+        .any(|needle| text.contains(needle))
+    {
+        Some(AppTypes::BITS_32)
+    } else {
+        None
+    }
+}
+
+/// use the bit width from the description or file name
+/// when the given application types don't specify exactly one.
+fn narrow_app_types(app_types: AppTypes, description: &str, name: &str) -> AppTypes {
+    let bits = AppTypes::BITS_16 | AppTypes::BITS_32 | AppTypes::BITS_64;
+    if (app_types & bits).bits().count_ones() == 1 {
+        return app_types;
+    }
+
+    match guess_bits(description, name) {
+        Some(guess) => (app_types - bits) | guess,
+        None => app_types,
+    }
+}
+
+/// the metadata for a single .sig or .pat file.
+#[derive(Debug, Clone)]
+pub struct LibraryInfo {
+    pub path:        PathBuf,
+    /// like `libcmt_15_msvc_x86`, from the file name.
+    pub name:        String,
+    /// like `MSVC C Standard Library for x86 (/MT)`, from the .sig header.
+    pub description: String,
+    /// the processor ID, like `sig::ARCH_X86`.
+    /// `None` when unknown, which matches all processors.
+    pub arch:        Option<u8>,
+    pub file_types:  FileTypes,
+    pub os_types:    OsTypes,
+    /// from the .sig header, though the bit width may be guessed from the
+    /// description or file name (see `narrow_app_types`).
+    pub app_types:   AppTypes,
+    /// do the signatures describe startup code?
+    pub startup:     bool,
+    pub compiler:    Compiler,
+    /// the number of signatures in the file.
+    pub count:       usize,
+}
+
+impl LibraryInfo {
+    /// record the metadata for the given .sig or .pat file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<LibraryInfo> {
+        let path = path.as_ref();
+        let buf = std::fs::read(path)?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        if is_pat(path) {
+            let sigs = parse_pat(path, &buf);
+            Ok(LibraryInfo {
+                path: path.to_path_buf(),
+                compiler: guess_compiler("", &name),
+                app_types: narrow_app_types(AppTypes::all(), "", &name),
+                name,
+                description: String::new(),
+                arch: None,
+                file_types: FileTypes::all(),
+                os_types: OsTypes::all(),
+                startup: false,
+                count: sigs.len(),
+            })
+        } else {
+            let sig = parse_sig(path, &buf)?;
+            Ok(LibraryInfo {
+                path: path.to_path_buf(),
+                compiler: guess_compiler(&sig.library_name, &name),
+                app_types: narrow_app_types(sig.app_types, &sig.library_name, &name),
+                name,
+                arch: Some(sig.arch),
+                file_types: sig.file_types,
+                os_types: sig.os_types,
+                startup: sig.is_startup(),
+                count: sig.sigs.len(),
+                description: sig.library_name,
+            })
+        }
+    }
+
+    /// load and compile the signatures from the file.
+    pub fn load(&self) -> Result<FlirtSignatureSet> {
+        let buf = std::fs::read(&self.path)?;
+        let sigs = if is_pat(&self.path) {
+            parse_pat(&self.path, &buf)
+        } else {
+            parse_sig(&self.path, &buf)?.sigs
+        };
+        Ok(FlirtSignatureSet::with_signatures(sigs))
+    }
+
+    /// the release of Visual Studio, like 2017, that shipped this MSVC library,
+    /// guessed from its description and file name.
+    /// `None` for libraries from other compilers.
+    pub fn msvc_version(&self) -> Option<u16> {
+        if self.compiler == Compiler::Msvc {
+            guess_msvc_version(&self.description, &self.name)
+        } else {
+            None
+        }
+    }
+
+    /// do the signatures apply to the given target?
+    /// doesn't consider the compiler.
+    pub fn is_compatible(&self, target: &Target) -> bool {
+        if let Some(arch) = self.arch {
+            if arch != target.arch {
+                return false;
+            }
+        }
+
+        // some signature files don't specify these fields, so treat
+        // an empty set as matching everything.
+        let bits = AppTypes::BITS_16 | AppTypes::BITS_32 | AppTypes::BITS_64;
+        (self.file_types.is_empty() || self.file_types.intersects(target.file_types))
+            && (self.os_types.is_empty() || self.os_types.intersects(target.os_types))
+            && (!self.app_types.intersects(bits)
+                || !target.app_types.intersects(bits)
+                || self.app_types.intersects(target.app_types & bits))
+    }
+}
+
+/// parse the given .pat file, skipping (and logging) any invalid lines,
+/// so that a few bad lines don't discard the library.
+fn parse_pat(path: &Path, buf: &[u8]) -> Vec<FlirtSignature> {
+    let (sigs, errors) = crate::pat::parse_lenient(&String::from_utf8_lossy(buf));
+    for e in errors.iter() {
+        warn!("library: {}: {}", path.display(), e);
+    }
+    sigs
+}
+
+/// parse the given .sig file, recovering (and logging) what we can from a corrupt tree.
+fn parse_sig(path: &Path, buf: &[u8]) -> Result<SigFile> {
+    let (sig, error) = SigFile::from_bytes_lenient(buf)?;
+    if let Some(e) = error {
+        warn!("library: {}: {}", path.display(), e);
+    }
+    Ok(sig)
+}
+
+fn is_pat(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("pat"))
+        .unwrap_or(false)
+}
+
+fn is_sig(path: &Path) -> bool {
+    path.extension()
+        .map(|ext| ext.eq_ignore_ascii_case("sig"))
+        .unwrap_or(false)
+}
+
+/// the program for which to select signatures.
+#[derive(Debug, Clone)]
+pub struct Target {
+    /// the processor ID, like `sig::ARCH_X86`.
+    pub arch:          u8,
+    /// like `FileTypes::PE`.
+    pub file_types:    FileTypes,
+    /// like `OsTypes::WIN`.
+    pub os_types:      OsTypes,
+    /// like `AppTypes::BITS_32 | AppTypes::EXE`.
+    pub app_types:     AppTypes,
+    /// the compilers that may have produced the program.
+    /// when empty, libraries from any compiler are selected.
+    pub compilers:     Vec<Compiler>,
+    /// the releases of Visual Studio, like 2017, that may have produced the
+    /// program. when empty, MSVC libraries from any release are selected.
+    pub msvc_versions: Vec<u16>,
+}
+
+/// the metadata for the signature files found within a directory tree.
+#[derive(Debug, Default)]
+pub struct LibraryIndex {
+    pub libraries: Vec<LibraryInfo>,
+}
+
+impl LibraryIndex {
+    /// index the .sig and .pat files found recursively within the given
+    /// directory, ordered by path.
+    /// files that fail to parse are skipped.
+    pub fn from_directory<P: AsRef<Path>>(path: P) -> Result<LibraryIndex> {
+        let mut paths = vec![];
+        let mut queue = vec![path.as_ref().to_path_buf()];
+        while let Some(dir) = queue.pop() {
+            for entry in std::fs::read_dir(&dir)? {
+                let path = entry?.path();
+                if path.is_dir() {
+                    queue.push(path);
+                } else if is_sig(&path) || is_pat(&path) {
+                    paths.push(path);
+                }
+            }
+        }
+        paths.sort();
+
+        let mut libraries = vec![];
+        for path in paths.into_iter() {
+            match LibraryInfo::from_path(&path) {
+                Ok(info) => {
+                    debug!(
+                        "library: {}: {} ({}) {} signatures",
+                        path.display(),
+                        info.description,
+                        info.compiler,
+                        info.count
+                    );
+                    libraries.push(info);
+                }
+                Err(e) => warn!("library: {}: skipping: {}", path.display(), e),
+            }
+        }
+
+        Ok(LibraryIndex { libraries })
+    }
+
+    /// select the libraries that apply to the given target.
+    ///
+    /// when some of the MSVC libraries come from the releases of Visual Studio
+    /// that built the target, then those from other releases are dropped.
+    /// otherwise, we keep them all, since the index may not have signatures
+    /// for the release, and the newer runtimes change little from release to
+    /// release.
+    ///
+    /// ```
+    /// use lancelot_flirt::{library::*, sig::{self, AppTypes, FileTypes, OsTypes}};
+    /// let index = LibraryIndex::from_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/sigs/sig")).unwrap();
+    /// let mut target = Target {
+    ///     arch:          sig::ARCH_X86,
+    ///     file_types:    FileTypes::PE,
+    ///     os_types:      OsTypes::WIN,
+    ///     app_types:     AppTypes::BITS_32 | AppTypes::EXE,
+    ///     compilers:     vec![Compiler::Msvc],
+    ///     msvc_versions: vec![2017],
+    /// };
+    /// assert_eq!(index.select(&target)[0].name, "libcmt_15_msvc_x86");
+    ///
+    /// // the library is for x86.
+    /// target.app_types = AppTypes::BITS_64 | AppTypes::EXE;
+    /// assert_eq!(index.select(&target).len(), 0);
+    ///
+    /// target.app_types = AppTypes::BITS_32 | AppTypes::EXE;
+    /// target.compilers = vec![Compiler::Borland];
+    /// assert_eq!(index.select(&target).len(), 0);
+    /// ```
+    pub fn select(&self, target: &Target) -> Vec<&LibraryInfo> {
+        let libraries: Vec<&LibraryInfo> = self
+            .libraries
+            .iter()
+            .filter(|info| info.is_compatible(target))
+            .filter(|info| {
+                target.compilers.is_empty()
+                    || info.compiler == Compiler::Unknown
+                    || target.compilers.contains(&info.compiler)
+            })
+            .collect();
+
+        let is_target_version = |info: &LibraryInfo| match info.msvc_version() {
+            Some(version) => target.msvc_versions.contains(&version),
+            None => false,
+        };
+        if !libraries.iter().any(|&info| is_target_version(info)) {
+            return libraries;
+        }
+
+        libraries
+            .into_iter()
+            .filter(|&info| info.msvc_version().is_none() || is_target_version(info))
+            .collect()
+    }
+
+    /// find the startup signatures, compatible with the given target,
+    /// that match the given bytes, which should be found at the entry point.
+    ///
+    /// the compilers of the matching libraries likely produced the program.
+    pub fn match_startup(&self, target: &Target, buf: &[u8]) -> Result<Vec<&LibraryInfo>> {
+        let mut ret = vec![];
+        for info in self.libraries.iter() {
+            if !info.startup || !info.is_compatible(target) {
+                continue;
+            }
+
+            let sigs = info.load()?;
+            let matches = sigs.r#match(buf);
+            if let Some(sig) = matches.first() {
+                debug!(
+                    "library: {}: startup signature matches: {}",
+                    info.name,
+                    sig.get_name().unwrap_or("(unknown)")
+                );
+                ret.push(info);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_guess_compiler() {
+        assert_eq!(
+            guess_compiler("MSVC C Standard Library for x86 (/MT)", "libcmt_15_msvc_x86"),
+            Compiler::Msvc
+        );
+        assert_eq!(guess_compiler("Visual C++ 6.0 runtime", "vc32rtf"), Compiler::Msvc);
+        assert_eq!(guess_compiler("", "vc32rtf"), Compiler::Msvc);
+        assert_eq!(guess_compiler("Borland Visual Component Library", "bds8vcl"), Compiler::Borland);
+        assert_eq!(guess_compiler("", "bc31rtl"), Compiler::Borland);
+        assert_eq!(guess_compiler("", "vcl"), Compiler::Borland);
+        assert_eq!(guess_compiler("GNU C++ for Windows", "mingw14"), Compiler::Gcc);
+        assert_eq!(guess_compiler("", "zlib"), Compiler::Unknown);
+    }
+
+    #[test]
+    fn test_guess_msvc_version() {
+        assert_eq!(
+            guess_msvc_version("MSVC C Standard Library for x86 (/MT)", "libcmt_15_msvc_x86"),
+            Some(2017)
+        );
+        assert_eq!(guess_msvc_version("Visual C++ 6.0 runtime", "vc32rtf"), Some(1998));
+        assert_eq!(guess_msvc_version("Visual C++ 2008 runtime", "vc32_9"), Some(2008));
+        assert_eq!(guess_msvc_version("", "msvc141_x64"), Some(2017));
+        assert_eq!(guess_msvc_version("", "vc32rtf"), None);
+    }
+
+    #[test]
+    fn test_narrow_app_types() {
+        let all = AppTypes::all();
+        let bits = AppTypes::BITS_16 | AppTypes::BITS_32 | AppTypes::BITS_64;
+        assert_eq!(
+            narrow_app_types(all, "MSVC C Standard Library for x86 (/MT)", "libcmt_15_msvc_x86"),
+            (all - bits) | AppTypes::BITS_32
+        );
+        assert_eq!(
+            narrow_app_types(all, "", "libcmt_x86_64"),
+            (all - bits) | AppTypes::BITS_64
+        );
+        assert_eq!(narrow_app_types(all, "", "zlib"), all);
+
+        // the header wins when it specifies the bit width.
+        let app_types = AppTypes::BITS_64 | AppTypes::EXE;
+        assert_eq!(narrow_app_types(app_types, "", "libcmt_15_msvc_x86"), app_types);
+    }
+
+    #[test]
+    fn test_select_msvc_version() {
+        let library = |name: &str, compiler: Compiler| LibraryInfo {
+            path: PathBuf::from(format!("{}.sig", name)),
+            name: name.to_string(),
+            description: String::new(),
+            arch: Some(crate::sig::ARCH_X86),
+            file_types: FileTypes::PE,
+            os_types: OsTypes::WIN,
+            app_types: AppTypes::BITS_32 | AppTypes::EXE,
+            startup: false,
+            compiler,
+            count: 1,
+        };
+        let index = LibraryIndex {
+            libraries: vec![
+                library("libcmt_15_msvc_x86", Compiler::Msvc),
+                library("libcmt_9_msvc_x86", Compiler::Msvc),
+                library("mfc_msvc_x86", Compiler::Msvc),
+                library("zlib", Compiler::Unknown),
+            ],
+        };
+        let mut target = Target {
+            arch:          crate::sig::ARCH_X86,
+            file_types:    FileTypes::PE,
+            os_types:      OsTypes::WIN,
+            app_types:     AppTypes::BITS_32 | AppTypes::EXE,
+            compilers:     vec![Compiler::Msvc],
+            msvc_versions: vec![2008],
+        };
+        let names = |target: &Target| {
+            index
+                .select(target)
+                .iter()
+                .map(|info| info.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // libraries from other releases are dropped, but not those of an unknown
+        // release.
+        assert_eq!(names(&target), vec!["libcmt_9_msvc_x86", "mfc_msvc_x86", "zlib"]);
+
+        // when there are no libraries from the release, or the release is unknown, keep
+        // them all.
+        target.msvc_versions = vec![2003];
+        assert_eq!(names(&target).len(), 4);
+        target.msvc_versions = vec![];
+        assert_eq!(names(&target).len(), 4);
+    }
+
+    #[test]
+    fn test_index() -> Result<()> {
+        let index = LibraryIndex::from_directory(concat!(env!("CARGO_MANIFEST_DIR"), "/sigs"))?;
+
+        let libcmt = index
+            .libraries
+            .iter()
+            .find(|info| info.name == "libcmt_15_msvc_x86" && is_sig(&info.path))
+            .unwrap();
+        assert_eq!(libcmt.description, "MSVC C Standard Library for x86 (/MT)");
+        assert_eq!(libcmt.arch, Some(crate::sig::ARCH_X86));
+        assert_eq!(libcmt.compiler, Compiler::Msvc);
+        assert!(!libcmt.startup);
+        assert_eq!(libcmt.count, 352);
+        // the header claims all application types, but the name says x86.
+        assert!(libcmt.app_types.contains(AppTypes::BITS_32));
+        assert!(!libcmt.app_types.contains(AppTypes::BITS_64));
+        assert_eq!(libcmt.msvc_version(), Some(2017));
+
+        // .pat files apply to all targets.
+        let target = Target {
+            arch:          13,
+            file_types:    FileTypes::ELF,
+            os_types:      OsTypes::UNIX,
+            app_types:     AppTypes::BITS_64,
+            compilers:     vec![],
+            msvc_versions: vec![],
+        };
+        assert!(index.select(&target).iter().all(|info| is_pat(&info.path)));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,48 @@
+//! A nom error type that records where parsing failed, and what was being
+//! parsed, so that the .pat and .sig parsers can report useful errors.
+//!
+//! Parsers label the constructs they parse with `nom::error::context`,
+//! and the innermost label is kept, like `CRC16` rather than `module`.
+use nom::error::{ErrorKind, ParseError};
+
+#[derive(Debug)]
+pub(crate) struct ParseFailure<I> {
+    /// the remaining input at the point of failure.
+    pub input:     I,
+    /// the construct that failed to parse, like `CRC16`.
+    pub construct: Option<&'static str>,
+}
+
+impl<I> ParseFailure<I> {
+    /// fail to parse the given construct at the given input.
+    pub fn new(input: I, construct: &'static str) -> nom::Err<ParseFailure<I>> {
+        nom::Err::Error(ParseFailure {
+            input,
+            construct: Some(construct),
+        })
+    }
+}
+
+impl<I> ParseError<I> for ParseFailure<I> {
+    fn from_error_kind(input: I, _kind: ErrorKind) -> Self {
+        ParseFailure { input, construct: None }
+    }
+
+    fn append(_input: I, _kind: ErrorKind, other: Self) -> Self {
+        other
+    }
+
+    fn add_context(_input: I, ctx: &'static str, mut other: Self) -> Self {
+        other.construct.get_or_insert(ctx);
+        other
+    }
+}
+
+/// the remaining input and construct of a failure,
+/// or `None` if the parser needed more input.
+pub(crate) fn get_failure<I>(e: nom::Err<ParseFailure<I>>) -> Option<ParseFailure<I>> {
+    match e {
+        nom::Err::Error(f) | nom::Err::Failure(f) => Some(f),
+        nom::Err::Incomplete(_) => None,
+    }
+}
@@ -32,23 +32,37 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take_till1, take_while, take_while_m_n},
     combinator::{map, map_res, opt, peek},
-    multi::{many0, many1},
-    sequence::pair,
+    error::context,
+    multi::many1,
     IResult,
 };
 use thiserror::Error;
 
 use super::{ByteSignature, FlirtSignature, Name, Offset, SigElement, Symbol, TailByte};
+use crate::parse::{get_failure, ParseFailure};
+
+mod writer;
+pub use writer::{write, write_signature};
 
 #[derive(Debug, Error)]
 pub enum PatError {
     #[error("The pattern is not supported")]
     NotSupported,
-    #[error("The .pat file is corrupt (or unsupported)")]
-    CorruptPatFile,
+    #[error("The .pat file is corrupt at line {line}, column {column}: invalid {construct}")]
+    InvalidLine {
+        line:      usize,
+        column:    usize,
+        construct: &'static str,
+    },
+    #[error("The .pat file is truncated: missing the terminating `---`")]
+    MissingTerminator,
+    #[error("The signature cannot be written to a .pat file: {0}")]
+    UnsupportedSignature(String),
 }
 
-fn whitespace(input: &str) -> IResult<&str, &str> {
+type PatResult<'a, O> = IResult<&'a str, O, ParseFailure<&'a str>>;
+
+fn whitespace(input: &str) -> PatResult<'_, &str> {
     take_while(|c| c == ' ')(input)
 }
 
@@ -61,16 +75,16 @@ fn from_hex(input: &str) -> Result<u8, std::num::ParseIntError> {
 }
 
 /// parse a single hex byte, like `AB`
-fn hex(input: &str) -> IResult<&str, u8> {
+fn hex(input: &str) -> PatResult<'_, u8> {
     map_res(take_while_m_n(2, 2, is_hex_digit), from_hex)(input)
 }
 
-fn hex_byte(input: &str) -> IResult<&str, u8> {
+fn hex_byte(input: &str) -> PatResult<'_, u8> {
     let (input, v) = hex(input)?;
     Ok((input, v))
 }
 
-fn hex_word(input: &str) -> IResult<&str, u16> {
+fn hex_word(input: &str) -> PatResult<'_, u16> {
     let (input, v1) = hex(input)?;
     let (input, v2) = hex(input)?;
     let v: u16 = ((v1 as u16) << 8) | (v2 as u16);
@@ -79,12 +93,12 @@ fn hex_word(input: &str) -> IResult<&str, u16> {
 
 /// parse a single byte signature element, which is either a hex byte or a
 /// wildcard.
-fn sig_element(input: &str) -> IResult<&str, SigElement> {
+fn sig_element(input: &str) -> PatResult<'_, SigElement> {
     alt((map(hex, SigElement::Byte), map(tag(".."), |_| SigElement::Wildcard)))(input)
 }
 
 /// parse byte signature elements, hex or wildcard.
-fn byte_signature(input: &str) -> IResult<&str, ByteSignature> {
+fn byte_signature(input: &str) -> PatResult<'_, ByteSignature> {
     let (input, elems) = many1(sig_element)(input)?;
     Ok((input, ByteSignature(elems)))
 }
@@ -92,12 +106,12 @@ fn byte_signature(input: &str) -> IResult<&str, ByteSignature> {
 /// parse a hex-encoded offset, like `0000`
 /// max is 0x8000.
 /// TODO: this can be negative.
-fn hex_offset(input: &str) -> IResult<&str, u16> {
+fn hex_offset(input: &str) -> PatResult<'_, u16> {
     hex_word(input)
 }
 
 /// parse a public offset, like `:0000`
-fn public_offset(input: &str) -> IResult<&str, u16> {
+fn public_offset(input: &str) -> PatResult<'_, u16> {
     let (input, _) = tag(":")(input)?;
     let (input, offset) = hex_offset(input)?;
     let (input, _) = peek(tag(" "))(input)?;
@@ -106,7 +120,7 @@ fn public_offset(input: &str) -> IResult<&str, u16> {
 }
 
 /// parse a local offset, like `:000B@`
-fn local_offset(input: &str) -> IResult<&str, u16> {
+fn local_offset(input: &str) -> PatResult<'_, u16> {
     let (input, _) = tag(":")(input)?;
     let (input, offset) = hex_offset(input)?;
     let (input, _) = tag("@")(input)?;
@@ -115,14 +129,14 @@ fn local_offset(input: &str) -> IResult<&str, u16> {
 }
 
 /// parse an external reference, like `^0002`
-fn reference_offset(input: &str) -> IResult<&str, u16> {
+fn reference_offset(input: &str) -> PatResult<'_, u16> {
     let (input, _) = tag("^")(input)?;
     let (input, offset) = hex_offset(input)?;
 
     Ok((input, offset))
 }
 
-fn offset(input: &str) -> IResult<&str, Offset> {
+fn offset(input: &str) -> PatResult<'_, Offset> {
     alt((
         // this must go first, because it has trailing `@`,
         // otherwise, the same as public.
@@ -132,7 +146,7 @@ fn offset(input: &str) -> IResult<&str, Offset> {
     ))(input)
 }
 
-fn symbol_name(input: &str) -> IResult<&str, &str> {
+fn symbol_name(input: &str) -> PatResult<'_, &str> {
     take_till1(|c| c == ' ' || c == '\n')(input)
 }
 
@@ -140,7 +154,7 @@ fn symbol_name(input: &str) -> IResult<&str, &str> {
 ///
 /// note: this also consumes trailing spaces so that a sequence of these can be
 /// easily parsed.
-fn symbol(input: &str) -> IResult<&str, Symbol> {
+fn symbol(input: &str) -> PatResult<'_, Symbol> {
     let (input, offset) = offset(input)?;
     let (input, _) = whitespace(input)?;
     let (input, name) = symbol_name(input)?;
@@ -170,14 +184,15 @@ fn symbol(input: &str) -> IResult<&str, Symbol> {
     }
 }
 
-fn symbols(input: &str) -> IResult<&str, Vec<Symbol>> {
+fn symbols(input: &str) -> PatResult<'_, Vec<Symbol>> {
     many1(symbol)(input)
 }
 
 // like: `(0012: 87)`
-fn tail_byte(input: &str) -> IResult<&str, TailByte> {
+fn tail_byte(input: &str) -> PatResult<'_, TailByte> {
     let (input, _) = tag("(")(input)?;
     let (input, offset) = hex_offset(input)?;
+    let (input, _) = opt(tag(":"))(input)?;
     let (input, _) = whitespace(input)?;
     let (input, value) = hex_byte(input)?;
     let (input, _) = tag(")")(input)?;
@@ -191,31 +206,31 @@ fn tail_byte(input: &str) -> IResult<&str, TailByte> {
     ))
 }
 
-fn tail_bytes(input: &str) -> IResult<&str, Vec<TailByte>> {
+fn tail_bytes(input: &str) -> PatResult<'_, Vec<TailByte>> {
     match opt(many1(tail_byte))(input)? {
         (input, Some(tail_bytes)) => Ok((input, tail_bytes)),
         (input, None) => Ok((input, vec![])),
     }
 }
 
-fn pat_signature(input: &str) -> IResult<&str, FlirtSignature> {
-    let (input, byte_sig) = byte_signature(input)?;
+fn pat_signature(input: &str) -> PatResult<'_, FlirtSignature> {
+    let (input, byte_sig) = context("pattern", byte_signature)(input)?;
     let (input, _) = whitespace(input)?;
     trace!("sig: {:?}", byte_sig);
 
-    let (input, size_of_bytes_crc16) = hex_byte(input)?;
+    let (input, size_of_bytes_crc16) = context("CRC16 length", hex_byte)(input)?;
     let (input, _) = whitespace(input)?;
     trace!("crc16 len: {:02x}", size_of_bytes_crc16);
 
-    let (input, crc16) = hex_word(input)?;
+    let (input, crc16) = context("CRC16", hex_word)(input)?;
     let (input, _) = whitespace(input)?;
     trace!("crc16: {:04x}", crc16);
 
-    let (input, size_of_function) = hex_word(input)?;
+    let (input, size_of_function) = context("function size", hex_word)(input)?;
     let (input, _) = whitespace(input)?;
     trace!("function size: {:04x}", size_of_function);
 
-    let (input, names) = symbols(input)?;
+    let (input, names) = context("names", symbols)(input)?;
     trace!("names: {:?}", names);
 
     // i'm not sure which of these comes first, footer pattern or tail bytes.
@@ -229,9 +244,15 @@ fn pat_signature(input: &str) -> IResult<&str, FlirtSignature> {
     trace!("footer: {:?}", footer);
 
     let (input, _) = opt(whitespace)(input)?;
-    let (input, tail_bytes) = tail_bytes(input)?;
+    let (input, tail_bytes) = context("tail bytes", tail_bytes)(input)?;
     trace!("tail bytes: {:02x?}", tail_bytes);
 
+    // there should be nothing else on the line.
+    let (input, _) = whitespace(input)?;
+    if !input.is_empty() {
+        return Err(ParseFailure::new(input, "trailing data"));
+    }
+
     Ok((
         input,
         FlirtSignature {
@@ -246,30 +267,166 @@ fn pat_signature(input: &str) -> IResult<&str, FlirtSignature> {
     ))
 }
 
-/// parse a .pat file into FLIRT signatures.
-fn pat(input: &str) -> IResult<&str, Vec<FlirtSignature>> {
-    // each signature is newline separated.
-    // drop the newline after we've parsed it.
-    let pat_sig_line = map(pair(pat_signature, alt((tag("\r\n"), tag("\n")))), |p| p.0);
-    let (input, sigs) = many0(pat_sig_line)(input)?;
-
-    // the file ends with `---`.
-    let (input, _) = tag("---")(input)?;
+/// parse the given line (without its newline) into a FLIRT signature.
+/// `line_number` is one-based, and used to report errors.
+fn pat_line(line: &str, line_number: usize) -> std::result::Result<FlirtSignature, PatError> {
+    pat_signature(line).map(|(_, sig)| sig).map_err(|e| {
+        let (column, construct) = match get_failure(e) {
+            Some(f) => (line.len() - f.input.len() + 1, f.construct.unwrap_or("signature")),
+            None => (line.len() + 1, "signature"),
+        };
+
+        PatError::InvalidLine {
+            line: line_number,
+            column,
+            construct,
+        }
+    })
+}
 
-    // TODO: assert there is nothing left in the file
+/// parse a .pat file into FLIRT signatures, and errors for the lines
+/// that could not be parsed.
+///
+/// each signature is on its own line, and the file ends with `---`.
+/// blank lines are ignored.
+fn pat(buf: &str) -> (Vec<FlirtSignature>, Vec<PatError>) {
+    let mut sigs = vec![];
+    let mut errors = vec![];
+
+    for (i, line) in buf.lines().enumerate() {
+        if line.starts_with("---") {
+            return (sigs, errors);
+        }
+
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        match pat_line(line, i + 1) {
+            Ok(sig) => sigs.push(sig),
+            Err(e) => errors.push(e),
+        }
+    }
 
-    Ok((input, sigs))
+    errors.push(PatError::MissingTerminator);
+    (sigs, errors)
 }
 
+/// parse a .pat file into FLIRT signatures.
+///
+/// fails at the first invalid line, reporting its location.
+/// see `parse_lenient` to skip invalid lines instead.
+///
 /// ```
 /// use lancelot_flirt::pat;
 /// let pat_buf = "3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure\n---";
 /// assert_eq!(pat::parse(pat_buf).unwrap().len(), 1);
+///
+/// let pat_buf = "3B0D........F27502F2C3F2E9...................................... 00 XXXX 0011 :0000 @__security_check_cookie@4\n---";
+/// assert_eq!(
+///     pat::parse(pat_buf).unwrap_err().to_string(),
+///     "The .pat file is corrupt at line 1, column 69: invalid CRC16"
+/// );
 /// ```
 pub fn parse(buf: &str) -> Result<Vec<FlirtSignature>> {
-    if let Ok((_, sigs)) = pat(buf) {
+    let (sigs, mut errors) = pat(buf);
+    if errors.is_empty() {
         Ok(sigs)
     } else {
-        Err(PatError::CorruptPatFile.into())
+        Err(errors.remove(0).into())
+    }
+}
+
+/// parse a .pat file into FLIRT signatures, skipping the lines that are invalid,
+/// so that one bad line doesn't discard the entire library.
+///
+/// returns the valid signatures, and an error for each invalid line.
+///
+/// ```
+/// use lancelot_flirt::pat;
+/// let pat_buf = "\
+/// 33C0C3 00 0000 0003 :0000 _zero
+/// 33C0 00 0000 0003 _one
+/// 31C0C3 00 0000 0003 :0000 _two
+/// ---";
+/// let (sigs, errors) = pat::parse_lenient(pat_buf);
+/// assert_eq!(sigs.len(), 2);
+/// assert_eq!(errors.len(), 1);
+/// assert_eq!(
+///     errors[0].to_string(),
+///     "The .pat file is corrupt at line 2, column 19: invalid names"
+/// );
+/// ```
+pub fn parse_lenient(buf: &str) -> (Vec<FlirtSignature>, Vec<PatError>) {
+    pat(buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let buf = include_str!("../../sigs/pat/__EH_prolog3.pat");
+        let sigs = parse(buf)?;
+        assert_eq!(sigs.len(), 4);
+        assert_eq!(write(&sigs)?.trim_end(), buf.trim_end());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip_tail_bytes() -> Result<()> {
+        let buf = "\
+3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure (000F: 87)(0010: 88)
+558BEC.......................................................... 02 ABCD 0030 :0000 _foo :0010 _bar ........C3
+---
+";
+        let sigs = parse(buf)?;
+        assert_eq!(sigs.len(), 2);
+        assert_eq!(sigs[0].tail_bytes.len(), 2);
+        assert!(sigs[1].footer.is_some());
+        assert_eq!(write(&sigs)?, buf);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_pad_pattern() -> Result<()> {
+        let buf = "33C0C3 00 0000 0003 :0000 _zero\n---\n";
+        let sigs = parse(buf)?;
+        assert_eq!(
+            write(&sigs)?,
+            "33C0C3.......................................................... 00 0000 0003 :0000 _zero\n---\n"
+        );
+
+        Ok(())
+    }
+
+    // inputs found by fuzzing `parse`, see `fuzz/`.
+    #[test]
+    fn test_fuzz_regressions() -> Result<()> {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("fuzz");
+        d.push("regressions");
+        d.push("pat_parse");
+
+        for entry in std::fs::read_dir(d)? {
+            let buf = std::fs::read_to_string(entry?.path())?;
+            let _ = parse(&buf);
+            let _ = parse_lenient(&buf);
+        }
+
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn test_roundtrip_synthetic(sigs in crate::strategy::signatures(1)) {
+            let buf = write(&sigs).unwrap();
+            prop_assert_eq!(write(&parse(&buf).unwrap()).unwrap(), buf);
+        }
     }
 }
@@ -0,0 +1,124 @@
+//! Serialize FLIRT signatures into the .pat file format.
+//!
+//! This is the inverse of the parser in the parent module,
+//! so that signature sets can be edited, merged, and written back out.
+use anyhow::Result;
+
+use super::PatError;
+use crate::{ByteSignature, FlirtSignature, SigElement, Symbol};
+
+/// the .pat format always describes the first 32 bytes of the function,
+/// padding shorter patterns with wildcards.
+const PATTERN_SIZE: usize = 0x20;
+
+fn write_byte_signature(out: &mut String, byte_sig: &ByteSignature) {
+    for elem in byte_sig.0.iter() {
+        match elem {
+            SigElement::Byte(v) => out.push_str(&format!("{:02X}", v)),
+            SigElement::Wildcard => out.push_str(".."),
+        }
+    }
+}
+
+/// offsets are encoded as four hex digits.
+fn check_offset(sig: &FlirtSignature, offset: i64) -> Result<u16> {
+    if !(0..=0xFFFF).contains(&offset) {
+        Err(PatError::UnsupportedSignature(format!("offset out of range: {:#x}: {}", offset, sig)).into())
+    } else {
+        Ok(offset as u16)
+    }
+}
+
+fn check_name<'a>(sig: &FlirtSignature, name: &'a str) -> Result<&'a str> {
+    if name.is_empty() || name.contains(|c: char| c.is_whitespace()) {
+        Err(PatError::UnsupportedSignature(format!("invalid name: {:?}: {}", name, sig)).into())
+    } else {
+        Ok(name)
+    }
+}
+
+/// serialize a single signature into a line of a .pat file
+/// (without the trailing newline).
+pub fn write_signature(sig: &FlirtSignature) -> Result<String> {
+    let mut out = String::new();
+
+    if sig.byte_sig.0.len() > PATTERN_SIZE {
+        return Err(PatError::UnsupportedSignature(format!("pattern too long: {}", sig)).into());
+    }
+    write_byte_signature(&mut out, &sig.byte_sig);
+    for _ in sig.byte_sig.0.len()..PATTERN_SIZE {
+        out.push_str("..");
+    }
+
+    if sig.size_of_function > 0xFFFF {
+        return Err(PatError::UnsupportedSignature(format!("function too large: {}", sig)).into());
+    }
+    out.push_str(&format!(
+        " {:02X} {:04X} {:04X}",
+        sig.size_of_bytes_crc16, sig.crc16, sig.size_of_function
+    ));
+
+    if sig.names.is_empty() {
+        return Err(PatError::UnsupportedSignature(format!("signature has no names: {}", sig)).into());
+    }
+
+    for symbol in sig.names.iter() {
+        let s = match symbol {
+            Symbol::Public(name) => format!(
+                " :{:04X} {}",
+                check_offset(sig, name.offset)?,
+                check_name(sig, &name.name)?
+            ),
+            Symbol::Local(name) => format!(
+                " :{:04X}@ {}",
+                check_offset(sig, name.offset)?,
+                check_name(sig, &name.name)?
+            ),
+            Symbol::Reference(name) => format!(
+                " ^{:04X} {}",
+                check_offset(sig, name.offset)?,
+                check_name(sig, &name.name)?
+            ),
+        };
+        out.push_str(&s);
+    }
+
+    if let Some(footer) = &sig.footer {
+        out.push(' ');
+        write_byte_signature(&mut out, footer);
+    }
+
+    if !sig.tail_bytes.is_empty() {
+        out.push(' ');
+        for tail_byte in sig.tail_bytes.iter() {
+            if tail_byte.offset > 0xFFFF {
+                return Err(PatError::UnsupportedSignature(format!("tail byte offset out of range: {}", sig)).into());
+            }
+            out.push_str(&format!("{}", tail_byte));
+        }
+    }
+
+    Ok(out)
+}
+
+/// serialize the given signatures into a .pat file.
+///
+/// ```
+/// use lancelot_flirt::pat;
+/// let pat_buf = "3B0D........F27502F2C3F2E9...................................... 00 0000 0011 :0000 @__security_check_cookie@4 :000B@ $failure$4 ^0002 ___security_cookie ^000D ___report_gsfailure\n---\n";
+/// let sigs = pat::parse(pat_buf).unwrap();
+/// assert_eq!(pat::write(&sigs).unwrap(), pat_buf);
+/// ```
+pub fn write(sigs: &[FlirtSignature]) -> Result<String> {
+    let mut out = String::new();
+
+    for sig in sigs.iter() {
+        out.push_str(&write_signature(sig)?);
+        out.push('\n');
+    }
+
+    // the file ends with `---`.
+    out.push_str("---\n");
+
+    Ok(out)
+}
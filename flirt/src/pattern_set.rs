@@ -1,26 +1,31 @@
-/// ## purpose
-/// to match multiple byte patterns against a byte slice in parallel.
-/// we should get all valid matches at the end.
-/// does not have to support scanning across the byte slice, only anchored at
-/// the start. need support for single character wild cards (`.`).
-///
-/// implemented via [RegexSet](https://docs.rs/regex/1.3.9/regex/struct.RegexSet.html)
-///
-/// > Match multiple (possibly overlapping) regular expressions in a single
-/// scan. >
-/// > A regex set corresponds to the union of two or more regular expressions.
-/// > That is, a regex set will match text where at least one of its constituent
-/// > regular expressions matches. A regex set as its formulated here provides a
-/// touch more power: >  it will also report which regular expressions in the
-/// set match. > Indeed, this is the key difference between regex sets and a
-/// single Regex with many alternates, > since only one alternate can match at a
-/// time.
+//! Match many byte patterns against the start of a buffer, in parallel.
+//!
+//! We should get all valid matches at the end. We only match anchored at the
+//! start of the buffer, but we do need single byte wildcards (`..`).
+//! To scan across a buffer, callers match at each offset, skipping those
+//! that can't begin a match (see `first_bytes`).
+//!
+//! The patterns are compiled into a prefix tree, like the one found in .sig
+//! files: each node has a transition for each literal byte that follows it,
+//! plus at most one transition for a wildcard. Patterns that share a prefix
+//! share the nodes for that prefix. To match, we walk the tree, following
+//! both the literal transition for the next byte and the wildcard transition
+//! (if any), and collect the patterns that end at each node we reach.
+//!
+//! Since each path through the tree corresponds to (the prefix of) some
+//! pattern, the work to match is bounded by the size of the tree, which is at
+//! most the total length of the patterns. In practice, most paths die within
+//! a few bytes.
+//!
+//! This replaces an implementation based on `regex::bytes::RegexSet`, which
+//! was slow to build and used a lot of memory for large signature sets.
 use anyhow::Result;
 use nom::{
     branch::alt,
     bytes::complete::{tag, take_while_m_n},
     combinator::{map, map_res},
     multi::many1,
+    number::complete::le_u32,
     IResult,
 };
 
@@ -52,6 +57,7 @@ impl std::fmt::Display for Symbol {
 #[derive(Hash, PartialEq, Eq, Clone)]
 pub struct Pattern(pub Vec<Symbol>);
 
+/// render the pattern as an equivalent (anchored) regular expression.
 impl std::fmt::Display for Pattern {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         let parts: Vec<String> = self.0.iter().map(|s| format!("{}", s)).collect();
@@ -99,9 +105,56 @@ impl std::convert::From<&str> for Pattern {
     }
 }
 
+/// parse a u32 count followed by that many records of the given size,
+/// returning the bytes of the records, which are read in place.
+fn records(input: &[u8], size: usize) -> IResult<&[u8], &[u8]> {
+    let (input, n) = le_u32(input)?;
+    match (n as usize).checked_mul(size) {
+        Some(len) if len <= input.len() => Ok((&input[len..], &input[..len])),
+        _ => Err(nom::Err::Error((input, nom::error::ErrorKind::Count))),
+    }
+}
+
+fn read_u32(buf: &[u8], offset: usize) -> u32 {
+    let mut v = [0u8; 4];
+    v.copy_from_slice(&buf[offset..offset + 4]);
+    u32::from_le_bytes(v)
+}
+
+/// a node in the prefix tree, identified by its index into `PatternSet.nodes`.
+///
+/// the transitions and matches of all the nodes are stored in shared, flat
+/// arrays, so that the tree is a handful of arrays of fixed-size records,
+/// which can be written to disk and matched in place (see `TreeRef` and `cache`).
+#[derive(Clone, Copy, Default)]
+struct Node {
+    /// the start of this node's transitions on literal bytes in `PatternSet.edges`.
+    edges_start:   u32,
+    edges_len:     u32,
+    /// transition on a wildcard.
+    /// the root is never a child, so zero means there is no transition.
+    wildcard:      u32,
+    /// the start of the indices of the patterns that end at this node,
+    /// in `PatternSet.matches`.
+    matches_start: u32,
+    matches_len:   u32,
+}
+
+/// a transition from a node on a literal byte.
+#[derive(Clone, Copy)]
+struct Edge {
+    value: u8,
+    child: u32,
+}
+
 pub struct PatternSet {
     patterns: Vec<Pattern>,
-    re:       regex::bytes::RegexSet,
+    /// the prefix tree, with the root node at index 0.
+    nodes:    Vec<Node>,
+    /// the transitions of each node, sorted by byte.
+    edges:    Vec<Edge>,
+    /// the indices of the patterns that end at each node.
+    matches:  Vec<u32>,
 }
 
 impl std::fmt::Debug for PatternSet {
@@ -113,9 +166,262 @@ impl std::fmt::Debug for PatternSet {
     }
 }
 
+/// the records of a prefix tree, either compiled in memory (`PatternSet`)
+/// or serialized in a buffer (`TreeRef`), so that both are matched the same way.
+trait Tree {
+    fn nodes_len(&self) -> usize;
+    fn node(&self, index: u32) -> Node;
+    fn edges_len(&self) -> usize;
+    fn edge(&self, index: u32) -> Edge;
+    fn matches_len(&self) -> usize;
+    /// the index of a pattern that ends at some node.
+    fn pattern_index(&self, index: u32) -> u32;
+}
+
+impl Tree for PatternSet {
+    fn nodes_len(&self) -> usize {
+        self.nodes.len()
+    }
+
+    fn node(&self, index: u32) -> Node {
+        self.nodes[index as usize]
+    }
+
+    fn edges_len(&self) -> usize {
+        self.edges.len()
+    }
+
+    fn edge(&self, index: u32) -> Edge {
+        self.edges[index as usize]
+    }
+
+    fn matches_len(&self) -> usize {
+        self.matches.len()
+    }
+
+    fn pattern_index(&self, index: u32) -> u32 {
+        self.matches[index as usize]
+    }
+}
+
+/// find the child of the given node on the given byte,
+/// by binary search of its sorted transitions.
+fn find_edge<T: Tree>(tree: &T, node: &Node, value: u8) -> Option<u32> {
+    let (mut low, mut high) = (node.edges_start, node.edges_start + node.edges_len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let edge = tree.edge(mid);
+        match edge.value.cmp(&value) {
+            std::cmp::Ordering::Less => low = mid + 1,
+            std::cmp::Ordering::Greater => high = mid,
+            std::cmp::Ordering::Equal => return Some(edge.child),
+        }
+    }
+    None
+}
+
+/// find the indices of the patterns that match at the start of the given buffer, in order.
+fn match_tree<T: Tree>(tree: &T, buf: &[u8]) -> Vec<u32> {
+    let mut matches: Vec<u32> = vec![];
+
+    // pairs of (node index, offset into buf).
+    let mut queue: Vec<(u32, usize)> = vec![(0, 0)];
+    while let Some((index, offset)) = queue.pop() {
+        let node = tree.node(index);
+        matches.extend((node.matches_start..node.matches_start + node.matches_len).map(|i| tree.pattern_index(i)));
+
+        if let Some(&v) = buf.get(offset) {
+            if let Some(child) = find_edge(tree, &node, v) {
+                queue.push((child, offset + 1));
+            }
+            if node.wildcard != 0 {
+                queue.push((node.wildcard, offset + 1));
+            }
+        }
+    }
+
+    matches.sort_unstable();
+    matches
+}
+
+/// compute which bytes may begin a match,
+/// so that scans can quickly skip the offsets at which nothing matches.
+fn first_bytes<T: Tree>(tree: &T) -> [bool; 256] {
+    let root = tree.node(0);
+    if root.wildcard != 0 || root.matches_len > 0 {
+        return [true; 256];
+    }
+
+    let mut ret = [false; 256];
+    for i in root.edges_start..root.edges_start + root.edges_len {
+        ret[tree.edge(i).value as usize] = true;
+    }
+    ret
+}
+
+/// are all the indices within the tree in range, and do the nodes form a tree?
+///
+/// like `PatternSetBuilder` produces, each transition must lead to a node
+/// with a greater index, and each node other than the root must have exactly one parent.
+/// otherwise, a node that transitions to itself (or nodes shared by many paths)
+/// would make `match_tree` queue work exponential in the length of the input.
+fn is_valid_tree<T: Tree>(tree: &T, pattern_count: usize) -> bool {
+    let in_range = |start: u32, len: u32, max: usize| (start as u64 + len as u64) <= max as u64;
+
+    let nodes_len = tree.nodes_len();
+    if nodes_len == 0 {
+        return false;
+    }
+
+    for i in 0..nodes_len as u32 {
+        let node = tree.node(i);
+        if !in_range(node.edges_start, node.edges_len, tree.edges_len())
+            || !in_range(node.matches_start, node.matches_len, tree.matches_len())
+            || node.wildcard as usize >= nodes_len
+        {
+            return false;
+        }
+    }
+    if !(0..tree.edges_len() as u32).all(|i| (tree.edge(i).child as usize) < nodes_len)
+        || !(0..tree.matches_len() as u32).all(|i| (tree.pattern_index(i) as usize) < pattern_count)
+    {
+        return false;
+    }
+
+    let mut parents = vec![0u32; nodes_len];
+    for i in 0..nodes_len as u32 {
+        let node = tree.node(i);
+        let edges = (node.edges_start..node.edges_start + node.edges_len).map(|j| tree.edge(j));
+
+        let mut previous: Option<u8> = None;
+        for edge in edges {
+            // `find_edge` binary searches the transitions.
+            if previous.map(|v| v >= edge.value).unwrap_or(false) {
+                return false;
+            }
+            previous = Some(edge.value);
+
+            if edge.child <= i {
+                return false;
+            }
+            parents[edge.child as usize] += 1;
+        }
+
+        if node.wildcard != 0 {
+            if node.wildcard <= i {
+                return false;
+            }
+            parents[node.wildcard as usize] += 1;
+        }
+    }
+
+    parents.iter().skip(1).all(|&count| count == 1)
+}
+
+/// a prefix tree written by `PatternSet::write_tree`, matched in place
+/// within the buffer, rather than copied into a `PatternSet`.
+#[derive(Clone, Copy)]
+pub(crate) struct TreeRef<'a> {
+    /// `Node` records, 20 bytes each.
+    nodes:   &'a [u8],
+    /// `Edge` records, 5 bytes each.
+    edges:   &'a [u8],
+    /// pattern indices, 4 bytes each.
+    matches: &'a [u8],
+}
+
+impl<'a> Tree for TreeRef<'a> {
+    fn nodes_len(&self) -> usize {
+        self.nodes.len() / 20
+    }
+
+    fn node(&self, index: u32) -> Node {
+        let offset = index as usize * 20;
+        Node {
+            edges_start:   read_u32(self.nodes, offset),
+            edges_len:     read_u32(self.nodes, offset + 4),
+            wildcard:      read_u32(self.nodes, offset + 8),
+            matches_start: read_u32(self.nodes, offset + 12),
+            matches_len:   read_u32(self.nodes, offset + 16),
+        }
+    }
+
+    fn edges_len(&self) -> usize {
+        self.edges.len() / 5
+    }
+
+    fn edge(&self, index: u32) -> Edge {
+        let offset = index as usize * 5;
+        Edge {
+            value: self.edges[offset],
+            child: read_u32(self.edges, offset + 1),
+        }
+    }
+
+    fn matches_len(&self) -> usize {
+        self.matches.len() / 4
+    }
+
+    fn pattern_index(&self, index: u32) -> u32 {
+        read_u32(self.matches, index as usize * 4)
+    }
+}
+
+impl<'a> TreeRef<'a> {
+    /// parse the prefix tree written by `PatternSet::write_tree` at the start of the given buffer,
+    /// for the given number of patterns.
+    ///
+    /// returns `None` when the tree isn't consistent with itself or the patterns,
+    /// since matching against it could then panic or never finish.
+    /// this is the only place that the tree needs to be validated.
+    pub(crate) fn read(input: &'a [u8], pattern_count: usize) -> IResult<&'a [u8], Option<TreeRef<'a>>> {
+        let (input, nodes) = records(input, 20)?;
+        let (input, edges) = records(input, 5)?;
+        let (input, matches) = records(input, 4)?;
+
+        let tree = TreeRef { nodes, edges, matches };
+        if is_valid_tree(&tree, pattern_count) {
+            Ok((input, Some(tree)))
+        } else {
+            Ok((input, None))
+        }
+    }
+
+    /// re-create a tree from the records of a tree previously returned by `read`,
+    /// without validating it again.
+    pub(crate) fn from_valid(buf: &'a [u8]) -> TreeRef<'a> {
+        // the records were validated when read, so they can't fail to parse here.
+        let (buf, nodes) = records(buf, 20).expect("valid tree");
+        let (buf, edges) = records(buf, 5).expect("valid tree");
+        let (_, matches) = records(buf, 4).expect("valid tree");
+        TreeRef { nodes, edges, matches }
+    }
+
+    /// find the indices of the patterns that match at the start of the given buffer, in order.
+    pub(crate) fn r#match(&self, buf: &[u8]) -> Vec<u32> {
+        match_tree(self, buf)
+    }
+
+    /// compute which bytes may begin a match.
+    pub(crate) fn first_bytes(&self) -> [bool; 256] {
+        first_bytes(self)
+    }
+}
+
 impl PatternSet {
+    /// find the patterns that match at the start of the given buffer,
+    /// ordered by the index at which they were added.
     pub fn r#match(&self, buf: &[u8]) -> Vec<&Pattern> {
-        self.re.matches(buf).into_iter().map(|i| &self.patterns[i]).collect()
+        match_tree(self, buf)
+            .into_iter()
+            .map(|i| &self.patterns[i as usize])
+            .collect()
+    }
+
+    /// compute which bytes may begin a match,
+    /// so that scans can quickly skip the offsets at which nothing matches.
+    pub fn first_bytes(&self) -> [bool; 256] {
+        first_bytes(self)
     }
 
     pub fn builder() -> PatternSetBuilder {
@@ -125,33 +431,124 @@ impl PatternSet {
     pub fn from_patterns(patterns: Vec<Pattern>) -> PatternSet {
         PatternSetBuilder { patterns }.build()
     }
+
+    /// the patterns in the set, ordered by the index at which they were added.
+    pub fn patterns(&self) -> &[Pattern] {
+        &self.patterns
+    }
+
+    /// serialize the prefix tree (but not the patterns) into the given buffer.
+    ///
+    /// layout, all little endian:
+    ///
+    ///   u32       node count
+    ///   node[]    u32 edges start, u32 edges length, u32 wildcard,
+    ///             u32 matches start, u32 matches length
+    ///   u32       edge count
+    ///   edge[]    u8 value, u32 child
+    ///   u32       match count
+    ///   u32[]     pattern indices
+    pub(crate) fn write_tree(&self, out: &mut Vec<u8>) {
+        out.extend(&(self.nodes.len() as u32).to_le_bytes());
+        for node in self.nodes.iter() {
+            out.extend(&node.edges_start.to_le_bytes());
+            out.extend(&node.edges_len.to_le_bytes());
+            out.extend(&node.wildcard.to_le_bytes());
+            out.extend(&node.matches_start.to_le_bytes());
+            out.extend(&node.matches_len.to_le_bytes());
+        }
+
+        out.extend(&(self.edges.len() as u32).to_le_bytes());
+        for edge in self.edges.iter() {
+            out.push(edge.value);
+            out.extend(&edge.child.to_le_bytes());
+        }
+
+        out.extend(&(self.matches.len() as u32).to_le_bytes());
+        for &index in self.matches.iter() {
+            out.extend(&index.to_le_bytes());
+        }
+    }
 }
 
 pub struct PatternSetBuilder {
     patterns: Vec<Pattern>,
 }
 
+/// a node in the prefix tree while it's being built.
+#[derive(Default)]
+struct BuilderNode {
+    /// transitions on literal bytes, sorted by byte.
+    children: Vec<(u8, usize)>,
+    wildcard: Option<usize>,
+    matches:  Vec<usize>,
+}
+
 impl PatternSetBuilder {
     pub fn add_pattern(&mut self, pattern: Pattern) {
         self.patterns.push(pattern)
     }
 
     pub fn build(self) -> PatternSet {
-        // should not be possible to generate invalid regex from a pattern
-        // otherwise, programming error.
-        // must reject invalid patterns when deserializing from pat/sig.
-
-        let mut patterns = vec![];
-        for pattern in self.patterns.iter() {
-            patterns.push(format!("{}", pattern));
+        let mut nodes: Vec<BuilderNode> = vec![Default::default()];
+
+        for (i, pattern) in self.patterns.iter().enumerate() {
+            let mut index = 0;
+
+            for &symbol in pattern.0.iter() {
+                let next = nodes.len();
+
+                index = if symbol == WILDCARD {
+                    match nodes[index].wildcard {
+                        Some(child) => child,
+                        None => {
+                            nodes[index].wildcard = Some(next);
+                            nodes.push(Default::default());
+                            next
+                        }
+                    }
+                } else {
+                    let v = symbol.0 as u8;
+                    let children = &mut nodes[index].children;
+                    match children.binary_search_by_key(&v, |&(k, _)| k) {
+                        Ok(j) => children[j].1,
+                        Err(j) => {
+                            children.insert(j, (v, next));
+                            nodes.push(Default::default());
+                            next
+                        }
+                    }
+                };
+            }
+
+            nodes[index].matches.push(i);
         }
 
-        let re = regex::bytes::RegexSet::new(patterns).expect("invalid regex");
-
-        PatternSet {
+        // flatten the tree, keeping the node indices.
+        let mut pattern_set = PatternSet {
             patterns: self.patterns,
-            re,
+            nodes:    Vec::with_capacity(nodes.len()),
+            edges:    vec![],
+            matches:  vec![],
+        };
+
+        for node in nodes.into_iter() {
+            pattern_set.nodes.push(Node {
+                edges_start:   pattern_set.edges.len() as u32,
+                edges_len:     node.children.len() as u32,
+                wildcard:      node.wildcard.unwrap_or(0) as u32,
+                matches_start: pattern_set.matches.len() as u32,
+                matches_len:   node.matches.len() as u32,
+            });
+
+            pattern_set.edges.extend(node.children.iter().map(|&(value, child)| Edge {
+                value,
+                child: child as u32,
+            }));
+            pattern_set.matches.extend(node.matches.iter().map(|&i| i as u32));
         }
+
+        pattern_set
     }
 }
 
@@ -315,6 +712,72 @@ mod tests {
         assert_eq!(pattern_set.r#match(b"\x00\x00\x00\x00").len(), 1);
     }
 
+    // patterns that end at the same node, or are duplicates, are all reported,
+    // in the order that they were added.
+    #[test]
+    fn test_match_prefix() {
+        let pattern_set = PatternSet::from_patterns(vec![
+            Pattern::from("AABBCCDD"),
+            Pattern::from("AABB"),
+            Pattern::from("AA..CC"),
+            Pattern::from("AABB"),
+        ]);
+
+        let matches = pattern_set.r#match(b"\xAA\xBB\xCC\xDD");
+        assert_eq!(matches.len(), 4);
+        assert!(matches[0] == &Pattern::from("AABBCCDD"));
+        assert!(matches[1] == &Pattern::from("AABB"));
+        assert!(matches[2] == &Pattern::from("AA..CC"));
+
+        // the buffer must be at least as long as the pattern.
+        assert_eq!(pattern_set.r#match(b"\xAA\xBB\xCC").len(), 3);
+        assert_eq!(pattern_set.r#match(b"\xAA").len(), 0);
+    }
+
+    // the prefix tree matches the same patterns as the equivalent regular expressions.
+    #[test]
+    fn test_match_regex() {
+        // a small linear congruential generator, so the test is deterministic.
+        let mut state = 0x1234_5678u32;
+        let mut next = move || {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            (state >> 16) as u8
+        };
+
+        let mut patterns = vec![];
+        for _ in 0..1000 {
+            let len = 1 + next() % 8;
+            // use a small alphabet so that patterns share prefixes.
+            let pattern = (0..len)
+                .map(|_| match next() % 4 {
+                    0 => WILDCARD,
+                    v => Symbol::from(v),
+                })
+                .collect();
+            patterns.push(Pattern(pattern));
+        }
+
+        let re = regex::bytes::RegexSet::new(patterns.iter().map(|p| format!("{}", p))).unwrap();
+        let pattern_set = PatternSet::from_patterns(patterns.clone());
+
+        for _ in 0..1000 {
+            let buf: Vec<u8> = (0..8).map(|_| next() % 4).collect();
+            let expected: Vec<&Pattern> = re.matches(&buf).into_iter().map(|i| &patterns[i]).collect();
+            assert!(pattern_set.r#match(&buf) == expected);
+        }
+    }
+
+    #[test]
+    fn test_first_bytes() {
+        let first = PatternSet::from_patterns(vec![Pattern::from("AABB"), Pattern::from("CC..")]).first_bytes();
+        assert_eq!(first.iter().filter(|&&b| b).count(), 2);
+        assert!(first[0xAA]);
+        assert!(first[0xCC]);
+
+        let first = PatternSet::from_patterns(vec![Pattern::from("..BB")]).first_bytes();
+        assert!(first.iter().all(|&b| b));
+    }
+
     #[test]
     fn test_match_pathological_case() {
         let pattern_set = PatternSet::from_patterns(vec![
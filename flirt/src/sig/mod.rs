@@ -4,13 +4,20 @@ use nom::{
     branch::alt,
     bytes::complete::{tag, take, take_while},
     combinator::peek,
+    error::context,
     number::complete::{be_u16, be_u8, le_u16, le_u32, le_u8},
     IResult,
 };
 use thiserror::Error;
 
 use super::{FlirtSignature, TailByte};
-use crate::{ByteSignature, SigElement};
+use crate::{
+    parse::{get_failure, ParseFailure},
+    ByteSignature, SigElement,
+};
+
+mod writer;
+pub use writer::{write, WriteOptions};
 
 #[derive(Debug, Error)]
 pub enum SigError {
@@ -18,12 +25,18 @@ pub enum SigError {
     NotSupported,
     #[error("The sig file compression method is not supported: {0}")]
     CompressionNotSupported(String),
-    #[error("The .sig file is corrupt (or unsupported)")]
-    CorruptSigFile,
+    /// the offset is into the unpacked file, that is,
+    /// the header followed by the decompressed payload.
+    #[error("The .sig file is corrupt at offset {offset:#x}: invalid {construct}")]
+    CorruptSigFile { offset: usize, construct: &'static str },
+    #[error("The signature cannot be written to a .sig file: {0}")]
+    UnsupportedSignature(String),
 }
 
 bitflags! {
-    struct Features: u16 {
+    /// flags that describe how the .sig file is encoded and used.
+    pub struct Features: u16 {
+        /// the signatures match startup code, such as the C runtime entry point.
         const STARTUP        = 0b0000_0001;
         const CTYPE_CRC      = 0b0000_0010;
         const TWO_BYTE_CTYPE = 0b0000_0100;
@@ -33,6 +46,73 @@ bitflags! {
     }
 }
 
+/// the processor ID of x86, including x64.
+pub const ARCH_X86: u8 = 0;
+
+/// the maximum length of a byte pattern, the path from the root of the tree to a leaf.
+/// FLIRT matches the first 32 bytes of each function.
+const MAX_PATTERN_SIZE: usize = 0x20;
+
+bitflags! {
+    /// the file formats to which the signatures apply.
+    pub struct FileTypes: u32 {
+        const DOS_EXE_OLD = 1 << 0;
+        const DOS_COM_OLD = 1 << 1;
+        const BIN         = 1 << 2;
+        const DOSDRV      = 1 << 3;
+        const NE          = 1 << 4;
+        const INTELHEX    = 1 << 5;
+        const MOSHEX      = 1 << 6;
+        const LX          = 1 << 7;
+        const LE          = 1 << 8;
+        const NLM         = 1 << 9;
+        const COFF        = 1 << 10;
+        const PE          = 1 << 11;
+        const OMF         = 1 << 12;
+        const SREC        = 1 << 13;
+        const ZIP         = 1 << 14;
+        const OMFLIB      = 1 << 15;
+        const AR          = 1 << 16;
+        const LOADER      = 1 << 17;
+        const ELF         = 1 << 18;
+        const W32RUN      = 1 << 19;
+        const AOUT        = 1 << 20;
+        const PILOT       = 1 << 21;
+        const DOS_EXE     = 1 << 22;
+        const DOS_COM     = 1 << 23;
+        const AIXAR       = 1 << 24;
+        const MACHO       = 1 << 25;
+    }
+}
+
+bitflags! {
+    /// the operating systems to which the signatures apply.
+    pub struct OsTypes: u16 {
+        const MSDOS   = 0x01;
+        const WIN     = 0x02;
+        const OS2     = 0x04;
+        const NETWARE = 0x08;
+        const UNIX    = 0x10;
+        const OTHER   = 0x20;
+    }
+}
+
+bitflags! {
+    /// the kinds of applications to which the signatures apply.
+    pub struct AppTypes: u16 {
+        const CONSOLE         = 0x0001;
+        const GRAPHICS        = 0x0002;
+        const EXE             = 0x0004;
+        const DLL             = 0x0008;
+        const DRIVER          = 0x0010;
+        const SINGLE_THREADED = 0x0020;
+        const MULTI_THREADED  = 0x0040;
+        const BITS_16         = 0x0080;
+        const BITS_32         = 0x0100;
+        const BITS_64         = 0x0200;
+    }
+}
+
 #[derive(Debug)]
 enum HeaderExtra {
     V5,
@@ -51,10 +131,20 @@ enum HeaderExtra {
 }
 
 impl HeaderExtra {
+    fn get_functions_count(&self) -> Option<u32> {
+        match self {
+            HeaderExtra::V5 => None,
+            HeaderExtra::V6_7 { functions_count }
+            | HeaderExtra::V8_9 { functions_count, .. }
+            | HeaderExtra::V10 { functions_count, .. } => Some(*functions_count),
+        }
+    }
+
     /// get the size of this structure in bytes.
     fn get_size(&self) -> usize {
         match self {
-            HeaderExtra::V5 | HeaderExtra::V6_7 { .. } => 4,
+            HeaderExtra::V5 => 0,
+            HeaderExtra::V6_7 { .. } => 4,
             HeaderExtra::V8_9 { .. } => 6,
             HeaderExtra::V10 { .. } => 8,
         }
@@ -64,24 +154,26 @@ impl HeaderExtra {
 #[derive(Debug)]
 struct Header {
     // offset 6
-    version:      u8,
+    version:             u8,
     // offset 7
-    arch:         u8,
+    arch:                u8,
     // offset 8
-    file_types:   u32,
+    file_types:          u32,
     // offset 0xC
-    os_types:     u16,
+    os_types:            u16,
     // offset 0xE
-    app_types:    u16,
+    app_types:           u16,
     // offset: 0x10
-    features:     Features,
+    features:            Features,
+    // offset: 0x12
+    old_functions_count: u16,
     // offset: 0x14
-    crc16:        u16,
+    crc16:               u16,
     // offset: 0x23
-    ctypes_crc16: u16,
+    ctypes_crc16:        u16,
     // offset 0x25
-    extra:        HeaderExtra,
-    library_name: String,
+    extra:               HeaderExtra,
+    library_name:        String,
 }
 
 impl Header {
@@ -90,23 +182,29 @@ impl Header {
     }
 }
 
-fn utf8(input: &[u8], size: u16) -> IResult<&[u8], String> {
+type SigResult<'a, O> = IResult<&'a [u8], O, ParseFailure<&'a [u8]>>;
+
+fn utf8(input: &[u8], size: u16) -> SigResult<'_, String> {
     let (input, s) = take(size)(input)?;
-    let s = String::from_utf8(s.to_vec()).expect("invalid string");
+    // older files may contain non-ASCII names in some legacy code page.
+    let s = String::from_utf8_lossy(s).into_owned();
     Ok((input, s))
 }
 
-fn header(input: &[u8]) -> IResult<&[u8], Header> {
-    let (input, _) = tag(b"IDASGN")(input)?;
-
-    let (input, version) = alt((
-        tag(b"\x0A"),
-        tag(b"\x09"),
-        tag(b"\x08"),
-        tag(b"\x07"),
-        tag(b"\x06"),
-        tag(b"\x05"),
-    ))(input)?;
+fn header(input: &[u8]) -> SigResult<'_, Header> {
+    let (input, _) = context("magic", tag(b"IDASGN"))(input)?;
+
+    let (input, version) = context(
+        "version",
+        alt((
+            tag(b"\x0A"),
+            tag(b"\x09"),
+            tag(b"\x08"),
+            tag(b"\x07"),
+            tag(b"\x06"),
+            tag(b"\x05"),
+        )),
+    )(input)?;
     let version = version[0];
 
     let (input, arch) = le_u8(input)?;
@@ -114,7 +212,7 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
     let (input, os_types) = le_u16(input)?;
     let (input, app_types) = le_u16(input)?;
     let (input, features) = le_u16(input)?;
-    let (input, _) = le_u16(input)?;
+    let (input, old_functions_count) = le_u16(input)?;
     let (input, crc16) = le_u16(input)?;
     let (input, _) = take(12u8)(input)?;
     let (input, library_name_length) = le_u8(input)?;
@@ -150,10 +248,10 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
                 },
             )
         }
-        _ => unimplemented!(),
+        _ => return Err(ParseFailure::new(input, "version")),
     };
 
-    let (input, library_name) = utf8(input, library_name_length as u16)?;
+    let (input, library_name) = context("library name", |input| utf8(input, library_name_length as u16))(input)?;
 
     Ok((
         input,
@@ -163,7 +261,8 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
             file_types,
             os_types,
             app_types,
-            features: Features::from_bits(features).expect("invalid features"),
+            features: Features::from_bits_truncate(features),
+            old_functions_count,
             crc16,
             ctypes_crc16,
             extra,
@@ -173,7 +272,7 @@ fn header(input: &[u8]) -> IResult<&[u8], Header> {
 }
 
 /// unpack a variable-length integer with max range 16 bits.
-fn vint16(input: &[u8]) -> IResult<&[u8], u16> {
+fn vint16(input: &[u8]) -> SigResult<'_, u16> {
     let (input, high) = be_u8(input)?;
     let high: u16 = high as u16;
 
@@ -187,7 +286,7 @@ fn vint16(input: &[u8]) -> IResult<&[u8], u16> {
 }
 
 /// unpack a variable-length integer with max range 32 bits.
-fn vint32(input: &[u8]) -> IResult<&[u8], u32> {
+fn vint32(input: &[u8]) -> SigResult<'_, u32> {
     let (input, b) = be_u8(input)?;
 
     if (b & 0x80) != 0x80 {
@@ -218,7 +317,7 @@ fn vint32(input: &[u8]) -> IResult<&[u8], u32> {
 /// unpack a variable-length integer with max range 16 bits into a 64 bit
 /// number. this is a utility routine for code that must parse into a common
 /// number type.
-fn vint16_64(input: &[u8]) -> IResult<&[u8], u64> {
+fn vint16_64(input: &[u8]) -> SigResult<'_, u64> {
     let (input, v) = vint16(input)?;
     let v = v as u64;
     Ok((input, v))
@@ -227,7 +326,7 @@ fn vint16_64(input: &[u8]) -> IResult<&[u8], u64> {
 /// unpack a variable-length integer with max range 16 bits into a 64 bit
 /// number. this is a utility routine for code that must parse into a common
 /// number type.
-fn vint32_64(input: &[u8]) -> IResult<&[u8], u64> {
+fn vint32_64(input: &[u8]) -> SigResult<'_, u64> {
     let (input, v) = vint32(input)?;
     let v = v as u64;
     Ok((input, v))
@@ -235,7 +334,7 @@ fn vint32_64(input: &[u8]) -> IResult<&[u8], u64> {
 
 /// as of version 10, many fields are now v32 instead of v16.
 /// this is a utility method for picking the appropriate unpacker.
-fn vword<'a>(input: &'a [u8], header: &Header) -> IResult<&'a [u8], u64> {
+fn vword<'a>(input: &'a [u8], header: &Header) -> SigResult<'a, u64> {
     if header.version < 9 {
         vint16_64(input)
     } else {
@@ -261,7 +360,7 @@ fn count_bits(v: u64) -> u16 {
 ///
 /// when a bit is set in the mask, then its considered a wildcard.
 /// otherwise, a byte literal is used.
-fn wildcard_mask(input: &[u8], length: u16) -> IResult<&[u8], u64> {
+fn wildcard_mask(input: &[u8], length: u16) -> SigResult<'_, u64> {
     Ok(if length == 0 {
         (input, 0u64)
     } else if length < 0x10 {
@@ -274,7 +373,7 @@ fn wildcard_mask(input: &[u8], length: u16) -> IResult<&[u8], u64> {
         (input, (high << 32) | low)
     } else {
         // dumpsig does support this, but we don't, yet.
-        panic!("mask size too large");
+        return Err(ParseFailure::new(input, "wildcard mask"));
     })
 }
 
@@ -298,16 +397,19 @@ bitflags! {
     }
 }
 
-fn parsing_flags(input: &[u8]) -> IResult<&[u8], ParsingFlags> {
-    let (input, b) = be_u8(input)?;
-    Ok((input, ParsingFlags::from_bits(b).expect("invalid parsing flags")))
+fn parsing_flags(input: &[u8]) -> SigResult<'_, ParsingFlags> {
+    let (rest, b) = be_u8(input)?;
+    match ParsingFlags::from_bits(b) {
+        Some(flags) => Ok((rest, flags)),
+        None => Err(ParseFailure::new(input, "parsing flags")),
+    }
 }
 
 /// parse a tail byte definition.
 ///
 /// a tail byte differentiates two (or more) otherwise identical functions
 /// by specifying the first byte that differs.
-fn tail_bytes<'a>(input: &'a [u8], header: &Header) -> IResult<&'a [u8], Vec<TailByte>> {
+fn tail_bytes<'a>(input: &'a [u8], header: &Header) -> SigResult<'a, Vec<TailByte>> {
     let (input, count) = if header.version < 8 {
         (input, 1)
     } else {
@@ -347,7 +449,7 @@ struct ReferencedName {
 ///
 /// note: i'm not yet sure how the pointer itself will be encoded. is it always
 /// a global offset?
-fn referenced_names<'a>(input: &'a [u8], header: &Header) -> IResult<&'a [u8], Vec<ReferencedName>> {
+fn referenced_names<'a>(input: &'a [u8], header: &Header) -> SigResult<'a, Vec<ReferencedName>> {
     let (input, count) = if header.version < 8 {
         (input, 1)
     } else {
@@ -396,18 +498,22 @@ struct Name {
 /// but its possible for this offset to be non-zero.
 /// apparently its possible to specify a negative offset, but that's not
 /// supported here.
-fn name<'a>(input: &'a [u8], header: &Header, base_offset: i64) -> IResult<&'a [u8], (Name, ParsingFlags)> {
+fn name<'a>(input: &'a [u8], header: &Header, base_offset: i64) -> SigResult<'a, (Name, ParsingFlags)> {
     let (input, relative_offset) = vword(input, header)?;
 
     // note: this field is only optionally present.
     // it is present if the value of the byte is less than 0x20.
     // otherwise, expect to parse the name as ASCII.
-    let (input, name_flags) = if peek(be_u8)(input)?.1 < 0x20 {
+    let (rest, name_flags) = if peek(be_u8)(input)?.1 < 0x20 {
         be_u8(input)?
     } else {
         (input, 0u8)
     };
-    let name_flags = NameFlags::from_bits(name_flags).expect("invalid name flags");
+    let name_flags = match NameFlags::from_bits(name_flags) {
+        Some(name_flags) => name_flags,
+        None => return Err(ParseFailure::new(input, "name flags")),
+    };
+    let input = rest;
 
     // offset was parsed before, but can be interpreted only after name_flags.
     let offset = if name_flags.intersects(NameFlags::NEGATIVE_OFFSET) {
@@ -417,7 +523,8 @@ fn name<'a>(input: &'a [u8], header: &Header, base_offset: i64) -> IResult<&'a [
     };
 
     let (input, s) = take_while(|b| b >= 0x20)(input)?;
-    let pname = String::from_utf8(s.to_vec()).expect("invalid name");
+    // like `utf8`, older files may contain names in some legacy code page.
+    let pname = String::from_utf8_lossy(s).into_owned();
 
     let (input, pflags) = parsing_flags(input)?;
 
@@ -434,17 +541,23 @@ fn name<'a>(input: &'a [u8], header: &Header, base_offset: i64) -> IResult<&'a [
     ))
 }
 
-fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResult<&'a [u8], Vec<FlirtSignature>> {
+/// parse the modules at a leaf of the tree, which share the given pattern,
+/// into `out`.
+fn leaf<'a>(
+    input: &'a [u8],
+    header: &Header,
+    prefix: &[SigElement],
+    out: &mut Vec<FlirtSignature>,
+) -> SigResult<'a, ()> {
     let mut flags: ParsingFlags;
     let mut input = input;
-    let mut ret = vec![];
 
     loop {
         // module
 
-        let (input_, crc_len) = be_u8(input)?;
+        let (input_, crc_len) = context("CRC16 length", be_u8)(input)?;
         input = input_;
-        let (input_, crc) = be_u16(input)?;
+        let (input_, crc) = context("CRC16", be_u16)(input)?;
         input = input_;
         trace!("crc: {:02x} {:04x}", crc_len, crc);
 
@@ -452,7 +565,7 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
             // module with crc
             let mut offset = 0i64;
 
-            let (input_, function_size) = vword(input, header)?;
+            let (input_, function_size) = context("function size", |input| vword(input, header))(input)?;
             input = input_;
             trace!("size: {:04x}", function_size);
 
@@ -461,7 +574,7 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
             loop {
                 // name
 
-                let (input_, (name, flags_)) = name(input, header, offset)?;
+                let (input_, (name, flags_)) = context("name", |input| name(input, header, offset))(input)?;
                 input = input_;
                 offset = name.offset;
                 flags = flags_;
@@ -486,7 +599,7 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
             }
 
             let (input_, tbytes) = if flags.intersects(ParsingFlags::TAIL_BYTES) {
-                tail_bytes(input, header)?
+                context("tail bytes", |input| tail_bytes(input, header))(input)?
             } else {
                 (input, vec![])
             };
@@ -496,7 +609,7 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
             input = input_;
 
             if flags.intersects(ParsingFlags::REFERENCED_FUNCTIONS) {
-                let (input_, ref_names) = referenced_names(input, header)?;
+                let (input_, ref_names) = context("referenced names", |input| referenced_names(input, header))(input)?;
                 input = input_;
                 trace!("references: {:x?}", ref_names);
 
@@ -508,8 +621,8 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
                 }
             }
 
-            ret.push(FlirtSignature {
-                byte_sig: ByteSignature(prefix.clone()),
+            out.push(FlirtSignature {
+                byte_sig: ByteSignature(prefix.to_vec()),
                 size_of_bytes_crc16: crc_len,
                 crc16: crc,
                 size_of_function: function_size,
@@ -528,38 +641,56 @@ fn leaf<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
         };
     }
 
-    Ok((input, ret))
+    Ok((input, ()))
 }
 
-/// prefix_length: number of pattern bytes covered by parent nodes.
-fn node<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResult<&'a [u8], Vec<FlirtSignature>> {
-    let (input, child_count) = vint16(input)?;
+/// parse a node of the tree, and its children, into `out`.
+///
+/// prefix: the pattern covered by parent nodes.
+///
+/// the signatures are collected into `out` as they're parsed,
+/// so that those preceding a corrupt node are available to lenient callers.
+///
+/// each child must extend the pattern, which may not exceed `MAX_PATTERN_SIZE`,
+/// so the recursion is at most that deep, even for a crafted file.
+fn node<'a>(
+    input: &'a [u8],
+    header: &Header,
+    prefix: Vec<SigElement>,
+    out: &mut Vec<FlirtSignature>,
+) -> SigResult<'a, ()> {
+    let (input, child_count) = context("child count", vint16)(input)?;
     let mut input = input;
-    let mut ret = vec![];
 
     trace!("child count: {:#x}", child_count);
 
     if child_count == 0 {
-        return leaf(input, header, prefix);
+        return leaf(input, header, &prefix, out);
     }
 
     for _ in 0..child_count {
         let (input_, length) = if header.version < 10 {
-            let (input, length) = be_u8(input)?;
+            let (input, length) = context("pattern length", be_u8)(input)?;
             (input, length as u16)
         } else {
-            vint16(input)?
+            context("pattern length", vint16)(input)?
         };
         input = input_;
         trace!("length: {:#x}", length);
 
-        let (input_, mask) = wildcard_mask(input, length)?;
-        input = input_;
+        if length == 0 || prefix.len() + length as usize > MAX_PATTERN_SIZE {
+            return Err(ParseFailure::new(input, "pattern length"));
+        }
+
+        let (input_, mask) = context("wildcard mask", |input| wildcard_mask(input, length))(input)?;
         trace!("wildcard_mask: {:#x}", mask);
 
-        let remaining_bytes = length - count_bits(mask);
-        let (input_, byte_literals) = take(remaining_bytes)(input)?;
+        let remaining_bytes = match length.checked_sub(count_bits(mask)) {
+            Some(remaining_bytes) => remaining_bytes,
+            None => return Err(ParseFailure::new(input, "wildcard mask")),
+        };
         input = input_;
+        let (input_, byte_literals) = context("pattern bytes", take(remaining_bytes))(input)?;
         trace!("byte_literals: {:02x?}", byte_literals);
 
         // expected pattern:
@@ -582,10 +713,16 @@ fn node<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
             if (mask & (1 << i)) > 0 {
                 pattern.push(SigElement::Wildcard)
             } else {
-                pattern.push(SigElement::Byte(byte_literals[j - 1]));
-                j -= 1;
+                // the mask may have bits set beyond the length of the pattern,
+                // leaving too few literals.
+                j = match j.checked_sub(1) {
+                    Some(j) => j,
+                    None => return Err(ParseFailure::new(input, "wildcard mask")),
+                };
+                pattern.push(SigElement::Byte(byte_literals[j]));
             }
         }
+        input = input_;
 
         // we have:
         //  [ prefix1 prefix2   C B A ]
@@ -593,68 +730,424 @@ fn node<'a>(input: &'a [u8], header: &Header, prefix: Vec<SigElement>) -> IResul
         //  [ prefix1 prefix2   A B C ]
         pattern[prefix.len()..].reverse();
 
-        let (input_, sigs) = node(input, header, pattern)?;
+        let (input_, _) = node(input, header, pattern, out)?;
         input = input_;
-        ret.extend(sigs.into_iter());
     }
 
-    Ok((input, ret))
+    Ok((input, ()))
+}
+
+/// convert a parse failure within the given (unpacked) .sig file into an error.
+fn to_error(buf: &[u8], e: nom::Err<ParseFailure<&[u8]>>) -> SigError {
+    match get_failure(e) {
+        Some(f) => SigError::CorruptSigFile {
+            offset:    buf.len() - f.input.len(),
+            construct: f.construct.unwrap_or("signature"),
+        },
+        None => SigError::CorruptSigFile {
+            offset:    buf.len(),
+            construct: "signature",
+        },
+    }
 }
 
-/// parse an (unpacked) .sig file into FLIRT signatures.
+/// parse an (unpacked) .sig file into its header and FLIRT signatures.
+///
+/// the signatures parsed before any error in the tree are returned alongside the error,
+/// since the tree can't be resynchronized after it.
+/// an invalid header is fatal.
 ///
 /// see `unpack_sig`.
-fn sig(input: &[u8]) -> Result<Vec<FlirtSignature>> {
-    //nom::util::dbg_dmp(...);
-    let (input, header) = match header(input) {
-        Err(_) => return Err(SigError::CorruptSigFile.into()),
-        Ok((input, header)) => (input, header),
-    };
+fn sig(buf: &[u8]) -> Result<(Header, Vec<FlirtSignature>, Option<SigError>)> {
+    let (input, header) = context("header", header)(buf).map_err(|e| to_error(buf, e))?;
 
     trace!("header: {:#?}", header);
 
-    let (_input, sigs) = match node(input, &header, vec![]) {
-        Err(_) => return Err(SigError::CorruptSigFile.into()),
-        Ok((input, sigs)) => (input, sigs),
+    let mut sigs = vec![];
+    let error = node(input, &header, vec![], &mut sigs).err().map(|e| to_error(buf, e));
+
+    Ok((header, sigs, error))
+}
+
+/// does the buffer start with a valid zlib header?
+///
+/// the CMF byte must specify deflate (method 8) with a window of at most 32KB,
+/// and the FCHECK bits ensure that CMF and FLG, as a big endian u16, are a
+/// multiple of 31. see RFC 1950.
+fn is_zlib_header(buf: &[u8]) -> bool {
+    match buf {
+        [cmf, flg, ..] => cmf & 0x0F == 8 && cmf >> 4 <= 7 && u16::from_be_bytes([*cmf, *flg]).is_multiple_of(31),
+        _ => false,
+    }
+}
+
+/// decompress the payload of a .sig file, which follows the header.
+///
+/// recent files wrap the deflate stream with a zlib header and checksum,
+/// while older files contain a raw deflate stream.
+/// for example, the Borland signatures shipped with IDA Pro 7.4
+/// (bc15c2.sig, bc15owl.sig, bc31cls.sig) start with the bytes 0x05, 0x0C,
+/// and 0xC4, which are the block headers of a raw stream, not zlib headers.
+fn decompress(compressed: &[u8]) -> Result<Vec<u8>> {
+    let decompressed = if is_zlib_header(compressed) {
+        inflate::inflate_bytes_zlib(compressed)
+    } else {
+        inflate::inflate_bytes(compressed)
     };
 
-    Ok(sigs)
+    decompressed.map_err(|e| SigError::CompressionNotSupported(e).into())
 }
 
+/// decompress the given .sig file, if necessary,
+/// returning the header followed by the uncompressed payload.
 pub fn unpack_sig(input: &[u8]) -> Result<Vec<u8>> {
-    match header(input) {
+    match context("header", header)(input) {
         Ok((compressed, header)) => {
-            /*
-            println!("{:02x?}", &input[..0x50]);
-            println!("{:02x?}", &compressed[..0x50]);
-            */
-
             if header.features.intersects(Features::COMPRESSED) {
-                let header_buf = &input[..header.get_size()];
-                match inflate::inflate_bytes_zlib(&compressed) {
-                    Ok(decompressed) => {
-                        // stitch together the header with the decompressed payload
-                        let mut buf = vec![];
-                        buf.extend(header_buf);
-                        buf.extend(decompressed);
-                        Ok(buf)
-                    }
-                    Err(e) => {
-                        // I've see possible CMF values:
-                        //  0xC4 - IDA Pro 7.4/sig/pc/bc31cls.sig
-                        //  0x0C - IDA Pro 7.4/sig/pc/bc15owl
-                        //  0x05 - IDA Pro 7.4/sig/pc/bc15c2.sig
-                        Err(SigError::CompressionNotSupported(e).into())
-                    }
-                }
+                // stitch together the header with the decompressed payload
+                let mut buf = input[..header.get_size()].to_vec();
+                buf.extend(decompress(compressed)?);
+                Ok(buf)
             } else {
                 Ok(input.to_vec())
             }
         }
-        Err(_) => Err(SigError::CorruptSigFile.into()),
+        Err(e) => Err(to_error(input, e).into()),
     }
 }
 
+/// a parsed .sig file: the metadata from its header, and its signatures.
+///
+/// the metadata describes the targets to which the signatures apply,
+/// so that tools can pick the relevant .sig files for a given program.
+///
+/// ```
+/// use lancelot_flirt::sig::{self, AppTypes, FileTypes, OsTypes, SigFile};
+/// let buf = include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig");
+/// let sig = SigFile::from_bytes(buf).unwrap();
+/// assert_eq!(sig.version, 9);
+/// assert_eq!(sig.arch, sig::ARCH_X86);
+/// assert!(sig.file_types.contains(FileTypes::PE));
+/// assert!(sig.os_types.contains(OsTypes::WIN));
+/// assert!(sig.app_types.contains(AppTypes::BITS_32));
+/// assert_eq!(sig.library_name, "MSVC C Standard Library for x86 (/MT)");
+/// assert_eq!(sig.functions_count, 256);
+/// ```
+#[derive(Debug)]
+pub struct SigFile {
+    /// the .sig format version, from 5 to 10.
+    pub version:         u8,
+    /// the processor ID, like `ARCH_X86`.
+    pub arch:            u8,
+    pub file_types:      FileTypes,
+    pub os_types:        OsTypes,
+    pub app_types:       AppTypes,
+    pub features:        Features,
+    /// like `MSVC C Standard Library for x86 (/MT)`.
+    pub library_name:    String,
+    /// the number of functions reported by the header.
+    pub functions_count: u32,
+    pub sigs:            Vec<FlirtSignature>,
+}
+
+impl SigFile {
+    /// parse the given .sig file, which may be compressed.
+    ///
+    /// fails if any part of the file is invalid, reporting its location.
+    /// see `from_bytes_lenient` to recover the valid signatures instead.
+    pub fn from_bytes(buf: &[u8]) -> Result<SigFile> {
+        let (sig, error) = SigFile::from_bytes_lenient(buf)?;
+        match error {
+            Some(e) => Err(e.into()),
+            None => Ok(sig),
+        }
+    }
+
+    /// parse the given .sig file, which may be compressed,
+    /// recovering the signatures that precede any invalid part of the file.
+    ///
+    /// unlike a .pat file, we can't skip over an invalid signature and continue,
+    /// because the signatures are encoded in a tree without any lengths.
+    /// so, the error, if any, is returned alongside the signatures parsed before it.
+    /// an invalid header or payload that can't be decompressed is still fatal.
+    ///
+    /// ```
+    /// use lancelot_flirt::sig::SigFile;
+    /// let buf = include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig");
+    /// let buf = lancelot_flirt::sig::unpack_sig(buf).unwrap();
+    ///
+    /// // truncate the file within the tree.
+    /// let truncated = &buf[..buf.len() / 2];
+    /// assert!(SigFile::from_bytes(truncated).is_err());
+    ///
+    /// let (sig, error) = SigFile::from_bytes_lenient(truncated).unwrap();
+    /// assert!(sig.sigs.len() > 0);
+    /// assert!(sig.sigs.len() < 352);
+    /// assert!(error.unwrap().to_string().starts_with("The .sig file is corrupt at offset"));
+    /// ```
+    pub fn from_bytes_lenient(buf: &[u8]) -> Result<(SigFile, Option<SigError>)> {
+        let (header, sigs, error) = sig(&unpack_sig(buf)?)?;
+
+        let sig = SigFile {
+            version: header.version,
+            arch: header.arch,
+            file_types: FileTypes::from_bits_truncate(header.file_types),
+            os_types: OsTypes::from_bits_truncate(header.os_types),
+            app_types: AppTypes::from_bits_truncate(header.app_types),
+            features: header.features,
+            // version 5 files only have a 16-bit count.
+            functions_count: header
+                .extra
+                .get_functions_count()
+                .unwrap_or(header.old_functions_count as u32),
+            library_name: header.library_name,
+            sigs,
+        };
+
+        Ok((sig, error))
+    }
+
+    /// does the .sig file describe startup code,
+    /// such as the entry point provided by a compiler runtime?
+    pub fn is_startup(&self) -> bool {
+        self.features.contains(Features::STARTUP)
+    }
+}
+
+/// parse the given .sig file, which may be compressed, into FLIRT signatures.
+///
+/// see `SigFile` to also fetch the metadata from the header.
 pub fn parse(buf: &[u8]) -> Result<Vec<FlirtSignature>> {
-    sig(&unpack_sig(buf)?)
+    Ok(SigFile::from_bytes(buf)?.sigs)
+}
+
+/// parse the given .sig file, which may be compressed, into FLIRT signatures,
+/// recovering the signatures that precede any invalid part of the file.
+///
+/// see `SigFile::from_bytes_lenient`.
+pub fn parse_lenient(buf: &[u8]) -> Result<(Vec<FlirtSignature>, Option<SigError>)> {
+    let (sig, error) = SigFile::from_bytes_lenient(buf)?;
+    Ok((sig.sigs, error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_zlib_header() {
+        assert!(is_zlib_header(&deflate::deflate_bytes_zlib(b"IDASGN")));
+
+        // first bytes of the payloads of some older .sig files shipped with IDA.
+        assert!(!is_zlib_header(&[0xC4, 0xBD]));
+        assert!(!is_zlib_header(&[0x0C, 0x9A]));
+        assert!(!is_zlib_header(&[0x05, 0xC1]));
+    }
+
+    #[test]
+    fn test_raw_deflate() -> Result<()> {
+        let sigs = parse(include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig"))?;
+        let buf = write(&sigs, &Default::default())?;
+
+        // rewrite the uncompressed file to contain a raw deflate stream.
+        let (body, header) = header(&buf).unwrap();
+        let mut compressed = buf[..header.get_size()].to_vec();
+        compressed[0x10] |= Features::COMPRESSED.bits() as u8;
+        compressed.extend(deflate::deflate_bytes(body));
+
+        assert_eq!(&unpack_sig(&compressed)?[header.get_size()..], body);
+        assert_eq!(parse(&compressed)?.len(), sigs.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_raw_deflate_file() -> Result<()> {
+        // the version 9 libcmt signatures, with the payload compressed
+        // like the older .sig files shipped with IDA: a raw deflate stream without a zlib header.
+        let buf = include_bytes!("../../sigs/deflate/libcmt_15_msvc_x86.sig");
+        let (body, header) = header(buf).unwrap();
+        assert!(header.features.intersects(Features::COMPRESSED));
+        assert!(!is_zlib_header(body));
+
+        let sig = SigFile::from_bytes(buf)?;
+        assert_eq!(sig.version, 9);
+        assert_eq!(sig.library_name, "MSVC C Standard Library for x86 (/MT)");
+
+        let expected = SigFile::from_bytes(include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig"))?;
+        assert_eq!(
+            sig.sigs.iter().map(|sig| format!("{:?}", sig)).collect::<Vec<_>>(),
+            expected.sigs.iter().map(|sig| format!("{:?}", sig)).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_legacy_raw_deflate_files() -> Result<()> {
+        // older .sig files, compressed by zlib as raw deflate streams.
+        // like the Borland signatures shipped with IDA Pro 7.4, the payloads start with
+        // the bytes 0x05, 0x0C, and 0xC4, which aren't valid zlib headers:
+        //
+        //   0x05: the final block, with dynamic Huffman codes for literals only.
+        //   0x0C: a non-final block, with dynamic Huffman codes for 258 literals/lengths.
+        //   0xC4: a non-final block, with dynamic Huffman codes for 281 literals/lengths.
+        let files: [(&[u8], u8, u8, usize); 3] = [
+            (include_bytes!("../../sigs/deflate/legacy_v5.sig"), 5, 0x05, 8),
+            (include_bytes!("../../sigs/deflate/legacy_v6.sig"), 6, 0x0C, 8),
+            (include_bytes!("../../sigs/deflate/legacy_v7.sig"), 7, 0xC4, 48),
+        ];
+
+        for &(buf, version, first, count) in files.iter() {
+            let (body, header) = header(buf).unwrap();
+            assert!(header.features.intersects(Features::COMPRESSED));
+            assert_eq!(body[0], first);
+            assert!(!is_zlib_header(body));
+
+            let sig = SigFile::from_bytes(buf)?;
+            assert_eq!(sig.version, version);
+            assert_eq!(sig.library_name, format!("legacy raw deflate v{}", version));
+            assert_eq!(sig.sigs.len(), count);
+            for sig in sig.sigs.iter() {
+                assert_eq!(sig.byte_sig.0.len(), 16);
+                assert!(sig.get_name().unwrap().starts_with("_legacy_"));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_corrupt() -> Result<()> {
+        let buf = unpack_sig(include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig"))?;
+
+        let mut corrupt = buf.clone();
+        corrupt[6] = 0x0B;
+        assert_eq!(
+            parse(&corrupt).unwrap_err().to_string(),
+            "The .sig file is corrupt at offset 0x6: invalid version"
+        );
+
+        // truncate the file after the child count of the root node.
+        let (_, header) = header(&buf).unwrap();
+        let mut corrupt = buf.clone();
+        corrupt.truncate(header.get_size() + 1);
+        assert_eq!(
+            parse(&corrupt).unwrap_err().to_string(),
+            format!(
+                "The .sig file is corrupt at offset {:#x}: invalid pattern length",
+                header.get_size() + 1
+            )
+        );
+
+        Ok(())
+    }
+
+    /// a version 5 .sig file, with the given tree.
+    fn v5(tree: &[u8]) -> Vec<u8> {
+        let mut buf = b"IDASGN\x05".to_vec();
+        // arch, file types, OS types, app types, features, function count, CRC16,
+        // reserved bytes, library name length, and ctypes CRC16.
+        buf.extend(&[0u8; 30]);
+        buf.extend(tree);
+        buf
+    }
+
+    #[test]
+    fn test_v5_header() -> Result<()> {
+        let tree = [
+            0x01, // one child
+            0x01, 0x00, 0xCC, // one byte pattern
+            0x00, // no children
+            0x00, // CRC16 length
+            0x00, 0x00, // CRC16
+            0x01, // function size
+            0x00, // name offset
+            b'_', b'f', b'o', b'o', 0x00, // name, with flags
+        ];
+
+        // version 5 headers don't have a functions count,
+        // so the library name immediately follows the ctypes CRC16.
+        let with_name = |tree: &[u8]| {
+            let mut buf = v5(tree);
+            buf[0x22] = 3;
+            buf.splice(0x25..0x25, b"bar".iter().cloned());
+            buf
+        };
+
+        let sig = SigFile::from_bytes(&with_name(&tree))?;
+        assert_eq!(sig.version, 5);
+        assert_eq!(sig.library_name, "bar");
+        assert_eq!(sig.sigs[0].get_name(), Some("_foo"));
+
+        // the compressed payload starts immediately after the library name, too.
+        let mut compressed = with_name(&deflate::deflate_bytes(&tree));
+        compressed[0x10] |= Features::COMPRESSED.bits() as u8;
+        let mut expected = with_name(&tree);
+        expected[0x10] |= Features::COMPRESSED.bits() as u8;
+        assert_eq!(unpack_sig(&compressed)?, expected);
+
+        let sig = SigFile::from_bytes(&compressed)?;
+        assert_eq!(sig.library_name, "bar");
+        assert_eq!(sig.sigs[0].get_name(), Some("_foo"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_deep_tree() -> Result<()> {
+        let leaf = [
+            0x00, // no children
+            0x00, // CRC16 length
+            0x00, 0x00, // CRC16
+            0x01, // function size
+            0x00, // name offset
+            b'_', b'f', b'o', b'o', 0x00, // name, with flags
+        ];
+
+        // each node has one child with a one byte pattern, 32 deep.
+        let mut tree = vec![];
+        for _ in 0..MAX_PATTERN_SIZE {
+            tree.extend(&[0x01, 0x01, 0x00, 0xCC]);
+        }
+        tree.extend(&leaf);
+        assert_eq!(parse(&v5(&tree))?[0].byte_sig.0.len(), MAX_PATTERN_SIZE);
+
+        // one more byte is too long.
+        let mut tree = [0x01, 0x01, 0x00, 0xCC].repeat(MAX_PATTERN_SIZE + 1);
+        tree.extend(&leaf);
+        assert!(parse(&v5(&tree)).is_err());
+
+        // children with empty patterns would recurse without bound.
+        let buf = v5(&[0x01, 0x00].repeat(200_000));
+        assert!(parse(&buf).is_err());
+        let (sigs, error) = parse_lenient(&buf)?;
+        assert!(sigs.is_empty());
+        assert!(matches!(
+            error,
+            Some(SigError::CorruptSigFile {
+                construct: "pattern length",
+                ..
+            })
+        ));
+
+        Ok(())
+    }
+
+    // inputs found by fuzzing `parse`, see `fuzz/`.
+    #[test]
+    fn test_fuzz_regressions() -> Result<()> {
+        let mut d = std::path::PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        d.push("fuzz");
+        d.push("regressions");
+        d.push("sig_parse");
+
+        for entry in std::fs::read_dir(d)? {
+            let buf = std::fs::read(entry?.path())?;
+            let _ = parse(&buf);
+            let _ = parse_lenient(&buf);
+        }
+
+        Ok(())
+    }
 }
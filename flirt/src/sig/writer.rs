@@ -0,0 +1,515 @@
+//! Encode FLIRT signatures into the .sig file format.
+//!
+//! This is the inverse of the parser in the parent module:
+//!
+//!   1. the header, with the library name and target metadata,
+//!   2. a prefix tree of the byte patterns, where each edge is a run of
+//!      (possibly wildcarded) bytes shared by all the signatures below it,
+//!   3. at each leaf, the modules grouped by CRC16, with their names, tail
+//!      bytes, and referenced names.
+//!
+//! Everything after the header may be compressed using zlib.
+//!
+//! We always emit version 10 files, which is what IDA 7.x produces.
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+use super::{Features, NameFlags, ParsingFlags, SigError, MAX_PATTERN_SIZE};
+use crate::{FlirtSignature, SigElement, Symbol};
+
+const VERSION: u8 = 10;
+
+/// metadata to write into the header of a .sig file.
+#[derive(Debug, Clone)]
+pub struct WriteOptions {
+    /// like `MSVC C Standard Library for x86 (/MT)`.
+    pub library_name: String,
+    /// processor ID, like 0 for x86.
+    pub arch:         u8,
+    /// bitfield of file types, like 0x800 for PE.
+    pub file_types:   u32,
+    /// bitfield of operating systems, like 0x2 for Windows.
+    pub os_types:     u16,
+    /// bitfield of application types, like 0x100 for 32-bit.
+    pub app_types:    u16,
+    /// compress the signatures using zlib.
+    pub compress:     bool,
+}
+
+impl Default for WriteOptions {
+    /// by default, apply to any x86 file.
+    fn default() -> Self {
+        WriteOptions {
+            library_name: String::new(),
+            arch:         0,
+            file_types:   0xFFFF_FFFF,
+            os_types:     0xFFFF,
+            app_types:    0xFFFF,
+            compress:     false,
+        }
+    }
+}
+
+/// pack a variable-length integer with max range 16 bits.
+/// see `vint16`.
+fn write_vint16(out: &mut Vec<u8>, v: u16) -> Result<()> {
+    if v < 0x80 {
+        out.push(v as u8);
+    } else if v <= 0x7FFF {
+        out.push(0x80 | (v >> 8) as u8);
+        out.push(v as u8);
+    } else {
+        return Err(SigError::UnsupportedSignature(format!("value too large: {:#x}", v)).into());
+    }
+    Ok(())
+}
+
+/// pack a variable-length integer with max range 32 bits.
+/// see `vint32`.
+fn write_vint32(out: &mut Vec<u8>, v: u32) {
+    if v < 0x80 {
+        out.push(v as u8);
+    } else if v < 0x4000 {
+        out.push(0x80 | (v >> 8) as u8);
+        out.push(v as u8);
+    } else if v < 0x2000_0000 {
+        out.push(0xC0 | (v >> 24) as u8);
+        out.push((v >> 16) as u8);
+        out.extend(&(v as u16).to_be_bytes());
+    } else {
+        out.push(0xFF);
+        out.extend(&v.to_be_bytes());
+    }
+}
+
+/// as of version 10, many fields are v32 rather than v16.
+/// see `vword`.
+fn write_vword(out: &mut Vec<u8>, v: u64) -> Result<()> {
+    if v > u32::MAX as u64 {
+        return Err(SigError::UnsupportedSignature(format!("value too large: {:#x}", v)).into());
+    }
+    write_vint32(out, v as u32);
+    Ok(())
+}
+
+/// pack the wildcard mask for a subpattern with the given length.
+/// see `wildcard_mask`.
+fn write_wildcard_mask(out: &mut Vec<u8>, length: usize, mask: u64) -> Result<()> {
+    if length == 0 {
+    } else if length < 0x10 {
+        write_vint16(out, mask as u16)?;
+    } else if length <= 0x20 {
+        write_vint32(out, mask as u32);
+    } else {
+        write_vint32(out, (mask >> 32) as u32);
+        write_vint32(out, mask as u32);
+    }
+    Ok(())
+}
+
+/// sort key for pattern elements: literal bytes, then wildcards.
+fn element_key(elem: &SigElement) -> u16 {
+    match elem {
+        SigElement::Byte(v) => *v as u16,
+        SigElement::Wildcard => 0x100,
+    }
+}
+
+fn element_eq(a: &SigElement, b: &SigElement) -> bool {
+    element_key(a) == element_key(b)
+}
+
+/// a signature, with its pattern as encoded in the tree.
+struct Entry<'a> {
+    pattern: Vec<SigElement>,
+    sig:     &'a FlirtSignature,
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str, base_offset: i64, offset: i64, flags: NameFlags) -> Result<()> {
+    if name.is_empty() || name.bytes().any(|b| b < 0x20) {
+        return Err(SigError::UnsupportedSignature(format!("invalid name: {:?}", name)).into());
+    }
+
+    let mut flags = flags;
+    let relative_offset = if offset < base_offset {
+        flags |= NameFlags::NEGATIVE_OFFSET;
+        base_offset - offset
+    } else {
+        offset - base_offset
+    };
+
+    write_vword(out, relative_offset as u64)?;
+    // the flags are optional, and are present when the byte is less than 0x20.
+    // otherwise, the byte is the first character of the name.
+    if !flags.is_empty() {
+        out.push(flags.bits());
+    }
+    out.extend(name.as_bytes());
+    Ok(())
+}
+
+fn write_module(out: &mut Vec<u8>, sig: &FlirtSignature, flags: ParsingFlags) -> Result<()> {
+    write_vword(out, sig.size_of_function)?;
+
+    let names: Vec<(&crate::Name, NameFlags)> = sig
+        .names
+        .iter()
+        .filter_map(|symbol| match symbol {
+            Symbol::Public(name) => Some((name, NameFlags::empty())),
+            Symbol::Local(name) => Some((name, NameFlags::LOCAL)),
+            Symbol::Reference(_) => None,
+        })
+        .collect();
+
+    let references: Vec<&crate::Name> = sig
+        .names
+        .iter()
+        .filter_map(|symbol| match symbol {
+            Symbol::Reference(name) => Some(name),
+            _ => None,
+        })
+        .collect();
+
+    if names.is_empty() {
+        return Err(SigError::UnsupportedSignature(format!("signature has no names: {}", sig)).into());
+    }
+
+    let mut flags = flags;
+    if !sig.tail_bytes.is_empty() {
+        flags |= ParsingFlags::TAIL_BYTES;
+    }
+    if !references.is_empty() {
+        flags |= ParsingFlags::REFERENCED_FUNCTIONS;
+    }
+
+    let mut base_offset = 0i64;
+    for (i, (name, name_flags)) in names.iter().enumerate() {
+        write_name(out, &name.name, base_offset, name.offset, *name_flags)?;
+        base_offset = name.offset;
+
+        if i == names.len() - 1 {
+            out.push(flags.bits());
+        } else {
+            out.push(ParsingFlags::MORE_PUBLIC_NAMES.bits());
+        }
+    }
+
+    if !sig.tail_bytes.is_empty() {
+        write_vword(out, sig.tail_bytes.len() as u64)?;
+        for tail_byte in sig.tail_bytes.iter() {
+            write_vword(out, tail_byte.offset)?;
+            out.push(tail_byte.value);
+        }
+    }
+
+    if !references.is_empty() {
+        write_vword(out, references.len() as u64)?;
+        for reference in references.iter() {
+            if reference.offset < 0 {
+                return Err(SigError::UnsupportedSignature(format!("negative reference offset: {}", sig)).into());
+            }
+            write_vword(out, reference.offset as u64)?;
+
+            let name = reference.name.as_bytes();
+            if name.is_empty() || name.len() > 0x7FFF {
+                return Err(SigError::UnsupportedSignature(format!("invalid reference name: {}", sig)).into());
+            } else if name.len() < 0x100 {
+                out.push(name.len() as u8);
+            } else {
+                // when the size is zero, then a vint16 with the true size follows.
+                out.push(0x0);
+                write_vint16(out, name.len() as u16)?;
+            }
+            out.extend(name);
+        }
+    }
+
+    Ok(())
+}
+
+/// write the modules found at a leaf of the tree,
+/// grouped by their CRC16.
+fn write_leaf(out: &mut Vec<u8>, entries: &[Entry]) -> Result<()> {
+    let mut groups: BTreeMap<(u8, u16), Vec<&FlirtSignature>> = Default::default();
+    for entry in entries.iter() {
+        groups
+            .entry((entry.sig.size_of_bytes_crc16, entry.sig.crc16))
+            .or_default()
+            .push(entry.sig);
+    }
+
+    for (i, ((crc_len, crc16), sigs)) in groups.iter().enumerate() {
+        out.push(*crc_len);
+        out.extend(&crc16.to_be_bytes());
+
+        for (j, sig) in sigs.iter().enumerate() {
+            let mut flags = ParsingFlags::empty();
+            if j != sigs.len() - 1 {
+                flags |= ParsingFlags::MORE_MODULES_WITH_SAME_CRC;
+            }
+            if i != groups.len() - 1 {
+                flags |= ParsingFlags::MORE_MODULES;
+            }
+
+            write_module(out, sig, flags)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// write the tree node for the given entries,
+/// which all share the same pattern up to `depth`.
+fn write_node(out: &mut Vec<u8>, mut entries: Vec<Entry>, depth: usize) -> Result<()> {
+    if entries.iter().all(|entry| entry.pattern.len() == depth) {
+        write_vint16(out, 0)?;
+        return write_leaf(out, &entries);
+    }
+
+    // a node is either a leaf or has children, so when some patterns end here
+    // while others continue, extend the short patterns with wildcards.
+    // this doesn't change what they match, other than requiring a larger buffer.
+    for entry in entries.iter_mut() {
+        if entry.pattern.len() == depth {
+            entry.pattern.resize(MAX_PATTERN_SIZE, SigElement::Wildcard);
+        }
+    }
+
+    // group the entries by the next element of their patterns.
+    entries.sort_by_key(|entry| element_key(&entry.pattern[depth]));
+    let mut children: Vec<Vec<Entry>> = vec![];
+    for entry in entries.into_iter() {
+        match children.last_mut() {
+            Some(child) if element_eq(&child[0].pattern[depth], &entry.pattern[depth]) => child.push(entry),
+            _ => children.push(vec![entry]),
+        }
+    }
+
+    write_vint16(out, children.len() as u16)?;
+    for child in children.into_iter() {
+        // the edge extends for as long as all the patterns agree.
+        let min_len = child.iter().map(|entry| entry.pattern.len()).min().unwrap();
+        let mut end = depth + 1;
+        while end < min_len
+            && child
+                .iter()
+                .all(|entry| element_eq(&entry.pattern[end], &child[0].pattern[end]))
+        {
+            end += 1;
+        }
+
+        let edge = &child[0].pattern[depth..end];
+        write_vint16(out, edge.len() as u16)?;
+
+        // the first element corresponds to the most significant bit.
+        let mut mask = 0u64;
+        for (i, elem) in edge.iter().enumerate() {
+            if let SigElement::Wildcard = elem {
+                mask |= 1 << (edge.len() - 1 - i);
+            }
+        }
+        write_wildcard_mask(out, edge.len(), mask)?;
+
+        for elem in edge.iter() {
+            if let SigElement::Byte(v) = elem {
+                out.push(*v);
+            }
+        }
+
+        write_node(out, child, end)?;
+    }
+
+    Ok(())
+}
+
+fn write_header(out: &mut Vec<u8>, options: &WriteOptions, functions_count: usize) -> Result<()> {
+    let library_name = options.library_name.as_bytes();
+    if library_name.len() > 0xFF {
+        return Err(SigError::UnsupportedSignature(String::from("library name too long")).into());
+    }
+
+    let mut features = Features::empty();
+    if options.compress {
+        features |= Features::COMPRESSED;
+    }
+
+    out.extend(b"IDASGN");
+    out.push(VERSION);
+    out.push(options.arch);
+    out.extend(&options.file_types.to_le_bytes());
+    out.extend(&options.os_types.to_le_bytes());
+    out.extend(&options.app_types.to_le_bytes());
+    out.extend(&features.bits().to_le_bytes());
+    // number of functions, for older versions.
+    out.extend(&(std::cmp::min(functions_count, 0xFFFF) as u16).to_le_bytes());
+    // crc16
+    out.extend(&0u16.to_le_bytes());
+    // ctype name
+    out.extend(&[0u8; 12]);
+    out.push(library_name.len() as u8);
+    // ctypes crc16
+    out.extend(&0u16.to_le_bytes());
+    // v6+: number of functions
+    out.extend(&(functions_count as u32).to_le_bytes());
+    // v8+: pattern size
+    out.extend(&(MAX_PATTERN_SIZE as u16).to_le_bytes());
+    // v10+: unknown
+    out.extend(&0u16.to_le_bytes());
+    out.extend(library_name);
+
+    Ok(())
+}
+
+/// encode the given signatures into a .sig file.
+///
+/// the .sig format can't represent footers (see `pat`),
+/// so signatures that collide should first be disambiguated using tail bytes.
+///
+/// ```
+/// use lancelot_flirt::{pat, sig};
+///
+/// let pat_buf = "\
+/// 518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 21 B4FE 006E :0000 __EH_prolog3_GS_align ^0041 ___security_cookie
+/// 518B4C240C895C240C8D5C240C508D442408F7D923C18D60F88B43F08904248B 1F E4CF 0063 :0000 __EH_prolog3_align ^003F ___security_cookie
+/// ---";
+/// let sigs = pat::parse(pat_buf).unwrap();
+///
+/// let options = sig::WriteOptions {
+///     library_name: String::from("example"),
+///     compress: true,
+///     ..Default::default()
+/// };
+/// let buf = sig::write(&sigs, &options).unwrap();
+///
+/// let sigs = sig::parse(&buf).unwrap();
+/// assert_eq!(sigs.len(), 2);
+/// assert_eq!(sigs[0].get_name().unwrap(), "__EH_prolog3_align");
+/// ```
+pub fn write(sigs: &[FlirtSignature], options: &WriteOptions) -> Result<Vec<u8>> {
+    let mut entries = vec![];
+    for sig in sigs.iter() {
+        if sig.byte_sig.0.is_empty() || sig.byte_sig.0.len() > MAX_PATTERN_SIZE {
+            return Err(SigError::UnsupportedSignature(format!("invalid pattern length: {}", sig)).into());
+        }
+
+        entries.push(Entry {
+            pattern: sig.byte_sig.0.clone(),
+            sig,
+        });
+    }
+
+    let mut body = vec![];
+    write_node(&mut body, entries, 0)?;
+
+    let mut out = vec![];
+    write_header(&mut out, options, sigs.len())?;
+
+    if options.compress {
+        out.extend(deflate::deflate_bytes_zlib(&body));
+    } else {
+        out.extend(body);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    /// render everything that a .sig file can represent.
+    fn render(sig: &FlirtSignature) -> String {
+        let names: Vec<String> = sig.names.iter().map(|name| format!("{}", name)).collect();
+        format!("{} {}", sig, names.join(" "))
+    }
+
+    fn render_all(sigs: &[FlirtSignature]) -> Vec<String> {
+        let mut ret: Vec<String> = sigs.iter().map(render).collect();
+        ret.sort();
+        ret
+    }
+
+    #[test]
+    fn test_vint() {
+        for &v in [0u16, 1, 0x7F, 0x80, 0xFF, 0x100, 0x7FFF].iter() {
+            let mut buf = vec![];
+            write_vint16(&mut buf, v).unwrap();
+            assert_eq!(super::super::vint16(&buf).unwrap(), (&[][..], v));
+        }
+        assert!(write_vint16(&mut vec![], 0x8000).is_err());
+
+        for &v in [0u32, 0x7F, 0x80, 0x3FFF, 0x4000, 0x1FFF_FFFF, 0x2000_0000, 0xFFFF_FFFF].iter() {
+            let mut buf = vec![];
+            write_vint32(&mut buf, v);
+            assert_eq!(super::super::vint32(&buf).unwrap(), (&[][..], v));
+        }
+    }
+
+    #[test]
+    fn test_roundtrip_sig() -> Result<()> {
+        let buf = include_bytes!("../../sigs/sig/libcmt_15_msvc_x86.sig");
+        let sigs = super::super::parse(buf)?;
+
+        for &compress in [false, true].iter() {
+            let options = WriteOptions {
+                library_name: String::from("MSVC C Standard Library for x86 (/MT)"),
+                compress,
+                ..Default::default()
+            };
+
+            let rewritten = super::super::parse(&write(&sigs, &options)?)?;
+            assert_eq!(render_all(&sigs), render_all(&rewritten));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_roundtrip_pat() -> Result<()> {
+        let buf = include_str!("../../sigs/pat/__EH_prolog3.pat");
+        let sigs = crate::pat::parse(buf)?;
+
+        let rewritten = super::super::parse(&write(&sigs, &Default::default())?)?;
+        assert_eq!(render_all(&sigs), render_all(&rewritten));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_prefix_pattern() -> Result<()> {
+        // the first pattern is a prefix of the second,
+        // so it must be extended with wildcards.
+        let buf = "\
+33C0C3 00 0000 0003 :0000 _zero
+33C0C3CC 00 0000 0004 :0000 _zero_cc
+---";
+        let sigs = crate::pat::parse(buf)?;
+
+        let rewritten = super::super::parse(&write(&sigs, &Default::default())?)?;
+        assert_eq!(rewritten.len(), 2);
+
+        let mut buf = vec![0x33, 0xC0, 0xC3];
+        buf.resize(0x20, 0xCC);
+        let set = crate::FlirtSignatureSet::with_signatures(rewritten);
+        assert_eq!(set.r#match(&buf).len(), 2);
+
+        Ok(())
+    }
+
+    proptest! {
+        // functions shorter than the pattern are excluded,
+        // since their patterns may be extended with wildcards (see `test_prefix_pattern`).
+        #[test]
+        fn test_roundtrip_synthetic(sigs in crate::strategy::signatures(MAX_PATTERN_SIZE), compress in any::<bool>()) {
+            let options = WriteOptions {
+                compress,
+                ..Default::default()
+            };
+
+            let rewritten = super::super::parse(&write(&sigs, &options).unwrap()).unwrap();
+            prop_assert_eq!(render_all(&sigs), render_all(&rewritten));
+        }
+    }
+}
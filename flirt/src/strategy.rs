@@ -0,0 +1,41 @@
+//! proptest strategies that generate synthetic signatures,
+//! for round-trip tests of the .pat and .sig writers.
+use proptest::prelude::*;
+
+use crate::FlirtSignature;
+
+/// names like `__EH_prolog3` or `?terminate@@YAXXZ`.
+fn name() -> impl Strategy<Value = String> {
+    "[_?@$A-Za-z][_?@$A-Za-z0-9]{0,31}"
+}
+
+/// the bytes of a function and, for each byte, whether it's a wildcard.
+fn function(min_size: usize) -> impl Strategy<Value = Vec<(u8, bool)>> {
+    // skew the bytes towards 0xCC, so that patterns share prefixes
+    // and the tree has some depth.
+    let byte = prop_oneof![Just(0xCC), any::<u8>()];
+    prop::collection::vec((byte, prop::bool::weighted(0.1)), min_size..0x200)
+}
+
+fn signature(min_size: usize) -> impl Strategy<Value = FlirtSignature> {
+    (
+        function(min_size),
+        name(),
+        prop::collection::vec((0i64..0x200, name()), 0..4),
+    )
+        .prop_map(|(function, name, references)| {
+            let buf: Vec<u8> = function.iter().map(|&(b, _)| b).collect();
+            let wildcards: Vec<bool> = function.iter().map(|&(_, wildcard)| wildcard).collect();
+            let references: Vec<(i64, &str)> = references
+                .iter()
+                .map(|(offset, name)| (*offset, name.as_str()))
+                .collect();
+
+            FlirtSignature::from_function(&buf, &wildcards, &name, &references)
+        })
+}
+
+/// sets of signatures for functions at least `min_size` bytes long.
+pub(crate) fn signatures(min_size: usize) -> impl Strategy<Value = Vec<FlirtSignature>> {
+    prop::collection::vec(signature(min_size), 1..16)
+}
@@ -60,6 +60,18 @@ pub fn from_bytes(buf: &PyBytes) -> PyResult<PE> {
     })
 }
 
+/// compute the Shannon entropy of the given data, in bits per byte.
+/// compressed or encrypted data approaches 8.0.
+///
+/// Args:
+///   buf (bytes): the data.
+///
+/// Returns: float
+#[pyfunction]
+pub fn entropy(buf: &PyBytes) -> f64 {
+    lancelot::analysis::entropy::entropy(buf.as_bytes())
+}
+
 /// Control Flow Graph (CFG) is the result of disassembling from a given
 /// address. The result is broken up into regions of non-branching instructions
 /// ("basic blocks").
@@ -525,6 +537,69 @@ impl PE {
         self.inner.module.read_va_at_va(va).map_err(to_py_err)
     }
 
+    /// compute the entropy of each section, in bits per byte,
+    /// both of the data in the file and as mapped into memory.
+    ///
+    /// Returns: List[Tuple[str, float, float]]: name, raw entropy, mapped entropy.
+    pub fn get_section_entropy(&self) -> PyResult<Vec<(String, f64, f64)>> {
+        Ok(lancelot::analysis::pe::entropy::find_pe_section_entropy(&self.inner)
+            .map_err(to_py_err)?
+            .into_iter()
+            .map(|section| (section.name, section.raw, section.mapped))
+            .collect())
+    }
+
+    /// compute the entropy of each resource, in bits per byte.
+    /// the path of a resource is like `RT_VERSION/0x1/0x409`.
+    ///
+    /// Returns: List[Tuple[str, int, int, float]]: path, virtual address, size, entropy.
+    pub fn get_resource_entropy(&self) -> PyResult<Vec<(String, u64, u64, f64)>> {
+        Ok(lancelot::analysis::pe::entropy::find_pe_resource_entropy(&self.inner)
+            .map_err(to_py_err)?
+            .into_iter()
+            .map(|resource| (resource.path, resource.address, resource.size, resource.entropy))
+            .collect())
+    }
+
+    /// compute the entropy of overlapping windows across the data at the given virtual address.
+    /// windows start every half window, and the final window may be shorter.
+    ///
+    /// Args:
+    ///   va (int): the virtual address at which to read data.
+    ///   length (int): the number of bytes to profile.
+    ///   window (int): the size of each window, by default 0x400 bytes.
+    ///
+    /// Raises:
+    ///   ValueError - if the address is invalid.
+    ///
+    /// Returns: List[Tuple[int, float]]: virtual address of the window, entropy.
+    #[args(window = "lancelot::analysis::entropy::WINDOW_SIZE")]
+    pub fn get_entropy_profile(&self, va: VA, length: usize, window: usize) -> PyResult<Vec<(u64, f64)>> {
+        let buf = self
+            .inner
+            .module
+            .address_space
+            .read_bytes(va, length)
+            .map_err(to_py_err)?;
+
+        Ok(lancelot::analysis::entropy::profile(&buf, window)
+            .into_iter()
+            .map(|(offset, entropy)| (va + offset, entropy))
+            .collect())
+    }
+
+    /// find the regions of the sections, as mapped into memory,
+    /// that are probably compressed or encrypted.
+    ///
+    /// Returns: List[Tuple[int, int]]: start and end virtual address of each region.
+    pub fn get_high_entropy_regions(&self) -> PyResult<Vec<(u64, u64)>> {
+        Ok(lancelot::analysis::pe::entropy::find_pe_high_entropy_regions(&self.inner)
+            .map_err(to_py_err)?
+            .into_iter()
+            .map(|region| (region.start, region.end))
+            .collect())
+    }
+
     pub fn probe(&self, va: i128) -> u8 {
         // probe should be pretty relaxed about what it accepts
         // so that it is easy to use.
@@ -567,6 +642,7 @@ impl PE {
 #[pymodule]
 fn lancelot(_py: Python, m: &PyModule) -> PyResult<()> {
     m.add_wrapped(wrap_pyfunction!(from_bytes))?;
+    m.add_wrapped(wrap_pyfunction!(entropy))?;
     m.add_class::<PE>()?;
 
     // indices into a flow tuple
@@ -158,3 +158,24 @@ def test_probe(k32):
 def test_insn_int(k32):
     ws = lancelot.from_bytes(k32)
     assert int(ws.read_insn(0x1800202B0)) == 0x1800202B0
+
+
+def test_entropy(k32):
+    assert lancelot.entropy(b"\x00" * 0x100) == 0.0
+    assert lancelot.entropy(bytes(range(0x100))) == 8.0
+
+    ws = lancelot.from_bytes(k32)
+
+    assert "Returns: List[Tuple[str, float, float]]" in ws.get_section_entropy.__doc__
+    sections = {name: (raw, mapped) for (name, raw, mapped) in ws.get_section_entropy()}
+    assert 5.0 < sections[".text"][0] < 7.0
+
+    assert "Returns: List[Tuple[str, int, int, float]]" in ws.get_resource_entropy.__doc__
+    resources = {path: entropy for (path, _, _, entropy) in ws.get_resource_entropy()}
+    assert "RT_VERSION/0x1/0x409" in resources
+
+    profile = ws.get_entropy_profile(0x180000000, 0x1000)
+    assert [va for (va, _) in profile] == [0x180000000 + i * 0x200 for i in range(7)]
+
+    assert "Returns: List[Tuple[int, int]]" in ws.get_high_entropy_regions.__doc__
+    assert ws.get_high_entropy_regions() == [(0x18007EC00, 0x18007F200), (0x180083400, 0x180083A00)]